@@ -0,0 +1,22 @@
+#!/bin/sh
+# Copies ffmpeg and ffprobe into src-tauri/binaries, named the way Tauri expects sidecar
+# binaries for this machine, so `pnpm tauri build --config src-tauri/tauri.ffmpeg.conf.json`
+# bundles them with the app.
+#
+# Usage: scripts/prepare-ffmpeg-sidecar.sh [path/to/ffmpeg] [path/to/ffprobe]
+set -eu
+
+ffmpeg=${1:-$(command -v ffmpeg)}
+ffprobe=${2:-$(command -v ffprobe)}
+target=$(rustc -vV | sed -n 's/^host: //p')
+ext=""
+case "$target" in
+  *windows*) ext=".exe" ;;
+esac
+
+dir="$(dirname "$0")/../src-tauri/binaries"
+mkdir -p "$dir"
+cp "$ffmpeg" "$dir/ffmpeg-$target$ext"
+cp "$ffprobe" "$dir/ffprobe-$target$ext"
+chmod +x "$dir/ffmpeg-$target$ext" "$dir/ffprobe-$target$ext"
+echo "Copied ffmpeg and ffprobe to $dir for $target"
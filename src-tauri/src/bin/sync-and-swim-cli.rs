@@ -0,0 +1,780 @@
+//! Headless interface to the same operations the app offers, for scripting loads and splits.
+//!
+//! Results go to stdout and progress goes to stderr. With `--json`, both are written as JSON
+//! (progress as one object per line) so the output can be piped into other tools.
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+use std::io::{self, Read};
+use std::path::Path;
+use std::process::ExitCode;
+use swim_headphones_transfer_lib::engine::audio_format::OutputFormat;
+use swim_headphones_transfer_lib::engine::device_report::export_device_contents;
+use swim_headphones_transfer_lib::engine::disk_image::{
+    copy_image_to_device, create_disk_image, DEFAULT_IMAGE_SIZE_MB,
+};
+use swim_headphones_transfer_lib::engine::encoding::{
+    encode_files, estimate_fit, EncodeProgress, EncodingPreset,
+};
+use swim_headphones_transfer_lib::engine::feeds::{self, FeedProgress, FeedSubscription};
+use swim_headphones_transfer_lib::engine::ffmpeg_capabilities::check_ffmpeg;
+use swim_headphones_transfer_lib::engine::files::{
+    copy_files_to_destinations, delete_files, list_audio_files,
+};
+use swim_headphones_transfer_lib::engine::find_ffmpeg::FfmpegPaths;
+use swim_headphones_transfer_lib::engine::marker_tones::{insert_marker_tones, MarkerTones};
+use swim_headphones_transfer_lib::engine::media_info::{probe_files, with_media_info};
+use swim_headphones_transfer_lib::engine::playlist::load_playlist;
+use swim_headphones_transfer_lib::engine::silence::{
+    analyze_silences, SilenceInterval, SilencePreset, SilenceSettings, SilenceThreshold,
+};
+use swim_headphones_transfer_lib::engine::{
+    audio_segment, find_ffmpeg, AudioFile, CopyProgress, FfmpegTools, ProgressSink, SegmentOptions,
+    SegmentProgress, SystemRunner,
+};
+
+#[derive(Parser)]
+#[command(
+    name = "sync-and-swim-cli",
+    version,
+    about = "Load and split audio for OpenSwim headphones"
+)]
+struct Cli {
+    /// Write results and progress as JSON
+    #[arg(long, global = true)]
+    json: bool,
+
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// List the audio files under a folder, in the order they would be copied
+    ListAudioFiles {
+        path: String,
+        /// Also read each file's length, format, tags and chapters
+        #[arg(long)]
+        probe: bool,
+    },
+    /// Copy files to the headphones one at a time, preserving their order
+    CopyFiles {
+        /// Destination folder, e.g. /Volumes/OpenSwim. Repeat to load several headphones at once.
+        #[arg(long, required = true)]
+        dest: Vec<String>,
+        #[arg(long, value_enum, default_value_t = Mode::Append)]
+        mode: Mode,
+        /// Folder to copy from. Without it or --playlist, a JSON list of files is read from stdin.
+        #[arg(long)]
+        source: Option<String>,
+        /// M3U, M3U8, PLS or XSPF playlist to copy, in playlist order
+        #[arg(long, conflicts_with = "source")]
+        playlist: Option<String>,
+        /// Put every file from --playlist into this one folder on the headphones
+        #[arg(long, requires = "playlist")]
+        playlist_folder: Option<String>,
+        /// Play a short cue before each folder after the first (needs ffmpeg)
+        #[arg(long, value_enum, default_value_t = Tones::None)]
+        marker_tones: Tones,
+        /// Re-encode before copying: keep_original, spoken_word_mono48k,
+        /// spoken_word_mono64k or music192k (needs ffmpeg)
+        #[arg(long, value_parser = parse_preset, default_value = "keep_original")]
+        preset: EncodingPreset,
+    },
+    /// Cut audio files into smaller pieces
+    SplitAudioFiles {
+        /// Folder to write the pieces to
+        #[arg(long)]
+        dest: String,
+        #[arg(long, default_value_t = 5)]
+        chunk_minutes: u32,
+        /// Prefer cutting at silent points near each chunk boundary
+        #[arg(long)]
+        cut_at_silence: bool,
+        #[command(flatten)]
+        silence: SilenceArgs,
+        /// Cut at chapter markers and name the pieces after the chapters. With
+        /// --cut-at-silence, chapters longer than --chunk-minutes are cut further at silences.
+        #[arg(long)]
+        by_chapters: bool,
+        /// Playback speed of the pieces, e.g. 1.3. Chunk lengths are measured at this speed.
+        #[arg(long, default_value_t = 1.0)]
+        tempo: f64,
+        /// How to encode the pieces, as for copy-files. Presets always produce MP3.
+        #[arg(long, value_parser = parse_preset, default_value = "keep_original")]
+        preset: EncodingPreset,
+        /// Format of the pieces. Converting re-encodes the audio.
+        #[arg(long, value_enum, default_value_t = Format::KeepInput)]
+        format: Format,
+        /// Start each piece this many seconds before the previous one ends, at a silence
+        /// nearby with --cut-at-silence
+        #[arg(long, default_value_t = 0.0)]
+        overlap_seconds: f64,
+        /// How many files to split at once. Defaults to the number of cores.
+        #[arg(long)]
+        jobs: Option<usize>,
+        files: Vec<String>,
+    },
+    /// Predict how many hours of a folder's audio fit on the headphones with each preset
+    EstimateFit {
+        #[arg(long)]
+        source: String,
+        #[arg(long)]
+        dest: String,
+    },
+    /// Delete files, e.g. tracks you've already listened to
+    DeleteFiles { files: Vec<String> },
+    /// List the silences in audio files, without needing ffmpeg (MP3, AAC, FLAC and WAV)
+    FindSilences {
+        #[command(flatten)]
+        silence: SilenceArgs,
+        files: Vec<String>,
+    },
+    /// Show each file's length, format, tags, chapters and cover art size
+    ProbeAudioFile { files: Vec<String> },
+    /// Check whether ffmpeg can be found, where it came from and what it can do.
+    /// Set SYNC_AND_SWIM_FFMPEG or SYNC_AND_SWIM_FFPROBE to use particular executables.
+    CheckFfmpeg,
+    /// Create an empty FAT32 disk image to prepare a load in (pass it to copy-files as --dest)
+    CreateDiskImage {
+        path: String,
+        #[arg(long, default_value_t = DEFAULT_IMAGE_SIZE_MB)]
+        size_mb: u64,
+    },
+    /// Copy a prepared disk image's contents onto the headphones, in the image's play order
+    FlashDiskImage {
+        image: String,
+        #[arg(long)]
+        dest: String,
+        #[arg(long, value_enum, default_value_t = Mode::Append)]
+        mode: Mode,
+    },
+    /// Write an M3U8 playlist and a text report of what's on the headphones, in play order
+    ExportDevice {
+        /// Mounted headphones, e.g. /Volumes/OpenSwim
+        #[arg(long)]
+        dest: String,
+        #[arg(long, default_value = "device.m3u8")]
+        playlist: String,
+        #[arg(long, default_value = "device-report.txt")]
+        report: String,
+    },
+    /// Download new podcast episodes into a staging folder, one subfolder per feed
+    RefreshFeeds {
+        /// Staging folder to download into
+        #[arg(long)]
+        staging: String,
+        /// RSS or Atom feed URL. Repeat for several feeds.
+        #[arg(long)]
+        feed: Vec<String>,
+        /// OPML subscription list to take feeds from
+        #[arg(long)]
+        opml: Option<String>,
+        /// How many of the newest episodes to keep per feed
+        #[arg(long)]
+        keep: Option<usize>,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Mode {
+    /// Add after existing files
+    Append,
+    /// Delete existing files first
+    Replace,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Tones {
+    None,
+    /// A different pitch and rhythm for each folder
+    Pattern,
+    /// As many beeps as the folder's number
+    Count,
+}
+
+impl From<Tones> for MarkerTones {
+    fn from(tones: Tones) -> Self {
+        match tones {
+            Tones::None => MarkerTones::None,
+            Tones::Pattern => MarkerTones::Pattern,
+            Tones::Count => MarkerTones::Count,
+        }
+    }
+}
+
+#[derive(Args)]
+struct SilenceArgs {
+    /// Silence detection tuned for a kind of recording
+    #[arg(long, value_enum)]
+    silence_preset: Option<Silence>,
+    /// Level below which audio counts as silence, in dB (e.g. -35), or "auto" to go by
+    /// each file's noise floor. Overrides the preset.
+    #[arg(long, value_parser = parse_threshold, allow_hyphen_values = true)]
+    silence_threshold: Option<SilenceThreshold>,
+    /// Shortest pause that counts, in seconds. Overrides the preset.
+    #[arg(long)]
+    min_silence: Option<f64>,
+}
+
+impl SilenceArgs {
+    fn settings(&self) -> SilenceSettings {
+        let defaults = self
+            .silence_preset
+            .map(|p| SilencePreset::from(p).settings())
+            .unwrap_or_default();
+        SilenceSettings {
+            threshold: self.silence_threshold.unwrap_or(defaults.threshold),
+            min_duration: self.min_silence.unwrap_or(defaults.min_duration),
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Silence {
+    /// Quiet recordings with short pauses
+    StudioNarration,
+    /// Background noise, threshold set from each file's noise floor
+    LiveLecture,
+    /// Long, very quiet gaps between tracks
+    Music,
+}
+
+impl From<Silence> for SilencePreset {
+    fn from(silence: Silence) -> Self {
+        match silence {
+            Silence::StudioNarration => SilencePreset::StudioNarration,
+            Silence::LiveLecture => SilencePreset::LiveLecture,
+            Silence::Music => SilencePreset::Music,
+        }
+    }
+}
+
+fn parse_threshold(value: &str) -> Result<SilenceThreshold, String> {
+    if value == "auto" {
+        return Ok(SilenceThreshold::Auto);
+    }
+    value
+        .trim_end_matches("dB")
+        .parse()
+        .map(SilenceThreshold::Decibels)
+        .map_err(|_| format!("Expected a level in dB or \"auto\", got {}", value))
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    /// The same format as each input file
+    KeepInput,
+    Mp3,
+    M4a,
+    Flac,
+    Wav,
+}
+
+impl From<Format> for OutputFormat {
+    fn from(format: Format) -> Self {
+        match format {
+            Format::KeepInput => OutputFormat::KeepInput,
+            Format::Mp3 => OutputFormat::Mp3,
+            Format::M4a => OutputFormat::M4a,
+            Format::Flac => OutputFormat::Flac,
+            Format::Wav => OutputFormat::Wav,
+        }
+    }
+}
+
+fn parse_preset(name: &str) -> Result<EncodingPreset, String> {
+    serde_json::from_value(serde_json::Value::String(name.to_string()))
+        .map_err(|_| format!("Unknown preset {}", name))
+}
+
+impl Mode {
+    fn as_str(self) -> &'static str {
+        match self {
+            Mode::Append => "append",
+            Mode::Replace => "replace",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct FileSilences {
+    file: String,
+    silences: Vec<SilenceInterval>,
+}
+
+#[derive(Serialize)]
+struct ProgressLine<'a, P: Serialize> {
+    event: &'a str,
+    #[serde(flatten)]
+    payload: P,
+}
+
+struct Output {
+    json: bool,
+}
+
+impl ProgressSink for Output {
+    fn copy_progress(&self, progress: CopyProgress) {
+        if self.json {
+            self.progress_line("copy-progress", progress);
+        } else if !progress.completed {
+            eprintln!(
+                "[{}/{}] {}",
+                progress.index + 1,
+                progress.total,
+                progress.file_name
+            );
+        }
+    }
+
+    fn segment_progress(&self, progress: SegmentProgress) {
+        if self.json {
+            self.progress_line("segment-progress", progress);
+        } else if !progress.completed {
+            match (progress.speed, progress.eta_seconds) {
+                (Some(speed), Some(eta)) => eprintln!(
+                    "  {}: {:.0}% ({:.1}x, {} left)",
+                    progress.file_name,
+                    progress.progress,
+                    speed,
+                    format_duration(eta)
+                ),
+                _ => eprintln!("  {}: {:.0}%", progress.file_name, progress.progress),
+            }
+        }
+    }
+
+    fn encode_progress(&self, progress: EncodeProgress) {
+        if self.json {
+            self.progress_line("encode-progress", progress);
+        } else if !progress.completed {
+            eprintln!(
+                "[{}/{}] Re-encoding {}",
+                progress.index + 1,
+                progress.total,
+                progress.file_name
+            );
+        }
+    }
+
+    fn feed_progress(&self, progress: FeedProgress) {
+        if self.json {
+            self.progress_line("feed-progress", progress);
+        } else if !progress.completed {
+            eprintln!(
+                "[{}/{}] {}: {}",
+                progress.index + 1,
+                progress.total,
+                progress.feed_title,
+                progress.episode_title
+            );
+        }
+    }
+}
+
+impl Output {
+    fn progress_line<P: Serialize>(&self, event: &str, payload: P) {
+        if let Ok(line) = serde_json::to_string(&ProgressLine { event, payload }) {
+            eprintln!("{}", line);
+        }
+    }
+
+    fn result<T: Serialize>(&self, value: &T, human: impl FnOnce(&T)) {
+        if self.json {
+            match serde_json::to_string_pretty(value) {
+                Ok(text) => println!("{}", text),
+                Err(e) => eprintln!("Failed to write JSON: {}", e),
+            }
+        } else {
+            human(value);
+        }
+    }
+}
+
+/// Seconds as `h:mm:ss`
+fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    format!(
+        "{}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Turns paths given on the command line into `AudioFile`s with no subfolder
+fn files_from_paths(paths: &[String]) -> Vec<AudioFile> {
+    paths
+        .iter()
+        .map(|path| AudioFile {
+            name: Path::new(path)
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_else(|| path.clone()),
+            path: path.clone(),
+            relative_path: String::new(),
+            media: None,
+        })
+        .collect()
+}
+
+fn files_from_stdin() -> Result<Vec<AudioFile>, String> {
+    let mut input = String::new();
+    io::stdin()
+        .read_to_string(&mut input)
+        .map_err(|e| format!("Failed to read stdin: {}", e))?;
+    serde_json::from_str(&input).map_err(|e| format!("Failed to parse file list: {}", e))
+}
+
+async fn run(cli: Cli) -> Result<(), String> {
+    let out = Output { json: cli.json };
+    match cli.command {
+        Commands::ListAudioFiles { path, probe } => {
+            let mut files = list_audio_files(&path)?;
+            if probe {
+                let tools = FfmpegTools::locate(SystemRunner).ok();
+                files = with_media_info(tools.as_ref(), files).await;
+            }
+            out.result(&files, |files| {
+                for file in files {
+                    let path = Path::new(&file.relative_path).join(&file.name);
+                    match file.media.as_ref().and_then(|m| m.duration) {
+                        Some(duration) => {
+                            println!("{}  {}", path.display(), format_duration(duration))
+                        }
+                        None => println!("{}", path.display()),
+                    }
+                }
+            });
+        }
+        Commands::CopyFiles {
+            dest,
+            mode,
+            source,
+            playlist,
+            playlist_folder,
+            marker_tones,
+            preset,
+        } => {
+            let files = match (source, playlist) {
+                (Some(source), _) => list_audio_files(&source)?,
+                (None, Some(playlist)) => {
+                    let load = load_playlist(&playlist, playlist_folder.as_deref())
+                        .map_err(|e| e.to_string())?;
+                    for entry in &load.missing {
+                        eprintln!("Skipping missing playlist entry: {}", entry);
+                    }
+                    load.files
+                }
+                (None, None) => files_from_stdin()?,
+            };
+            // Encoded files and cues go in a folder of this run's own, so runs side by side
+            // don't clear out each other's files
+            let work_dir =
+                std::env::temp_dir().join(format!("sync-and-swim-{:016x}", rand::random::<u64>()));
+            let copied = async {
+                let files = match (MarkerTones::from(marker_tones), preset) {
+                    (MarkerTones::None, EncodingPreset::KeepOriginal) => files,
+                    (style, preset) => {
+                        let tools = FfmpegTools::locate(SystemRunner).map_err(|e| e.to_string())?;
+                        let encoded_dir = work_dir.join("encoded");
+                        let files = encode_files(&tools, files, preset, &encoded_dir, &out)
+                            .await
+                            .map_err(|e| e.to_string())?;
+                        let cue_dir = work_dir.join("marker-tones");
+                        insert_marker_tones(&tools, files, style, &cue_dir)
+                            .await
+                            .map_err(|e| e.to_string())?
+                    }
+                };
+                let count = files.len();
+                let report = copy_files_to_destinations(files, &dest, mode.as_str(), &out);
+                Ok::<_, String>((count, report))
+            }
+            .await;
+            let _ = std::fs::remove_dir_all(&work_dir);
+            let (count, report) = copied?;
+            if let Some(error) = report.source_error {
+                return Err(error);
+            }
+            let results = report.destinations;
+            out.result(
+                &serde_json::json!({ "copied": count, "destinations": results }),
+                |_| {
+                    for result in &results {
+                        match &result.error {
+                            None => println!("Copied {} files to {}", count, result.dest_path),
+                            Some(e) => println!("Failed to copy to {}: {}", result.dest_path, e),
+                        }
+                    }
+                },
+            );
+            if results.iter().any(|r| r.error.is_some()) {
+                return Err("Some destinations failed".to_string());
+            }
+        }
+        Commands::SplitAudioFiles {
+            dest,
+            chunk_minutes,
+            cut_at_silence,
+            silence,
+            by_chapters,
+            tempo,
+            preset,
+            format,
+            overlap_seconds,
+            jobs,
+            files,
+        } => {
+            let files = files_from_paths(&files);
+            let tools = FfmpegTools::locate(SystemRunner).ok();
+            let options = SegmentOptions {
+                silence: silence.settings(),
+                tempo,
+                preset,
+                format: format.into(),
+                overlap: overlap_seconds,
+                ..SegmentOptions::from_minutes(chunk_minutes, cut_at_silence, by_chapters)
+            };
+            let jobs = jobs.unwrap_or_else(audio_segment::default_concurrency);
+            audio_segment::split_files(tools.as_ref(), &files, &dest, &options, jobs, &out).await?;
+            out.result(&serde_json::json!({ "split": files.len() }), |_| {
+                println!("Split {} files into {}", files.len(), dest)
+            });
+        }
+        Commands::EstimateFit { source, dest } => {
+            let files = list_audio_files(&source)?;
+            let tools = FfmpegTools::locate(SystemRunner).map_err(|e| e.to_string())?;
+            let estimates = estimate_fit(&tools, &files, &dest)
+                .await
+                .map_err(|e| e.to_string())?;
+            out.result(&estimates, |estimates| {
+                for estimate in estimates {
+                    println!(
+                        "{}: {:.1} of {:.1} hours, room for {:.1} hours{}",
+                        estimate.preset.label(),
+                        estimate.selection_hours.min(estimate.hours_that_fit),
+                        estimate.selection_hours,
+                        estimate.hours_that_fit,
+                        if estimate.fits { "" } else { " (doesn't fit)" }
+                    );
+                }
+            });
+        }
+        Commands::DeleteFiles { files } => {
+            let files = files_from_paths(&files);
+            let count = files.len();
+            delete_files(files)?;
+            out.result(&serde_json::json!({ "deleted": count }), |_| {
+                println!("Deleted {} files", count)
+            });
+        }
+        Commands::CreateDiskImage { path, size_mb } => {
+            create_disk_image(Path::new(&path), size_mb).map_err(|e| e.to_string())?;
+            out.result(&serde_json::json!({ "created": path }), |_| {
+                println!("Created {}", path)
+            });
+        }
+        Commands::FlashDiskImage { image, dest, mode } => {
+            let report = copy_image_to_device(&image, &dest, mode.as_str(), &out);
+            if let Some(error) = report.destinations.into_iter().find_map(|r| r.error) {
+                return Err(error);
+            }
+            out.result(
+                &serde_json::json!({ "copied": image, "dest": dest }),
+                |_| println!("Copied the contents of {} to {}", image, dest),
+            );
+        }
+        Commands::ExportDevice {
+            dest,
+            playlist,
+            report,
+        } => {
+            let tools = FfmpegTools::locate(SystemRunner).ok();
+            if tools.is_none() {
+                eprintln!("ffmpeg not found; durations will be left out");
+            }
+            let contents = export_device_contents(
+                &dest,
+                tools.as_ref(),
+                Path::new(&playlist),
+                Path::new(&report),
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+            out.result(&contents, |contents| {
+                println!(
+                    "Listed {} tracks in {} and {}",
+                    contents.tracks.len(),
+                    playlist,
+                    report
+                )
+            });
+        }
+        Commands::RefreshFeeds {
+            staging,
+            feed,
+            opml,
+            keep,
+        } => {
+            let mut subscriptions: Vec<FeedSubscription> = feed
+                .into_iter()
+                .map(|url| FeedSubscription {
+                    url,
+                    title: None,
+                    keep,
+                })
+                .collect();
+            if let Some(opml) = opml {
+                let contents = std::fs::read_to_string(&opml)
+                    .map_err(|e| format!("Failed to read {}: {}", opml, e))?;
+                for mut subscription in feeds::parse_opml(&contents).map_err(|e| e.to_string())? {
+                    subscription.keep = keep;
+                    subscriptions.push(subscription);
+                }
+            }
+            let results = feeds::refresh_feeds(&subscriptions, &staging, &out);
+            out.result(&results, |results| {
+                for result in results {
+                    match &result.error {
+                        None => println!(
+                            "{}: downloaded {}, removed {}",
+                            result.title,
+                            result.downloaded.len(),
+                            result.removed.len()
+                        ),
+                        Some(e) => println!("{}: {}", result.title, e),
+                    }
+                }
+            });
+            if results.iter().any(|r| r.error.is_some()) {
+                return Err("Some feeds failed".to_string());
+            }
+        }
+        Commands::FindSilences { silence, files } => {
+            let settings = silence.settings();
+            let mut found = Vec::with_capacity(files.len());
+            for file in files {
+                let silences = analyze_silences(Path::new(&file), &settings)
+                    .map_err(|e| format!("{}: {:#}", file, e))?;
+                found.push(FileSilences { file, silences });
+            }
+            out.result(&found, |found| {
+                for entry in found {
+                    println!("{}", entry.file);
+                    for silence in &entry.silences {
+                        println!("  {:>9.2} - {:.2}", silence.start, silence.end);
+                    }
+                }
+            });
+        }
+        Commands::ProbeAudioFile { files } => {
+            let tools = FfmpegTools::locate(SystemRunner).ok();
+            let probed = probe_files(tools.as_ref(), &files).await;
+            out.result(&probed, |probed| {
+                for file in probed {
+                    println!("{}", file.path);
+                    if let Some(error) = &file.error {
+                        println!("  error: {}", error);
+                    }
+                    let Some(info) = &file.info else { continue };
+                    if let Some(duration) = info.duration {
+                        println!("  duration: {}", format_duration(duration));
+                    }
+                    if let Some(codec) = &info.codec {
+                        println!("  codec: {}", codec);
+                    }
+                    if let Some(bit_rate) = info.bit_rate {
+                        println!("  bit rate: {} kbps", bit_rate / 1000);
+                    }
+                    if let Some(sample_rate) = info.sample_rate {
+                        println!("  sample rate: {} Hz", sample_rate);
+                    }
+                    if let Some(channels) = info.channels {
+                        println!("  channels: {}", channels);
+                    }
+                    for (key, value) in &info.tags {
+                        println!("  {}: {}", key, value);
+                    }
+                    for chapter in &info.chapters {
+                        println!(
+                            "  chapter {} - {}: {}",
+                            format_duration(chapter.start),
+                            format_duration(chapter.end),
+                            chapter.title
+                        );
+                    }
+                    if let Some(art_bytes) = info.art_bytes {
+                        println!("  cover art: {} bytes", art_bytes);
+                    }
+                }
+            });
+        }
+        Commands::CheckFfmpeg => {
+            let report = check_ffmpeg(&SystemRunner, &FfmpegPaths::default()).await;
+            out.result(&report, |report| {
+                match &report.ffmpeg {
+                    Some(found) => println!(
+                        "Found ffmpeg {} at: {} ({})",
+                        report.version.as_deref().unwrap_or("of unknown version"),
+                        found.path.display(),
+                        found.source.describe()
+                    ),
+                    None => println!(
+                        "FFmpeg not found in ${}, the app bundle, PATH or common locations",
+                        find_ffmpeg::FFMPEG_ENV_VAR
+                    ),
+                }
+                match &report.ffprobe {
+                    Some(ffprobe) => println!(
+                        "Found ffprobe at: {} ({})",
+                        ffprobe.path.display(),
+                        ffprobe.source.describe()
+                    ),
+                    None => println!("ffprobe not found, so ffmpeg will describe inputs instead"),
+                }
+                if let Some(configuration) = &report.configuration {
+                    println!("Built with: {}", configuration);
+                }
+                for warning in &report.warnings {
+                    println!("Warning: {}", warning);
+                }
+                for status in &report.features {
+                    match &status.reason {
+                        None => println!("  {:?}: available", status.feature),
+                        Some(reason) => {
+                            println!("  {:?}: unavailable ({})", status.feature, reason)
+                        }
+                    }
+                }
+            });
+            if report.ffmpeg.is_none() {
+                return Err(
+                    "ffmpeg not found; MP3 splitting, file details and silence detection \
+                     still work without it"
+                        .to_string(),
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("Failed to start async runtime: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    match runtime.block_on(run(cli)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fs;
+use std::path::PathBuf;
+use tauri::Manager;
+
+/// A JSON file in the app config directory, like the saved profiles or feed subscriptions
+pub struct ConfigFile {
+    path: PathBuf,
+    /// What the file holds, for error messages
+    what: &'static str,
+}
+
+impl ConfigFile {
+    pub fn new(app: &tauri::AppHandle, file_name: &str, what: &'static str) -> Result<Self> {
+        let config_dir = app
+            .path()
+            .app_config_dir()
+            .context("Could not find the app config directory")?;
+        Ok(ConfigFile {
+            path: config_dir.join(file_name),
+            what,
+        })
+    }
+
+    /// Reads the file, or gives the default when nothing has been saved yet
+    pub fn read<T: DeserializeOwned + Default>(&self) -> Result<T> {
+        if !self.path.exists() {
+            return Ok(T::default());
+        }
+        let contents = fs::read_to_string(&self.path)
+            .with_context(|| format!("Failed to read {}", self.what))?;
+        serde_json::from_str(&contents).with_context(|| format!("Failed to parse {}", self.what))
+    }
+
+    pub fn write<T: Serialize + ?Sized>(&self, value: &T) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let contents = serde_json::to_string_pretty(value)?;
+        fs::write(&self.path, contents).with_context(|| format!("Failed to write {}", self.what))
+    }
+}
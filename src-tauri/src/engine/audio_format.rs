@@ -0,0 +1,161 @@
+//! Containers and codecs for split parts, so a split keeps the input's format unless it's
+//! asked to convert.
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use super::find_ffmpeg::FfmpegTools;
+use super::media_info;
+use super::process::ProcessRunner;
+
+/// Format to write split parts in
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    /// Whatever the input is, copied without re-encoding when nothing else changes the audio
+    #[default]
+    KeepInput,
+    Mp3,
+    /// AAC in an MPEG-4 container
+    M4a,
+    Flac,
+    Wav,
+}
+
+/// How parts in one format are written
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Container {
+    pub extension: &'static str,
+    /// ffmpeg's name for the muxer
+    pub muxer: &'static str,
+    /// Encoder arguments for when the audio can't be copied
+    encoder: &'static [&'static str],
+}
+
+impl Container {
+    pub const MP3: Container = Container {
+        extension: "mp3",
+        muxer: "mp3",
+        encoder: &["-c:a", "libmp3lame", "-q:a", "2"],
+    };
+    const M4A: Container = Container {
+        extension: "m4a",
+        muxer: "ipod",
+        encoder: &["-c:a", "aac", "-b:a", "160k"],
+    };
+    const ALAC: Container = Container {
+        extension: "m4a",
+        muxer: "ipod",
+        encoder: &["-c:a", "alac"],
+    };
+    const FLAC: Container = Container {
+        extension: "flac",
+        muxer: "flac",
+        encoder: &["-c:a", "flac"],
+    };
+    const WAV: Container = Container {
+        extension: "wav",
+        muxer: "wav",
+        encoder: &["-c:a", "pcm_s16le"],
+    };
+    const OGG: Container = Container {
+        extension: "ogg",
+        muxer: "ogg",
+        encoder: &["-c:a", "libvorbis", "-q:a", "5"],
+    };
+    const OPUS: Container = Container {
+        extension: "opus",
+        muxer: "opus",
+        encoder: &["-c:a", "libopus", "-b:a", "96k"],
+    };
+
+    /// The container a codec is usually stored in, or None for codecs we don't know how
+    /// to write on their own
+    fn for_codec(codec: &str) -> Option<Container> {
+        match codec {
+            "mp3" => Some(Container::MP3),
+            "aac" => Some(Container::M4A),
+            "alac" => Some(Container::ALAC),
+            "flac" => Some(Container::FLAC),
+            "vorbis" => Some(Container::OGG),
+            "opus" => Some(Container::OPUS),
+            codec if codec.starts_with("pcm_") => Some(Container::WAV),
+            _ => None,
+        }
+    }
+
+    pub fn encoder_args(&self) -> Vec<String> {
+        self.encoder.iter().map(|arg| arg.to_string()).collect()
+    }
+}
+
+impl OutputFormat {
+    /// The container to write for an input with `input_codec`, and whether the input's
+    /// audio can go into it as is. Inputs in codecs we don't recognize are converted to MP3.
+    pub fn resolve(self, input_codec: &str) -> (Container, bool) {
+        let native = Container::for_codec(input_codec);
+        let target = match self {
+            OutputFormat::KeepInput => native.unwrap_or(Container::MP3),
+            OutputFormat::Mp3 => Container::MP3,
+            OutputFormat::M4a => Container::M4A,
+            OutputFormat::Flac => Container::FLAC,
+            OutputFormat::Wav => Container::WAV,
+        };
+        (target, native == Some(target))
+    }
+}
+
+/// The codec of the first audio stream, as ffmpeg names it (`mp3`, `aac`, `pcm_s16le`...)
+pub async fn input_codec<R: ProcessRunner>(
+    tools: &FfmpegTools<R>,
+    input_filename: &str,
+) -> Result<String> {
+    let Some(ffprobe) = &tools.ffprobe else {
+        return media_info::describe_with_ffmpeg(tools, input_filename)
+            .await?
+            .codec
+            .ok_or_else(|| anyhow!("No audio stream found in {}", input_filename));
+    };
+    let output = tools
+        .runner
+        .output(
+            ffprobe,
+            &[
+                "-v".to_string(),
+                "error".to_string(),
+                "-select_streams".to_string(),
+                "a:0".to_string(),
+                "-show_entries".to_string(),
+                "stream=codec_name".to_string(),
+                "-of".to_string(),
+                "default=noprint_wrappers=1:nokey=1".to_string(),
+                input_filename.to_string(),
+            ],
+        )
+        .await
+        .context("Failed to execute ffprobe")?;
+    let codec = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !output.success || codec.is_empty() {
+        return Err(anyhow!("No audio stream found in {}", input_filename));
+    }
+    Ok(codec)
+}
+
+#[test]
+fn test_resolve_keeps_input_format_or_converts() {
+    assert_eq!(
+        OutputFormat::KeepInput.resolve("aac"),
+        (Container::M4A, true)
+    );
+    assert_eq!(
+        OutputFormat::KeepInput.resolve("pcm_s24le"),
+        (Container::WAV, true)
+    );
+    assert_eq!(
+        OutputFormat::KeepInput.resolve("wmav2"),
+        (Container::MP3, false)
+    );
+    assert_eq!(OutputFormat::Mp3.resolve("mp3"), (Container::MP3, true));
+    assert_eq!(OutputFormat::M4a.resolve("alac"), (Container::M4A, false));
+    assert_eq!(OutputFormat::Flac.resolve("mp3").0.extension, "flac");
+}
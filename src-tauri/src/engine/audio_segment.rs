@@ -0,0 +1,1256 @@
+use anyhow::{Context, Result};
+use futures_util::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use super::audio_format::{input_codec, Container, OutputFormat};
+use super::encoding::EncodingPreset;
+use super::ffmpeg_progress::ProgressReader;
+use super::files::{safe_file_name, AudioFile, CopyProgress};
+use super::find_ffmpeg::FfmpegTools;
+use super::media_info;
+use super::mp3_split::Mp3File;
+use super::process::ProcessRunner;
+use super::progress::ProgressSink;
+use super::silence::{analyze_silences, silence_points, SilenceSettings};
+
+#[derive(Clone, serde::Serialize)]
+pub struct SegmentProgress {
+    pub file_name: String,
+    pub progress: f64,
+    pub completed: bool,
+    pub index: usize,
+    pub total: usize,
+    /// How many times faster than real time ffmpeg is going, when it's doing the work
+    pub speed: Option<f64>,
+    /// Roughly how many seconds are left, going by the speed
+    pub eta_seconds: Option<f64>,
+}
+
+pub(super) async fn audio_file_duration<R: ProcessRunner>(
+    tools: &FfmpegTools<R>,
+    input_filename: &str,
+) -> Result<f64> {
+    let Some(ffprobe) = &tools.ffprobe else {
+        return media_info::duration_without_ffprobe(tools, input_filename).await;
+    };
+    let output = tools
+        .runner
+        .output(
+            ffprobe,
+            &[
+                "-v".to_string(),
+                "error".to_string(),
+                "-show_entries".to_string(),
+                "format=duration".to_string(),
+                "-of".to_string(),
+                "default=noprint_wrappers=1:nokey=1".to_string(),
+                input_filename.to_string(),
+            ],
+        )
+        .await
+        .context("Failed to execute ffprobe")?;
+
+    let duration =
+        String::from_utf8(output.stdout.clone()).context("Failed to parse ffprobe output")?;
+    let duration = duration
+        .trim()
+        .parse::<f64>()
+        .context("Failed to parse duration")?;
+
+    Ok(duration)
+}
+
+fn split_at_silences(silences: Vec<f64>, segment_time: i32) -> Vec<f64> {
+    let mut split_points: Vec<f64> = Vec::new();
+    let mut last_split = 0.0;
+    let mut last_split_ix: i64 = -1;
+    let mut idx = 0;
+    while idx < silences.len() {
+        let this_stretch = silences[idx] - last_split;
+        if this_stretch > segment_time as f64 {
+            if idx as i64 - 1 != last_split_ix {
+                last_split_ix = (idx - 1) as i64;
+                last_split = silences[last_split_ix as usize];
+                split_points.push(last_split);
+            } else {
+                last_split += segment_time as f64;
+                split_points.push(last_split);
+            }
+        } else {
+            idx += 1;
+        }
+    }
+    split_points
+}
+
+#[test]
+fn test_split_at_silences_splits_early_if_necessary() {
+    //                          |            |            |
+    let silences = vec![80.0, 90.0, 110.0, 180.0, 200.0, 250.0, 310.0];
+    let segment_time = 100;
+    let split_points = split_at_silences(silences, segment_time);
+    assert_eq!(split_points, vec![90.0, 180.0, 250.0]);
+}
+
+#[test]
+fn test_split_at_silences_splits_at_segment_time_if_no_silence_for_long_enough() {
+    let silences = vec![120.0, 350.0];
+    let segment_time = 100;
+    let split_points = split_at_silences(silences, segment_time);
+    assert_eq!(split_points, vec![100.0, 120.0, 220.0, 320.0]);
+}
+
+/// Cuts every `segment_time` seconds
+fn fixed_points(duration: f64, segment_time: i32) -> Vec<f64> {
+    let num_segments = (duration / segment_time as f64).ceil() as i32;
+    (1..num_segments)
+        .map(|i| i as f64 * segment_time as f64)
+        .collect()
+}
+
+/// A chapter marker embedded in the file, e.g. an M4B chapter atom or an ID3 CHAP frame
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Chapter {
+    pub start: f64,
+    pub end: f64,
+    pub title: String,
+}
+
+#[derive(Deserialize)]
+struct ProbedChapters {
+    #[serde(default)]
+    chapters: Vec<ProbedChapter>,
+}
+
+/// A chapter as ffprobe prints it in JSON
+#[derive(Deserialize)]
+pub(super) struct ProbedChapter {
+    start_time: String,
+    end_time: String,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+}
+
+async fn chapters<R: ProcessRunner>(
+    tools: &FfmpegTools<R>,
+    input_filename: &str,
+) -> Result<Vec<Chapter>> {
+    let Some(ffprobe) = &tools.ffprobe else {
+        return Ok(media_info::describe_with_ffmpeg(tools, input_filename)
+            .await?
+            .chapters);
+    };
+    let output = tools
+        .runner
+        .output(
+            ffprobe,
+            &[
+                "-v".to_string(),
+                "error".to_string(),
+                "-show_chapters".to_string(),
+                "-of".to_string(),
+                "json".to_string(),
+                input_filename.to_string(),
+            ],
+        )
+        .await
+        .context("Failed to execute ffprobe")?;
+    let probed: ProbedChapters =
+        serde_json::from_slice(&output.stdout).context("Failed to parse ffprobe chapters")?;
+    probed_chapters(probed.chapters)
+}
+
+/// Converts ffprobe's chapters, naming untitled ones by their number
+pub(super) fn probed_chapters(chapters: Vec<ProbedChapter>) -> Result<Vec<Chapter>> {
+    chapters
+        .into_iter()
+        .enumerate()
+        .map(|(i, chapter)| {
+            Ok(Chapter {
+                start: chapter.start_time.parse().context("Bad chapter start")?,
+                end: chapter.end_time.parse().context("Bad chapter end")?,
+                title: chapter
+                    .tags
+                    .get("title")
+                    .filter(|t| !t.trim().is_empty())
+                    .cloned()
+                    .unwrap_or_else(|| format!("Chapter {}", i + 1)),
+            })
+        })
+        .collect()
+}
+
+/// Where to cut a file, and optionally what to call each resulting part
+struct SplitPlan {
+    points: Vec<f64>,
+    titles: Option<Vec<String>>,
+    /// Silences found in the file, when cutting at silences
+    silences: Vec<f64>,
+    /// Length of the input in seconds, when it could be found
+    duration: Option<f64>,
+}
+
+/// Cuts at every chapter. With `silences`, chapters longer than `segment_time` are also
+/// cut at silences inside them, and their parts are titled "Chapter (1 of 3)" and so on.
+fn split_chapters(chapters: &[Chapter], silences: Option<&[f64]>, segment_time: i32) -> SplitPlan {
+    let mut points = Vec::new();
+    let mut titles = Vec::new();
+    for (i, chapter) in chapters.iter().enumerate() {
+        if i > 0 {
+            points.push(chapter.start);
+        }
+        let mut inner = Vec::new();
+        if let Some(silences) = silences {
+            if chapter.end - chapter.start > segment_time as f64 {
+                let mut relative: Vec<f64> = silences
+                    .iter()
+                    .filter(|s| **s > chapter.start && **s < chapter.end)
+                    .map(|s| s - chapter.start)
+                    .collect();
+                // The chapter's end counts as a silence, so the stretch after the last real
+                // silence is cut down to size too
+                relative.push(chapter.end - chapter.start);
+                inner = split_at_silences(relative, segment_time)
+                    .into_iter()
+                    .map(|p| p + chapter.start)
+                    .filter(|p| *p < chapter.end)
+                    .collect();
+            }
+        }
+        let parts = inner.len() + 1;
+        if parts == 1 {
+            titles.push(chapter.title.clone());
+        } else {
+            titles.extend((1..=parts).map(|k| format!("{} ({} of {})", chapter.title, k, parts)));
+        }
+        points.extend(inner);
+    }
+    SplitPlan {
+        points,
+        titles: Some(titles),
+        silences: silences.map(<[f64]>::to_vec).unwrap_or_default(),
+        duration: chapters.last().map(|chapter| chapter.end),
+    }
+}
+
+#[test]
+fn test_split_chapters_cuts_long_chapters_at_silences() {
+    let chapters = vec![
+        Chapter {
+            start: 0.0,
+            end: 90.0,
+            title: "Prologue".to_string(),
+        },
+        Chapter {
+            start: 90.0,
+            end: 400.0,
+            title: "Chapter 1".to_string(),
+        },
+    ];
+    let silences = vec![50.0, 150.0, 185.0, 300.0];
+
+    let plan = split_chapters(&chapters, None, 100);
+    assert_eq!(plan.points, vec![90.0]);
+    assert_eq!(plan.titles.unwrap(), vec!["Prologue", "Chapter 1"]);
+
+    let plan = split_chapters(&chapters, Some(&silences), 100);
+    assert_eq!(plan.points, vec![90.0, 185.0, 285.0, 300.0]);
+    assert_eq!(
+        plan.titles.unwrap(),
+        vec![
+            "Prologue",
+            "Chapter 1 (1 of 4)",
+            "Chapter 1 (2 of 4)",
+            "Chapter 1 (3 of 4)",
+            "Chapter 1 (4 of 4)",
+        ]
+    );
+}
+
+/// Uses the file's chapters when asked to and it has more than one, otherwise cuts at
+/// fixed lengths or silences. Points are in input time, scaled so that with a tempo other
+/// than 1 each part still plays for `segment_time`.
+async fn plan_split<R: ProcessRunner>(
+    tools: &FfmpegTools<R>,
+    input_filename: &str,
+    options: &SegmentOptions,
+) -> Result<SplitPlan> {
+    // A part covers more (or less) of the recording than it takes to play
+    let input_segment_time = (options.segment_time as f64 * options.tempo).round() as i32;
+    let silences = match options.cut_at_silence {
+        true => Some(silence_points(tools, input_filename, &options.silence).await?),
+        false => None,
+    };
+    if options.by_chapters {
+        let chapters = chapters(tools, input_filename).await?;
+        if chapters.len() > 1 {
+            return Ok(split_chapters(
+                &chapters,
+                silences.as_deref(),
+                input_segment_time,
+            ));
+        }
+    }
+    // Cutting at silences only needs the duration to measure progress
+    let (points, duration) = match silences.as_deref() {
+        Some(silences) => (
+            split_at_silences(silences.to_vec(), input_segment_time),
+            audio_file_duration(tools, input_filename).await.ok(),
+        ),
+        None => {
+            let duration = audio_file_duration(tools, input_filename).await?;
+            (fixed_points(duration, input_segment_time), Some(duration))
+        }
+    };
+    Ok(SplitPlan {
+        points,
+        titles: None,
+        silences: silences.unwrap_or_default(),
+        duration,
+    })
+}
+
+/// Start and end of each part, in input time. Every part after the first starts `overlap`
+/// seconds before the previous one ends, moved to the nearest silence within reach so the
+/// replay starts at a natural pause.
+fn overlapping_parts(points: &[f64], silences: &[f64], overlap: f64) -> Vec<(f64, Option<f64>)> {
+    let mut parts = Vec::with_capacity(points.len() + 1);
+    let mut start = 0.0;
+    for &point in points {
+        parts.push((start, Some(point)));
+        let target = point - overlap;
+        // Anything from half to twice the overlap still replays a useful amount
+        let snapped = silences
+            .iter()
+            .copied()
+            .filter(|s| *s >= point - 2.0 * overlap && *s <= point - overlap / 2.0)
+            .min_by(|a, b| (a - target).abs().total_cmp(&(b - target).abs()));
+        start = snapped.unwrap_or(target).max(start);
+    }
+    parts.push((start, None));
+    parts
+}
+
+/// Builds an `atempo` filter for any speed. A single atempo only reliably accepts factors
+/// between 0.5 and 2, so larger changes are chained.
+fn atempo_filter(tempo: f64) -> String {
+    let mut remaining = tempo;
+    let mut stages = Vec::new();
+    while remaining > 2.0 {
+        stages.push(2.0);
+        remaining /= 2.0;
+    }
+    while remaining < 0.5 {
+        stages.push(0.5);
+        remaining /= 0.5;
+    }
+    stages.push(remaining);
+    stages
+        .iter()
+        .map(|stage| format!("atempo={}", stage))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// How each file should be cut up
+#[derive(Debug, Clone)]
+pub struct SegmentOptions {
+    /// Target length of each part, in seconds
+    pub segment_time: i32,
+    pub cut_at_silence: bool,
+    /// What counts as a silence when cutting at silences
+    pub silence: SilenceSettings,
+    /// Cut at the file's chapter markers, naming parts after the chapters. Combined with
+    /// `cut_at_silence`, long chapters are cut further at silences.
+    pub by_chapters: bool,
+    /// Playback speed, e.g. 1.3. Anything but 1 re-encodes the audio, keeping its pitch.
+    pub tempo: f64,
+    /// How to encode the parts. Anything but keeping the original re-encodes the audio
+    /// as MP3, whatever the `format`.
+    pub preset: EncodingPreset,
+    pub format: OutputFormat,
+    /// Seconds each part repeats from the end of the one before, so restarting a track
+    /// replays what came just before it
+    pub overlap: f64,
+}
+
+impl SegmentOptions {
+    pub fn from_minutes(chunk_minutes: u32, cut_at_silence: bool, by_chapters: bool) -> Self {
+        SegmentOptions {
+            segment_time: (chunk_minutes * 60) as i32,
+            cut_at_silence,
+            silence: SilenceSettings::default(),
+            by_chapters,
+            tempo: 1.0,
+            preset: EncodingPreset::KeepOriginal,
+            format: OutputFormat::KeepInput,
+            overlap: 0.0,
+        }
+    }
+}
+
+/// Where and how the parts of one file are written
+struct PartOutput {
+    /// Parts are written as `<prefix>_part_0000.<extension>` and so on
+    prefix: String,
+    container: Container,
+    /// Output arguments for ffmpeg
+    codec_args: Vec<String>,
+}
+
+impl PartOutput {
+    /// Copies the audio straight across unless the speed, the preset or the format changes it
+    fn new(prefix: String, options: &SegmentOptions, input_codec: &str) -> Self {
+        let (container, copyable, encoder_args) = match options.preset.codec_args() {
+            Some(preset_args) => (Container::MP3, false, preset_args),
+            None => {
+                let (container, copyable) = options.format.resolve(input_codec);
+                let mut args = vec!["-vn".to_string()];
+                args.extend(container.encoder_args());
+                (container, copyable, args)
+            }
+        };
+        let codec_args = if copyable && options.tempo == 1.0 {
+            vec!["-c".to_string(), "copy".to_string()]
+        } else {
+            let mut args = Vec::new();
+            // Changing speed means decoding, so the audio has to be encoded again
+            if options.tempo != 1.0 {
+                args.extend(["-filter:a".to_string(), atempo_filter(options.tempo)]);
+            }
+            args.extend(encoder_args);
+            args
+        };
+        PartOutput {
+            prefix,
+            container,
+            codec_args,
+        }
+    }
+
+    fn part(&self, i: usize) -> String {
+        format!("{}_part_{:04}.{}", self.prefix, i, self.container.extension)
+    }
+}
+
+/// Turns ffmpeg's `-progress` updates into progress through the whole file, which may take
+/// several ffmpeg runs
+struct OutputProgress<'a, P: ProgressSink> {
+    sink: &'a P,
+    file_name: &'a str,
+    index: usize,
+    total: usize,
+    /// Seconds of output the file makes, when its duration is known
+    output_duration: Option<f64>,
+    /// Seconds of output written by earlier runs
+    written: f64,
+    reader: ProgressReader,
+}
+
+impl<'a, P: ProgressSink> OutputProgress<'a, P> {
+    fn new(
+        sink: &'a P,
+        file_name: &'a str,
+        index: usize,
+        total: usize,
+        output_duration: Option<f64>,
+    ) -> Self {
+        OutputProgress {
+            sink,
+            file_name,
+            index,
+            total,
+            output_duration,
+            written: 0.0,
+            reader: ProgressReader::default(),
+        }
+    }
+
+    fn line(&mut self, line: &str) {
+        let (Some(update), Some(output_duration)) = (self.reader.line(line), self.output_duration)
+        else {
+            return;
+        };
+        let done = (self.written + update.out_time).min(output_duration);
+        self.sink.segment_progress(SegmentProgress {
+            file_name: self.file_name.to_string(),
+            progress: done / output_duration.max(f64::EPSILON) * 100.0,
+            completed: false,
+            index: self.index,
+            total: self.total,
+            speed: update.speed,
+            eta_seconds: update.speed.map(|speed| (output_duration - done) / speed),
+        });
+    }
+
+    /// Starts counting from the end of a finished run of `seconds` of output
+    fn run_finished(&mut self, seconds: f64) {
+        self.written += seconds;
+        self.reader = ProgressReader::default();
+    }
+}
+
+/// Arguments that make ffmpeg report progress as `key=value` lines on stderr, in place of
+/// its usual status line
+fn progress_args() -> [String; 3] {
+    [
+        "-nostats".to_string(),
+        "-progress".to_string(),
+        "pipe:2".to_string(),
+    ]
+}
+
+/// Writes each part with its own ffmpeg run, since the segment muxer can't overlap parts
+#[allow(clippy::too_many_arguments)]
+async fn extract_overlapping_parts<R: ProcessRunner>(
+    tools: &FfmpegTools<R>,
+    input_filename: &str,
+    output: &PartOutput,
+    plan: &SplitPlan,
+    options: &SegmentOptions,
+    file_name: &str,
+    index: usize,
+    total: usize,
+    progress: &impl ProgressSink,
+) -> Result<()> {
+    let parts = overlapping_parts(
+        &plan.points,
+        &plan.silences,
+        options.overlap * options.tempo,
+    );
+    // Parts overlap, so the output adds up to more than the input
+    let output_duration = plan.duration.map(|duration| {
+        parts
+            .iter()
+            .map(|(start, end)| end.unwrap_or(duration) - start)
+            .sum::<f64>()
+            / options.tempo
+    });
+    let mut tracker = OutputProgress::new(progress, file_name, index, total, output_duration);
+    for (i, (start, end)) in parts.iter().enumerate() {
+        let mut args = vec!["-y".to_string()];
+        args.extend(progress_args());
+        args.extend(["-ss".to_string(), start.to_string()]);
+        if let Some(end) = end {
+            args.extend(["-to".to_string(), end.to_string()]);
+        }
+        args.extend(["-i".to_string(), input_filename.to_string()]);
+        args.extend(output.codec_args.iter().cloned());
+        args.push(output.part(i));
+        let succeeded = tools
+            .runner
+            .run_with_stderr(&tools.ffmpeg, &args, &mut |line| tracker.line(line))
+            .await
+            .context("Failed to execute ffmpeg")?;
+        if !succeeded {
+            return Err(anyhow::anyhow!("ffmpeg command failed"));
+        }
+        if let Some(length) = end
+            .or(plan.duration)
+            .map(|end| (end - start) / options.tempo)
+        {
+            tracker.run_finished(length);
+        }
+    }
+    Ok(())
+}
+
+/// Writes every part in one run of ffmpeg's segment muxer, following its progress
+#[allow(clippy::too_many_arguments)]
+async fn run_segment_muxer<R: ProcessRunner>(
+    tools: &FfmpegTools<R>,
+    input_filename: &str,
+    output: &PartOutput,
+    plan: &SplitPlan,
+    options: &SegmentOptions,
+    file_name: &str,
+    index: usize,
+    total: usize,
+    progress: &impl ProgressSink,
+) -> Result<()> {
+    // The segment muxer cuts by output timestamps, which the tempo stretches or squeezes
+    let splits: Vec<f64> = plan.points.iter().map(|p| p / options.tempo).collect();
+    let mut args = progress_args().to_vec();
+    args.extend([
+        "-i".to_string(),
+        input_filename.to_string(),
+        "-f".to_string(),
+        "segment".to_string(),
+        "-segment_times".to_string(),
+        splits
+            .iter()
+            .map(|s| s.to_string())
+            .collect::<Vec<_>>()
+            .join(","),
+        "-segment_format".to_string(),
+        output.container.muxer.to_string(),
+    ]);
+    args.extend(output.codec_args.iter().cloned());
+    args.push(format!(
+        "{}_part_%04d.{}",
+        output.prefix, output.container.extension
+    ));
+
+    let output_duration = plan.duration.map(|duration| duration / options.tempo);
+    let mut tracker = OutputProgress::new(progress, file_name, index, total, output_duration);
+    let mut on_line = |line: &str| tracker.line(line);
+    let succeeded = tools
+        .runner
+        .run_with_stderr(&tools.ffmpeg, &args, &mut on_line)
+        .await?;
+    if !succeeded {
+        return Err(anyhow::anyhow!("ffmpeg command failed"));
+    }
+    Ok(())
+}
+
+/// Whether a file can be cut in-process without ffmpeg: an MP3 going out unchanged
+fn can_split_natively(input_filename: &str, options: &SegmentOptions) -> bool {
+    let is_mp3 = Path::new(input_filename)
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("mp3"));
+    is_mp3
+        && options.tempo == 1.0
+        && options.preset.codec_args().is_none()
+        && matches!(options.format, OutputFormat::KeepInput | OutputFormat::Mp3)
+        && !options.by_chapters
+}
+
+/// Cuts an MP3 between frames, finding silences with the native analysis
+fn split_mp3_natively(
+    input_filename: &str,
+    output_prefix: &str,
+    options: &SegmentOptions,
+    file_name: &str,
+    index: usize,
+    total: usize,
+    progress: &impl ProgressSink,
+) -> Result<()> {
+    let mp3 = Mp3File::open(Path::new(input_filename))?;
+    let silences = match options.cut_at_silence {
+        true => Some(
+            analyze_silences(Path::new(input_filename), &options.silence)?
+                .iter()
+                .map(|s| s.start)
+                .collect::<Vec<_>>(),
+        ),
+        false => None,
+    };
+    let points = match &silences {
+        Some(silences) => split_at_silences(silences.clone(), options.segment_time),
+        None => fixed_points(mp3.duration(), options.segment_time),
+    };
+    let parts: Vec<(f64, Option<f64>)> = if options.overlap > 0.0 {
+        overlapping_parts(
+            &points,
+            silences.as_deref().unwrap_or_default(),
+            options.overlap,
+        )
+    } else {
+        std::iter::once(0.0)
+            .chain(points.iter().copied())
+            .zip(points.iter().copied().map(Some).chain([None]))
+            .collect()
+    };
+    for (i, (start, end)) in parts.iter().enumerate() {
+        let part = format!("{}_part_{:04}.mp3", output_prefix, i);
+        mp3.write_part(*start, *end, i + 1, parts.len(), Path::new(&part))?;
+        progress.segment_progress(SegmentProgress {
+            file_name: file_name.to_string(),
+            progress: (i + 1) as f64 / parts.len() as f64 * 100.0,
+            completed: false,
+            index,
+            total,
+            speed: None,
+            eta_seconds: None,
+        });
+    }
+    Ok(())
+}
+
+/// Forwards updates from a split running on a blocking thread
+struct ForwardedProgress(tokio::sync::mpsc::UnboundedSender<SegmentProgress>);
+
+impl ProgressSink for ForwardedProgress {
+    fn segment_progress(&self, progress: SegmentProgress) {
+        let _ = self.0.send(progress);
+    }
+}
+
+/// Runs [`split_mp3_natively`] on a blocking thread, so files split in parallel don't wait
+/// on each other
+async fn split_mp3_in_background(
+    input_filename: &str,
+    output_prefix: &str,
+    options: &SegmentOptions,
+    file_name: &str,
+    index: usize,
+    total: usize,
+    progress: &impl ProgressSink,
+) -> Result<()> {
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+    let (input_filename, output_prefix, options, file_name) = (
+        input_filename.to_string(),
+        output_prefix.to_string(),
+        options.clone(),
+        file_name.to_string(),
+    );
+    let job = tokio::task::spawn_blocking(move || {
+        split_mp3_natively(
+            &input_filename,
+            &output_prefix,
+            &options,
+            &file_name,
+            index,
+            total,
+            &ForwardedProgress(sender),
+        )
+    });
+    // The channel closes once the split finishes and drops its sender
+    while let Some(update) = receiver.recv().await {
+        progress.segment_progress(update);
+    }
+    job.await?
+}
+
+/// Splits one file into parts in `output_folder`. MP3s that don't need re-encoding are cut
+/// in-process; everything else needs ffmpeg.
+pub async fn segment_audio<R: ProcessRunner>(
+    tools: Option<&FfmpegTools<R>>,
+    input_filename: &str,
+    output_folder: &str,
+    options: &SegmentOptions,
+    index: usize,
+    total: usize,
+    progress: &impl ProgressSink,
+) -> Result<()> {
+    // Ensure output directory exists
+    fs::create_dir_all(output_folder)?;
+
+    // Get input filename without path and extension
+    let input_name = Path::new(input_filename)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .context("Invalid input filename")?;
+
+    // Get just the filename for progress reporting
+    let file_name = Path::new(input_filename)
+        .file_name()
+        .and_then(|s| s.to_str())
+        .context("Invalid input filename")?
+        .to_string();
+
+    if !(options.tempo.is_finite() && options.tempo > 0.0) {
+        return Err(anyhow::anyhow!("Invalid tempo {}", options.tempo));
+    }
+    let output_prefix = format!("{}/{}", output_folder, input_name);
+
+    // Emit initial progress
+    progress.segment_progress(SegmentProgress {
+        file_name: file_name.clone(),
+        progress: 0.0,
+        completed: false,
+        index,
+        total,
+        speed: None,
+        eta_seconds: None,
+    });
+
+    if can_split_natively(input_filename, options) {
+        match split_mp3_in_background(
+            input_filename,
+            &output_prefix,
+            options,
+            &file_name,
+            index,
+            total,
+            progress,
+        )
+        .await
+        {
+            Ok(()) => {
+                progress.segment_progress(SegmentProgress {
+                    file_name,
+                    progress: 100.0,
+                    completed: true,
+                    index,
+                    total,
+                    speed: None,
+                    eta_seconds: None,
+                });
+                return Ok(());
+            }
+            Err(e) if tools.is_some() => {
+                log::info!("Splitting {} with ffmpeg: {:#}", input_filename, e)
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    let tools = tools.context("FFmpeg not found")?;
+    let plan = plan_split(tools, input_filename, options).await?;
+    let codec = input_codec(tools, input_filename).await?;
+    let output = PartOutput::new(output_prefix, options, &codec);
+
+    if options.overlap > 0.0 {
+        extract_overlapping_parts(
+            tools,
+            input_filename,
+            &output,
+            &plan,
+            options,
+            &file_name,
+            index,
+            total,
+            progress,
+        )
+        .await?;
+    } else {
+        run_segment_muxer(
+            tools,
+            input_filename,
+            &output,
+            &plan,
+            options,
+            &file_name,
+            index,
+            total,
+            progress,
+        )
+        .await?;
+    }
+
+    // Name chapter parts after their chapters, keeping a number in front so they sort in order.
+    // Chapters that start at or past the end of the audio get no part of their own.
+    if let Some(titles) = &plan.titles {
+        for (i, title) in titles.iter().enumerate() {
+            let part = output.part(i);
+            if !Path::new(&part).exists() {
+                break;
+            }
+            let named = format!(
+                "{}/{} {:03} {}.{}",
+                output_folder,
+                input_name,
+                i + 1,
+                safe_file_name(title),
+                output.container.extension
+            );
+            fs::rename(&part, &named).with_context(|| format!("Failed to rename {}", part))?;
+        }
+    }
+
+    // Emit completion
+    progress.segment_progress(SegmentProgress {
+        file_name,
+        progress: 100.0,
+        completed: true,
+        index,
+        total,
+        speed: None,
+        eta_seconds: None,
+    });
+
+    Ok(())
+}
+
+/// The number of files split at once when no limit is given: one per core
+pub fn default_concurrency() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// Splits each file into parts, up to `concurrency` files at a time. Progress for a file
+/// carries its place in the list as its `index`. Files start in order but finish out of
+/// order, so copy progress also counts how many files are done so far as `finished`. After
+/// a failure no more files are started, and the first error is returned once the running
+/// ones finish.
+pub async fn split_files<R: ProcessRunner>(
+    tools: Option<&FfmpegTools<R>>,
+    files: &[AudioFile],
+    dest_path: &str,
+    options: &SegmentOptions,
+    concurrency: usize,
+    progress: &impl ProgressSink,
+) -> Result<(), String> {
+    let finished = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    // Counting through indices rather than enumerating `files` keeps the compiler able to
+    // prove this future is Send, which the job queue needs to spawn it
+    let results: Vec<Result<(), String>> = stream::iter(0..files.len())
+        .map(|index| {
+            let (file, finished, failed) = (&files[index], &finished, &failed);
+            async move {
+                if failed.load(Ordering::SeqCst) {
+                    return Ok(());
+                }
+                progress.copy_progress(CopyProgress {
+                    file_name: file.name.clone(),
+                    dest_path: dest_path.to_string(),
+                    completed: false,
+                    index,
+                    finished: finished.load(Ordering::SeqCst),
+                    total: files.len(),
+                });
+
+                segment_audio(
+                    tools,
+                    &file.path,
+                    dest_path,
+                    options,
+                    index,
+                    files.len(),
+                    progress,
+                )
+                .await
+                .map_err(|e| {
+                    failed.store(true, Ordering::SeqCst);
+                    format!("Failed to split {}: {}", file.name, e)
+                })?;
+
+                progress.copy_progress(CopyProgress {
+                    file_name: file.name.clone(),
+                    dest_path: dest_path.to_string(),
+                    completed: true,
+                    index,
+                    finished: finished.fetch_add(1, Ordering::SeqCst) + 1,
+                    total: files.len(),
+                });
+                Ok(())
+            }
+        })
+        .buffer_unordered(concurrency.max(1))
+        .collect()
+        .await;
+    results.into_iter().collect()
+}
+
+#[cfg(test)]
+struct FakeFfmpeg {
+    codec: &'static str,
+    silencedetect_stderr: String,
+    segment_stderr: Vec<String>,
+    calls: std::sync::Mutex<Vec<Vec<String>>>,
+}
+
+#[cfg(test)]
+impl ProcessRunner for FakeFfmpeg {
+    async fn output(
+        &self,
+        _program: &Path,
+        args: &[String],
+    ) -> Result<super::process::ProcessOutput> {
+        self.calls.lock().unwrap().push(args.to_vec());
+        let asks_for = |entry: &str| args.iter().any(|a| a == entry);
+        let stdout = if asks_for("stream=codec_name") {
+            self.codec.as_bytes().to_vec()
+        } else if asks_for("format=duration") {
+            b"300".to_vec()
+        } else {
+            Vec::new()
+        };
+        Ok(super::process::ProcessOutput {
+            success: true,
+            stdout,
+            stderr: self.silencedetect_stderr.clone().into_bytes(),
+        })
+    }
+
+    async fn run_with_stderr(
+        &self,
+        _program: &Path,
+        args: &[String],
+        on_line: &mut (dyn FnMut(&str) + Send),
+    ) -> Result<bool> {
+        self.calls.lock().unwrap().push(args.to_vec());
+        for line in &self.segment_stderr {
+            on_line(line);
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+#[derive(Default)]
+struct RecordedProgress(std::sync::Mutex<Vec<(f64, Option<f64>)>>);
+
+#[cfg(test)]
+impl ProgressSink for RecordedProgress {
+    fn segment_progress(&self, progress: SegmentProgress) {
+        let entry = (progress.progress, progress.eta_seconds);
+        self.0.lock().unwrap().push(entry);
+    }
+}
+
+#[test]
+fn test_segment_audio_cuts_at_silences_and_reports_progress() {
+    let tools = FfmpegTools {
+        runner: FakeFfmpeg {
+            codec: "aac",
+            silencedetect_stderr: [
+                "[silencedetect @ 0x1] silence_start: 80.5",
+                "[silencedetect @ 0x1] silence_end: 82 | silence_duration: 1.5",
+                "[silencedetect @ 0x1] silence_start: 150",
+                "[silencedetect @ 0x1] silence_start: 230",
+            ]
+            .join("\n"),
+            segment_stderr: vec![
+                "[segment @ 0x2] Opening 'out/book_part_0000.m4a' for writing".to_string(),
+                "out_time_us=150000000".to_string(),
+                "speed=30x".to_string(),
+                "progress=continue".to_string(),
+                "[segment @ 0x2] Opening 'out/book_part_0001.m4a' for writing".to_string(),
+                "out_time_us=300000000".to_string(),
+                "progress=end".to_string(),
+            ],
+            calls: Default::default(),
+        },
+        ffmpeg: "ffmpeg".into(),
+        ffprobe: Some("ffprobe".into()),
+    };
+    let output_folder = std::env::temp_dir().join("sync-and-swim-segment-test");
+    let progress = RecordedProgress::default();
+
+    tokio::runtime::Runtime::new()
+        .unwrap()
+        .block_on(segment_audio(
+            Some(&tools),
+            "book.m4a",
+            output_folder.to_str().unwrap(),
+            &SegmentOptions {
+                segment_time: 100,
+                cut_at_silence: true,
+                silence: SilenceSettings::default(),
+                by_chapters: false,
+                tempo: 1.0,
+                preset: EncodingPreset::KeepOriginal,
+                format: OutputFormat::KeepInput,
+                overlap: 0.0,
+            },
+            0,
+            1,
+            &progress,
+        ))
+        .unwrap();
+
+    let calls = tools.runner.calls.lock().unwrap();
+    let arg_after = |name: &str| calls[3].iter().skip_while(|arg| *arg != name).nth(1);
+    assert_eq!(arg_after("-segment_times").unwrap(), "80.5,150");
+    // AAC stays in an M4A container rather than being written out as .mp3
+    assert_eq!(arg_after("-segment_format").unwrap(), "ipod");
+    assert_eq!(arg_after("-c").unwrap(), "copy");
+    assert!(calls[3].last().unwrap().ends_with("book_part_%04d.m4a"));
+    // Halfway through the 300 seconds at 30x leaves 5 seconds to go
+    assert_eq!(
+        *progress.0.lock().unwrap(),
+        vec![
+            (0.0, None),
+            (50.0, Some(5.0)),
+            (100.0, Some(0.0)),
+            (100.0, None)
+        ]
+    );
+}
+
+#[test]
+fn test_tempo_measures_parts_in_output_time() {
+    let tools = FfmpegTools {
+        runner: FakeFfmpeg {
+            codec: "mp3",
+            silencedetect_stderr: [
+                "[silencedetect @ 0x1] silence_start: 80.5",
+                "[silencedetect @ 0x1] silence_start: 150",
+                "[silencedetect @ 0x1] silence_start: 230",
+            ]
+            .join("\n"),
+            segment_stderr: Vec::new(),
+            calls: Default::default(),
+        },
+        ffmpeg: "ffmpeg".into(),
+        ffprobe: Some("ffprobe".into()),
+    };
+    let output_folder = std::env::temp_dir().join("sync-and-swim-tempo-test");
+
+    tokio::runtime::Runtime::new()
+        .unwrap()
+        .block_on(segment_audio(
+            Some(&tools),
+            "book.mp3",
+            output_folder.to_str().unwrap(),
+            &SegmentOptions {
+                segment_time: 100,
+                cut_at_silence: true,
+                silence: SilenceSettings::default(),
+                by_chapters: false,
+                tempo: 1.25,
+                preset: EncodingPreset::KeepOriginal,
+                format: OutputFormat::KeepInput,
+                overlap: 0.0,
+            },
+            0,
+            1,
+            &super::progress::NoProgress,
+        ))
+        .unwrap();
+
+    let calls = tools.runner.calls.lock().unwrap();
+    let arg_after = |name: &str| {
+        calls[3]
+            .iter()
+            .skip_while(|arg| *arg != name)
+            .nth(1)
+            .cloned()
+    };
+    // 125 seconds of input plays in 100 at 1.25x
+    assert_eq!(arg_after("-segment_times").unwrap(), "64.4,120");
+    assert_eq!(arg_after("-filter:a").unwrap(), "atempo=1.25");
+    assert_eq!(arg_after("-c"), None);
+    assert_eq!(atempo_filter(3.0), "atempo=2,atempo=1.5");
+}
+
+#[test]
+fn test_overlap_extracts_each_part_separately() {
+    let tools = FfmpegTools {
+        runner: FakeFfmpeg {
+            // Not an MP3, so it can't be split natively and has to go through ffmpeg
+            codec: "aac",
+            silencedetect_stderr: [
+                "[silencedetect @ 0x1] silence_start: 72",
+                "[silencedetect @ 0x1] silence_start: 80.5",
+                "[silencedetect @ 0x1] silence_start: 150",
+                "[silencedetect @ 0x1] silence_start: 230",
+            ]
+            .join("\n"),
+            segment_stderr: Vec::new(),
+            calls: Default::default(),
+        },
+        ffmpeg: "ffmpeg".into(),
+        ffprobe: Some("ffprobe".into()),
+    };
+    let output_folder = std::env::temp_dir().join("sync-and-swim-overlap-test");
+
+    tokio::runtime::Runtime::new()
+        .unwrap()
+        .block_on(segment_audio(
+            Some(&tools),
+            "book.m4a",
+            output_folder.to_str().unwrap(),
+            &SegmentOptions {
+                overlap: 10.0,
+                ..SegmentOptions {
+                    segment_time: 100,
+                    ..SegmentOptions::from_minutes(0, true, false)
+                }
+            },
+            0,
+            1,
+            &super::progress::NoProgress,
+        ))
+        .unwrap();
+
+    let calls = tools.runner.calls.lock().unwrap();
+    let ranges: Vec<(String, Option<String>)> = calls
+        .iter()
+        .filter(|args| args.iter().any(|a| a == "-ss"))
+        .map(|args| {
+            let after = |name: &str| args.iter().skip_while(|a| *a != name).nth(1).cloned();
+            (after("-ss").unwrap(), after("-to"))
+        })
+        .collect();
+    // The second part starts at the silence at 72 rather than exactly 10s early
+    assert_eq!(
+        ranges,
+        vec![
+            ("0".to_string(), Some("80.5".to_string())),
+            ("72".to_string(), Some("150".to_string())),
+            ("140".to_string(), None),
+        ]
+    );
+}
+
+#[cfg(test)]
+#[derive(Default)]
+struct RecordedBatch {
+    copies: std::sync::Mutex<Vec<(bool, usize, usize)>>,
+    segments: std::sync::Mutex<Vec<(String, usize)>>,
+}
+
+#[cfg(test)]
+impl ProgressSink for RecordedBatch {
+    fn copy_progress(&self, progress: CopyProgress) {
+        let entry = (progress.completed, progress.index, progress.finished);
+        self.copies.lock().unwrap().push(entry);
+    }
+
+    fn segment_progress(&self, progress: SegmentProgress) {
+        let entry = (progress.file_name, progress.index);
+        self.segments.lock().unwrap().push(entry);
+    }
+}
+
+#[test]
+fn test_split_files_runs_several_at_once_and_counts_finished_files() {
+    let tools = FfmpegTools {
+        runner: FakeFfmpeg {
+            codec: "aac",
+            silencedetect_stderr: "[silencedetect @ 0x1] silence_start: 80.5".to_string(),
+            segment_stderr: Vec::new(),
+            calls: Default::default(),
+        },
+        ffmpeg: "ffmpeg".into(),
+        ffprobe: Some("ffprobe".into()),
+    };
+    let output_folder = std::env::temp_dir().join("sync-and-swim-split-files-test");
+    let files: Vec<AudioFile> = ["one.m4a", "two.m4a", "three.m4a"]
+        .iter()
+        .map(|name| AudioFile {
+            name: name.to_string(),
+            path: name.to_string(),
+            relative_path: name.to_string(),
+            media: None,
+        })
+        .collect();
+    let progress = RecordedBatch::default();
+
+    tokio::runtime::Runtime::new()
+        .unwrap()
+        .block_on(split_files(
+            Some(&tools),
+            &files,
+            output_folder.to_str().unwrap(),
+            &SegmentOptions {
+                segment_time: 100,
+                ..SegmentOptions::from_minutes(0, true, false)
+            },
+            2,
+            &progress,
+        ))
+        .unwrap();
+
+    let calls = tools.runner.calls.lock().unwrap();
+    for stem in ["one", "two", "three"] {
+        let suffix = format!("{}_part_%04d.m4a", stem);
+        assert!(calls
+            .iter()
+            .any(|args| args.last().unwrap().ends_with(&suffix)));
+    }
+    let copies = progress.copies.lock().unwrap();
+    let completed: Vec<(usize, usize)> = copies
+        .iter()
+        .filter(|(completed, _, _)| *completed)
+        .map(|(_, index, finished)| (*index, *finished))
+        .collect();
+    let finished: Vec<usize> = completed.iter().map(|(_, finished)| *finished).collect();
+    assert_eq!(finished, vec![1, 2, 3]);
+    let mut indices: Vec<usize> = completed.iter().map(|(index, _)| *index).collect();
+    indices.sort();
+    assert_eq!(indices, vec![0, 1, 2]);
+    // Segment progress keeps each file's own place in the batch
+    let segments = progress.segments.lock().unwrap();
+    assert!(segments.contains(&("three.m4a".to_string(), 2)));
+    assert!(!segments.contains(&("three.m4a".to_string(), 0)));
+}
@@ -0,0 +1,229 @@
+//! A record of what's on the headphones, in the order it will play: an M3U8 playlist and a
+//! plain-text report with per-folder track lists, durations and sizes.
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use super::audio_segment::audio_file_duration;
+use super::files::{deep_list_files, AudioFile};
+use super::find_ffmpeg::FfmpegTools;
+use super::media_info::native_duration_in_background;
+use super::process::ProcessRunner;
+
+#[derive(Debug, Serialize, Clone)]
+pub struct DeviceTrack {
+    pub file: AudioFile,
+    /// Bytes
+    pub size: u64,
+    /// Seconds, when ffprobe, ffmpeg or the app itself could read the file
+    pub duration: Option<f64>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct DeviceReport {
+    pub tracks: Vec<DeviceTrack>,
+    pub total_size: u64,
+    /// Seconds, counting only tracks whose duration is known
+    pub total_duration: f64,
+}
+
+impl DeviceReport {
+    pub fn new(tracks: Vec<DeviceTrack>) -> Self {
+        DeviceReport {
+            total_size: tracks.iter().map(|t| t.size).sum(),
+            total_duration: tracks.iter().filter_map(|t| t.duration).sum(),
+            tracks,
+        }
+    }
+}
+
+/// Lists the tracks on the device in directory order, which is the order they'll play.
+/// Without ffmpeg, durations are read from the files directly, and left out for formats
+/// that can't be read that way.
+pub async fn inspect_device<R: ProcessRunner>(
+    dest_path: &str,
+    tools: Option<&FfmpegTools<R>>,
+) -> Result<DeviceReport> {
+    let files = deep_list_files(dest_path).map_err(anyhow::Error::msg)?;
+    let mut tracks = Vec::with_capacity(files.len());
+    for file in files {
+        let size = fs::metadata(&file.path).map(|m| m.len()).unwrap_or(0);
+        let duration = match tools {
+            Some(tools) => audio_file_duration(tools, &file.path).await.ok(),
+            None => native_duration_in_background(&file.path).await.ok(),
+        };
+        tracks.push(DeviceTrack {
+            file,
+            size,
+            duration,
+        });
+    }
+    Ok(DeviceReport::new(tracks))
+}
+
+fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
+
+fn format_size(bytes: u64) -> String {
+    const MB: f64 = 1024.0 * 1024.0;
+    if bytes as f64 >= 1024.0 * MB {
+        format!("{:.2} GB", bytes as f64 / (1024.0 * MB))
+    } else {
+        format!("{:.1} MB", bytes as f64 / MB)
+    }
+}
+
+/// Writes the tracks as an extended M3U playlist. Paths are relative to the playlist when
+/// it's saved on the device itself, so it stays valid wherever the device is mounted.
+pub fn render_m3u8(report: &DeviceReport, playlist_dir: &Path) -> String {
+    let mut out = String::from("#EXTM3U\n");
+    for track in &report.tracks {
+        let duration = track.duration.map(|d| d.round() as i64).unwrap_or(-1);
+        let path = Path::new(&track.file.path);
+        let location = match path.strip_prefix(playlist_dir) {
+            Ok(relative) => relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/"),
+            Err(_) => track.file.path.clone(),
+        };
+        let _ = writeln!(out, "#EXTINF:{},{}", duration, track.file.name);
+        let _ = writeln!(out, "{}", location);
+    }
+    out
+}
+
+/// A human-readable listing: one section per folder, each track with its duration, the
+/// running time up to the end of that track, and its size
+pub fn render_report(dest_path: &str, report: &DeviceReport) -> String {
+    let unknown = report
+        .tracks
+        .iter()
+        .filter(|t| t.duration.is_none())
+        .count();
+    let mut out = String::new();
+    let _ = writeln!(out, "Contents of {}", dest_path);
+    let _ = writeln!(
+        out,
+        "{} tracks, {}{}, {}",
+        report.tracks.len(),
+        format_duration(report.total_duration),
+        if unknown > 0 {
+            format!(" (plus {} of unknown length)", unknown)
+        } else {
+            String::new()
+        },
+        format_size(report.total_size)
+    );
+
+    let mut folder: Option<&str> = None;
+    let mut cumulative = 0.0;
+    for (index, track) in report.tracks.iter().enumerate() {
+        if folder != Some(track.file.relative_path.as_str()) {
+            folder = Some(&track.file.relative_path);
+            let name = if track.file.relative_path.is_empty() {
+                "(top level)"
+            } else {
+                &track.file.relative_path
+            };
+            let _ = writeln!(out, "\n{}", name);
+        }
+        cumulative += track.duration.unwrap_or(0.0);
+        let _ = writeln!(
+            out,
+            "{:>5}. {}  {}  [{}]  {}",
+            index + 1,
+            track.file.name,
+            track
+                .duration
+                .map(format_duration)
+                .unwrap_or_else(|| "?".to_string()),
+            format_duration(cumulative),
+            format_size(track.size)
+        );
+    }
+    out
+}
+
+/// Inspects the device and writes the playlist and report
+pub async fn export_device_contents<R: ProcessRunner>(
+    dest_path: &str,
+    tools: Option<&FfmpegTools<R>>,
+    playlist_path: &Path,
+    report_path: &Path,
+) -> Result<DeviceReport> {
+    let mut report = inspect_device(dest_path, tools).await?;
+    // A playlist or report saved on the device by an earlier export isn't a track
+    let outputs: Vec<_> = [playlist_path, report_path]
+        .iter()
+        .filter_map(|p| fs::canonicalize(p).ok())
+        .collect();
+    report
+        .tracks
+        .retain(|t| fs::canonicalize(&t.file.path).map_or(true, |path| !outputs.contains(&path)));
+    let report = DeviceReport::new(report.tracks);
+    let playlist_dir = playlist_path.parent().unwrap_or(Path::new(""));
+    fs::write(playlist_path, render_m3u8(&report, playlist_dir))
+        .with_context(|| format!("Failed to write {}", playlist_path.display()))?;
+    fs::write(report_path, render_report(dest_path, &report))
+        .with_context(|| format!("Failed to write {}", report_path.display()))?;
+    Ok(report)
+}
+
+#[test]
+fn test_render_playlist_and_report() {
+    let track = |folder: &str, name: &str, duration: Option<f64>| DeviceTrack {
+        file: AudioFile {
+            name: name.to_string(),
+            path: Path::new("/Volumes/OpenSwim")
+                .join(folder)
+                .join(name)
+                .to_string_lossy()
+                .to_string(),
+            relative_path: folder.to_string(),
+            media: None,
+        },
+        size: 3 * 1024 * 1024,
+        duration,
+    };
+    let tracks = vec![
+        track("Book", "part 1.mp3", Some(1800.4)),
+        track("Book", "part 2.mp3", Some(1900.0)),
+        track("Music", "song.mp3", None),
+    ];
+    let report = DeviceReport::new(tracks);
+
+    let playlist = render_m3u8(&report, Path::new("/Volumes/OpenSwim"));
+    assert_eq!(
+        playlist,
+        "#EXTM3U\n\
+         #EXTINF:1800,part 1.mp3\nBook/part 1.mp3\n\
+         #EXTINF:1900,part 2.mp3\nBook/part 2.mp3\n\
+         #EXTINF:-1,song.mp3\nMusic/song.mp3\n"
+    );
+
+    let text = render_report("/Volumes/OpenSwim", &report);
+    assert_eq!(
+        text,
+        "Contents of /Volumes/OpenSwim\n\
+         3 tracks, 1:01:40 (plus 1 of unknown length), 9.0 MB\n\
+         \n\
+         Book\n\
+         \x20   1. part 1.mp3  30:00  [30:00]  3.0 MB\n\
+         \x20   2. part 2.mp3  31:40  [1:01:40]  3.0 MB\n\
+         \n\
+         Music\n\
+         \x20   3. song.mp3  ?  [1:01:40]  3.0 MB\n"
+    );
+}
@@ -0,0 +1,360 @@
+//! Loads prepared inside a FAT32 image file rather than on mounted headphones.
+//!
+//! The headphones play files in the order their directory entries were written. Writing into
+//! an image we control gives the same order without any timestamp delays, and lets a load be
+//! built (and checked) without the device plugged in. The finished image's contents are then
+//! copied onto the real device with [`copy_image_to_device`].
+
+use anyhow::{Context, Result};
+use fatfs::{Dir, FatType, FileSystem, FormatVolumeOptions, FsOptions};
+use fscommon::BufStream;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc::Receiver;
+
+use super::files::{
+    prepare_destination, AudioFile, CopyMessage, CopyProgress, CopyReport, DestinationResult,
+};
+use super::history::to_hex;
+use super::progress::ProgressSink;
+
+/// Size of images created on demand, matching a 4 GB OpenSwim
+pub const DEFAULT_IMAGE_SIZE_MB: u64 = 3800;
+
+const VOLUME_LABEL: [u8; 11] = *b"OPENSWIM   ";
+
+type ImageFs = FileSystem<BufStream<File>>;
+
+/// Whether a destination path names a disk image rather than a folder
+pub fn is_disk_image(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("img"))
+}
+
+/// Creates an empty FAT32 image. The file is sparse, so it only takes up as much disk
+/// space as the files later written into it.
+pub fn create_disk_image(path: &Path, size_mb: u64) -> Result<()> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+    file.set_len(size_mb * 1024 * 1024)?;
+    fatfs::format_volume(
+        BufStream::new(file),
+        FormatVolumeOptions::new()
+            .fat_type(FatType::Fat32)
+            .volume_label(VOLUME_LABEL),
+    )
+    .context("Failed to format disk image")
+}
+
+fn open_image(path: &Path) -> Result<ImageFs> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    FileSystem::new(BufStream::new(file), FsOptions::new()).context("Not a FAT disk image")
+}
+
+/// Free space inside an image. One that doesn't exist yet will be created at the default size.
+pub fn free_space(path: &Path) -> Result<u64> {
+    if !path.exists() {
+        return Ok(DEFAULT_IMAGE_SIZE_MB * 1024 * 1024);
+    }
+    let stats = open_image(path)?.stats()?;
+    Ok(stats.free_clusters() as u64 * stats.cluster_size() as u64)
+}
+
+/// Converts a relative folder path to the '/'-separated form used inside the image
+fn image_path(relative_path: &str) -> Vec<String> {
+    Path::new(relative_path)
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(part) => Some(part.to_string_lossy().to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn create_dirs<'a>(
+    root: Dir<'a, BufStream<File>>,
+    parts: &[String],
+) -> io::Result<Dir<'a, BufStream<File>>> {
+    let mut dir = root;
+    for part in parts {
+        dir = dir.create_dir(part)?;
+    }
+    Ok(dir)
+}
+
+/// Removes everything inside `dir`
+fn clear_dir(dir: &Dir<BufStream<File>>) -> io::Result<()> {
+    let entries: Vec<_> = dir
+        .iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_name() != "." && e.file_name() != "..")
+        .collect();
+    for entry in entries {
+        if entry.is_dir() {
+            clear_dir(&entry.to_dir())?;
+        }
+        dir.remove(&entry.file_name())?;
+    }
+    Ok(())
+}
+
+/// Writes the files sent by the reader into the image at `dest_path`, creating it if needed
+pub(super) fn write_image(
+    dest_path: &str,
+    mode: &str,
+    total: usize,
+    messages: Receiver<CopyMessage>,
+    progress: &impl ProgressSink,
+) -> Result<(), String> {
+    let path = Path::new(dest_path);
+    if !path.exists() {
+        create_disk_image(path, DEFAULT_IMAGE_SIZE_MB).map_err(|e| e.to_string())?;
+    }
+    let image = open_image(path).map_err(|e| e.to_string())?;
+    let root = image.root_dir();
+    if mode == "replace" {
+        clear_dir(&root).map_err(|e| e.to_string())?;
+    }
+
+    let mut current = None;
+    for message in messages {
+        match message {
+            CopyMessage::Start(index, file) => {
+                progress.copy_progress(CopyProgress {
+                    file_name: file.name.clone(),
+                    dest_path: dest_path.to_string(),
+                    completed: false,
+                    index,
+                    finished: index,
+                    total,
+                });
+                let dir = create_dirs(root.clone(), &image_path(&file.relative_path))
+                    .map_err(|e| format!("Failed to copy {}: {}", file.name, e))?;
+                let mut out = dir
+                    .create_file(&file.name)
+                    .map_err(|e| format!("Failed to copy {}: {}", file.name, e))?;
+                out.truncate()
+                    .map_err(|e| format!("Failed to copy {}: {}", file.name, e))?;
+                current = Some((index, file, dir, out));
+            }
+            CopyMessage::Data(chunk) => {
+                if let Some((_, file, _, out)) = current.as_mut() {
+                    out.write_all(&chunk)
+                        .map_err(|e| format!("Failed to copy {}: {}", file.name, e))?;
+                }
+            }
+            CopyMessage::End => {
+                let Some((index, file, _, mut out)) = current.take() else {
+                    continue;
+                };
+                out.flush()
+                    .map_err(|e| format!("Failed to copy {}: {}", file.name, e))?;
+                progress.copy_progress(CopyProgress {
+                    file_name: file.name,
+                    dest_path: dest_path.to_string(),
+                    completed: true,
+                    index,
+                    finished: index + 1,
+                    total,
+                });
+            }
+            CopyMessage::Abort => {
+                if let Some((_, file, dir, out)) = current.take() {
+                    drop(out);
+                    dir.remove(&file.name)
+                        .map_err(|e| format!("Failed to remove partial {}: {}", file.name, e))?;
+                }
+            }
+        }
+    }
+    drop(current);
+    drop(root);
+    image
+        .unmount()
+        .map_err(|e| format!("Failed to finish writing {}: {}", dest_path, e))
+}
+
+fn visit_image_dir(
+    dir: &Dir<BufStream<File>>,
+    relative_path: &Path,
+    files: &mut Vec<AudioFile>,
+) -> io::Result<()> {
+    for entry in dir.iter() {
+        let entry = entry?;
+        let name = entry.file_name();
+        if name == "." || name == ".." {
+            continue;
+        }
+        if entry.is_dir() {
+            visit_image_dir(&entry.to_dir(), &relative_path.join(&name), files)?;
+        } else {
+            let relative = relative_path.to_string_lossy().to_string();
+            let mut path = image_path(&relative);
+            path.push(name.clone());
+            files.push(AudioFile {
+                name,
+                path: path.join("/"),
+                relative_path: relative,
+                media: None,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Lists the files in an image in play order. Each `path` is the '/'-separated path inside
+/// the image.
+pub fn list_disk_image(image_path: &str) -> Result<Vec<AudioFile>, String> {
+    let image = open_image(Path::new(image_path)).map_err(|e| e.to_string())?;
+    let mut files = Vec::new();
+    visit_image_dir(&image.root_dir(), Path::new(""), &mut files).map_err(|e| e.to_string())?;
+    Ok(files)
+}
+
+/// Copies everything in an image onto a mounted device, one file at a time in the image's
+/// play order, so the device plays them in the same order. Each file is hashed as it's
+/// read out of the image.
+pub fn copy_image_to_device(
+    image_path: &str,
+    dest_path: &str,
+    mode: &str,
+    progress: &impl ProgressSink,
+) -> CopyReport {
+    let mut sha256 = Vec::new();
+    let error = copy_image_files(image_path, dest_path, mode, &mut sha256, progress).err();
+    CopyReport {
+        destinations: vec![DestinationResult {
+            dest_path: dest_path.to_string(),
+            error,
+        }],
+        sha256,
+        source_error: None,
+    }
+}
+
+fn copy_image_files(
+    image_path: &str,
+    dest_path: &str,
+    mode: &str,
+    sha256: &mut Vec<Option<String>>,
+    progress: &impl ProgressSink,
+) -> Result<(), String> {
+    let files = list_disk_image(image_path)?;
+    let image = open_image(Path::new(image_path)).map_err(|e| e.to_string())?;
+    let root = image.root_dir();
+
+    prepare_destination(dest_path, mode)?;
+
+    let total = files.len();
+    for (index, file) in files.into_iter().enumerate() {
+        progress.copy_progress(CopyProgress {
+            file_name: file.name.clone(),
+            dest_path: dest_path.to_string(),
+            completed: false,
+            index,
+            finished: index,
+            total,
+        });
+
+        let mut dest_file = PathBuf::from(dest_path);
+        if !file.relative_path.is_empty() {
+            dest_file = dest_file.join(&file.relative_path);
+            fs::create_dir_all(&dest_file).map_err(|e| e.to_string())?;
+        }
+        dest_file = dest_file.join(&file.name);
+
+        let mut source = root
+            .open_file(&file.path)
+            .map_err(|e| format!("Failed to copy {}: {}", file.name, e))?;
+        let mut out =
+            File::create(&dest_file).map_err(|e| format!("Failed to copy {}: {}", file.name, e))?;
+        let hash = copy_hashed(&mut source, &mut out)
+            .map_err(|e| format!("Failed to copy {}: {}", file.name, e))?;
+        sha256.push(Some(hash));
+
+        // Small delay to ensure files have distinct timestamps
+        std::thread::sleep(std::time::Duration::from_millis(100));
+
+        progress.copy_progress(CopyProgress {
+            file_name: file.name,
+            dest_path: dest_path.to_string(),
+            completed: true,
+            index,
+            finished: index + 1,
+            total,
+        });
+    }
+    Ok(())
+}
+
+/// Copies `source` into `out` and makes sure it arrived, returning the contents' SHA-256
+fn copy_hashed(source: &mut impl Read, out: &mut File) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        match source.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => {
+                hasher.update(&buffer[..n]);
+                out.write_all(&buffer[..n])?;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    out.sync_all()?;
+    Ok(to_hex(&hasher.finalize()))
+}
+
+#[test]
+fn test_copy_into_disk_image_keeps_play_order() {
+    use super::files::copy_files;
+    use super::progress::NoProgress;
+
+    let root = std::env::temp_dir().join("sync-and-swim-disk-image-test");
+    let _ = fs::remove_dir_all(&root);
+    let source = root.join("source");
+    fs::create_dir_all(source.join("Book")).unwrap();
+    // Written out of order, so the image order has to come from the copy order
+    for name in ["03 three.mp3", "01 one.mp3", "02 two.mp3"] {
+        fs::write(source.join("Book").join(name), name).unwrap();
+    }
+    let image = root.join("load.img");
+    create_disk_image(&image, 64).unwrap();
+    let image = image.to_str().unwrap();
+
+    let files = super::files::list_audio_files(source.to_str().unwrap()).unwrap();
+    copy_files(files, image, "replace", &NoProgress).unwrap();
+
+    let names: Vec<_> = list_disk_image(image)
+        .unwrap()
+        .into_iter()
+        .map(|f| (f.relative_path, f.name))
+        .collect();
+    assert_eq!(
+        names,
+        vec![
+            ("Book".to_string(), "01 one.mp3".to_string()),
+            ("Book".to_string(), "02 two.mp3".to_string()),
+            ("Book".to_string(), "03 three.mp3".to_string()),
+        ]
+    );
+
+    let device = root.join("device");
+    let report = copy_image_to_device(image, device.to_str().unwrap(), "replace", &NoProgress);
+    assert!(report.destinations[0].error.is_none());
+    let first = device.join("Book").join("01 one.mp3");
+    assert_eq!(fs::read(&first).unwrap(), b"01 one.mp3");
+    assert_eq!(report.sha256[0], super::history::hash_file(&first).ok());
+}
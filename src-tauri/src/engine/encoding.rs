@@ -0,0 +1,333 @@
+//! Re-encoding presets that trade quality for space, and predictions of how much of a
+//! selection will fit on the headphones with each.
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::audio_segment::audio_file_duration;
+use super::disk_image;
+use super::files::AudioFile;
+use super::find_ffmpeg::FfmpegTools;
+use super::playlist::unique_name;
+use super::process::ProcessRunner;
+use super::progress::ProgressSink;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum EncodingPreset {
+    #[default]
+    KeepOriginal,
+    SpokenWordMono48k,
+    SpokenWordMono64k,
+    Music192k,
+}
+
+/// A preset as shown to the user
+#[derive(Debug, Serialize, Clone)]
+pub struct PresetInfo {
+    pub preset: EncodingPreset,
+    pub label: &'static str,
+    /// Estimated size of an hour of audio. Unknown for "keep original", which depends on
+    /// the files.
+    pub bytes_per_hour: Option<u64>,
+}
+
+impl EncodingPreset {
+    pub const ALL: [EncodingPreset; 4] = [
+        EncodingPreset::KeepOriginal,
+        EncodingPreset::SpokenWordMono48k,
+        EncodingPreset::SpokenWordMono64k,
+        EncodingPreset::Music192k,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            EncodingPreset::KeepOriginal => "Keep original",
+            EncodingPreset::SpokenWordMono48k => "Spoken word, mono 48 kbps",
+            EncodingPreset::SpokenWordMono64k => "Spoken word, mono 64 kbps",
+            EncodingPreset::Music192k => "Music, stereo 192 kbps",
+        }
+    }
+
+    fn bitrate_kbps(self) -> Option<u64> {
+        match self {
+            EncodingPreset::KeepOriginal => None,
+            EncodingPreset::SpokenWordMono48k => Some(48),
+            EncodingPreset::SpokenWordMono64k => Some(64),
+            EncodingPreset::Music192k => Some(192),
+        }
+    }
+
+    fn channels(self) -> Option<u32> {
+        match self {
+            EncodingPreset::SpokenWordMono48k | EncodingPreset::SpokenWordMono64k => Some(1),
+            EncodingPreset::KeepOriginal | EncodingPreset::Music192k => None,
+        }
+    }
+
+    /// Constant bitrate MP3, so the size follows directly from the bitrate
+    pub fn bytes_per_hour(self) -> Option<u64> {
+        self.bitrate_kbps().map(|kbps| kbps * 1000 / 8 * 3600)
+    }
+
+    pub fn info(self) -> PresetInfo {
+        PresetInfo {
+            preset: self,
+            label: self.label(),
+            bytes_per_hour: self.bytes_per_hour(),
+        }
+    }
+
+    /// Output codec arguments for ffmpeg, or None when the audio should be copied as is
+    pub fn codec_args(self) -> Option<Vec<String>> {
+        let kbps = self.bitrate_kbps()?;
+        let mut args = vec!["-vn".to_string()];
+        if let Some(channels) = self.channels() {
+            args.extend(["-ac".to_string(), channels.to_string()]);
+        }
+        args.extend([
+            "-c:a".to_string(),
+            "libmp3lame".to_string(),
+            "-b:a".to_string(),
+            format!("{}k", kbps),
+        ]);
+        Some(args)
+    }
+}
+
+/// Progress re-encoding files, before they're copied anywhere
+#[derive(Debug, Serialize, Clone)]
+pub struct EncodeProgress {
+    pub file_name: String,
+    pub completed: bool,
+    /// The file's place in the list
+    pub index: usize,
+    /// How many files are done, counting this one once it's completed
+    pub finished: usize,
+    pub total: usize,
+}
+
+/// Re-encodes each file into `work_dir` with the preset, returning the files to copy in
+/// their place. Names keep their stem and get an `.mp3` extension, with " (2)" and so on
+/// added when files in one folder differ only by extension.
+pub async fn encode_files<R: ProcessRunner>(
+    tools: &FfmpegTools<R>,
+    files: Vec<AudioFile>,
+    preset: EncodingPreset,
+    work_dir: &Path,
+    progress: &impl ProgressSink,
+) -> Result<Vec<AudioFile>> {
+    let Some(codec_args) = preset.codec_args() else {
+        return Ok(files);
+    };
+    let total = files.len();
+    let mut encoded = Vec::with_capacity(total);
+    let mut used_names: HashMap<String, HashSet<String>> = HashMap::new();
+    for (index, file) in files.into_iter().enumerate() {
+        progress.encode_progress(EncodeProgress {
+            file_name: file.name.clone(),
+            completed: false,
+            index,
+            finished: index,
+            total,
+        });
+        let name = unique_name(
+            &Path::new(&file.name)
+                .with_extension("mp3")
+                .to_string_lossy(),
+            used_names.entry(file.relative_path.clone()).or_default(),
+        );
+        let folder: PathBuf = work_dir.join(&file.relative_path);
+        fs::create_dir_all(&folder)?;
+        let output_path = folder.join(&name);
+
+        let mut args = vec!["-y".to_string(), "-i".to_string(), file.path.clone()];
+        args.extend(codec_args.iter().cloned());
+        args.push(output_path.to_string_lossy().to_string());
+        let output = tools
+            .runner
+            .output(&tools.ffmpeg, &args)
+            .await
+            .context("Failed to execute ffmpeg")?;
+        if !output.success {
+            return Err(anyhow!("Failed to re-encode {}", file.name));
+        }
+
+        progress.encode_progress(EncodeProgress {
+            file_name: file.name.clone(),
+            completed: true,
+            index,
+            finished: index + 1,
+            total,
+        });
+        encoded.push(AudioFile {
+            name,
+            path: output_path.to_string_lossy().to_string(),
+            relative_path: file.relative_path,
+            media: None,
+        });
+    }
+    Ok(encoded)
+}
+
+/// How a selection would fit on a destination with a preset
+#[derive(Debug, Serialize, Clone)]
+pub struct FitEstimate {
+    pub preset: EncodingPreset,
+    /// Playing time of the selection
+    pub selection_hours: f64,
+    /// Estimated size of the selection after encoding
+    pub estimated_bytes: u64,
+    pub available_bytes: u64,
+    /// How many hours of audio like this selection would fit in the free space
+    pub hours_that_fit: f64,
+    pub fits: bool,
+}
+
+/// Space left on a destination folder or disk image
+pub fn available_space(dest_path: &str) -> Result<u64> {
+    if disk_image::is_disk_image(dest_path) {
+        return disk_image::free_space(Path::new(dest_path));
+    }
+    fs2::available_space(dest_path)
+        .with_context(|| format!("Failed to read free space on {}", dest_path))
+}
+
+fn fit_estimate(
+    preset: EncodingPreset,
+    seconds: f64,
+    original_bytes: u64,
+    available_bytes: u64,
+) -> FitEstimate {
+    let estimated_bytes = match preset.bytes_per_hour() {
+        Some(per_hour) => (per_hour as f64 * seconds / 3600.0) as u64,
+        None => original_bytes,
+    };
+    let selection_hours = seconds / 3600.0;
+    let hours_that_fit = if estimated_bytes == 0 {
+        0.0
+    } else {
+        available_bytes as f64 / (estimated_bytes as f64 / selection_hours)
+    };
+    FitEstimate {
+        preset,
+        selection_hours,
+        estimated_bytes,
+        available_bytes,
+        hours_that_fit,
+        fits: estimated_bytes <= available_bytes,
+    }
+}
+
+/// Predicts, for every preset, the size of `files` and how many hours would fit on the
+/// destination. Measures each file's length with ffprobe.
+pub async fn estimate_fit<R: ProcessRunner>(
+    tools: &FfmpegTools<R>,
+    files: &[AudioFile],
+    dest_path: &str,
+) -> Result<Vec<FitEstimate>> {
+    let mut seconds = 0.0;
+    let mut original_bytes = 0;
+    for file in files {
+        seconds += audio_file_duration(tools, &file.path)
+            .await
+            .with_context(|| format!("Failed to read the length of {}", file.name))?;
+        original_bytes += fs::metadata(&file.path).map(|m| m.len()).unwrap_or(0);
+    }
+    let available_bytes = available_space(dest_path)?;
+    Ok(EncodingPreset::ALL
+        .iter()
+        .map(|preset| fit_estimate(*preset, seconds, original_bytes, available_bytes))
+        .collect())
+}
+
+#[test]
+fn test_fit_estimate_uses_preset_bitrate() {
+    // 10 hours of audio, originally 128 kbps stereo (576 MB), with 1 GB free
+    let seconds = 10.0 * 3600.0;
+    let original = 128 * 1000 / 8 * 36000;
+    let available = 1_000_000_000;
+
+    let mono = fit_estimate(
+        EncodingPreset::SpokenWordMono48k,
+        seconds,
+        original,
+        available,
+    );
+    assert_eq!(mono.estimated_bytes, 216_000_000);
+    assert!(mono.fits);
+    assert!((mono.hours_that_fit - 46.3).abs() < 0.1);
+
+    let keep = fit_estimate(EncodingPreset::KeepOriginal, seconds, original, available);
+    assert_eq!(keep.estimated_bytes, original);
+    assert!((keep.hours_that_fit - 17.36).abs() < 0.01);
+
+    assert_eq!(
+        EncodingPreset::SpokenWordMono48k.codec_args().unwrap(),
+        vec!["-vn", "-ac", "1", "-c:a", "libmp3lame", "-b:a", "48k"]
+    );
+    assert_eq!(EncodingPreset::KeepOriginal.codec_args(), None);
+}
+
+#[test]
+fn test_encode_files_keeps_names_apart() {
+    struct FakeFfmpeg;
+    impl ProcessRunner for FakeFfmpeg {
+        async fn output(
+            &self,
+            _program: &Path,
+            _args: &[String],
+        ) -> Result<super::process::ProcessOutput> {
+            Ok(super::process::ProcessOutput {
+                success: true,
+                ..Default::default()
+            })
+        }
+
+        async fn run_with_stderr(
+            &self,
+            _program: &Path,
+            _args: &[String],
+            _on_line: &mut (dyn FnMut(&str) + Send),
+        ) -> Result<bool> {
+            Ok(true)
+        }
+    }
+
+    let tools = FfmpegTools {
+        runner: FakeFfmpeg,
+        ffmpeg: PathBuf::from("ffmpeg"),
+        ffprobe: None,
+    };
+    let file = |name: &str, relative_path: &str| AudioFile {
+        name: name.to_string(),
+        path: format!("/source/{}/{}", relative_path, name),
+        relative_path: relative_path.to_string(),
+        media: None,
+    };
+    let files = vec![
+        file("talk.m4a", "show"),
+        file("talk.mp3", "show"),
+        file("talk.m4a", "other show"),
+    ];
+    let work_dir = std::env::temp_dir().join("sync-and-swim-encode-names-test");
+
+    let encoded = tokio::runtime::Runtime::new()
+        .unwrap()
+        .block_on(encode_files(
+            &tools,
+            files,
+            EncodingPreset::SpokenWordMono48k,
+            &work_dir,
+            &super::progress::NoProgress,
+        ))
+        .unwrap();
+    let _ = fs::remove_dir_all(&work_dir);
+
+    let names: Vec<&str> = encoded.iter().map(|f| f.name.as_str()).collect();
+    assert_eq!(names, vec!["talk.mp3", "talk (2).mp3", "talk.mp3"]);
+    assert_ne!(encoded[0].path, encoded[1].path);
+}
@@ -0,0 +1,484 @@
+//! Podcast feeds: parsing RSS/Atom feeds and OPML subscription lists, and downloading new
+//! episodes into a staging folder that [`list_audio_files`](super::files::list_audio_files)
+//! picks up.
+//!
+//! Each feed gets its own folder in the staging area. Episodes are named
+//! `YYYY-MM-DD Title.mp3` after their publish date, so sorting by name plays them in the
+//! order they came out.
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, NaiveDate};
+use roxmltree::{Document, Node, ParsingOptions};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use super::files::safe_file_name;
+use super::progress::ProgressSink;
+
+const ATOM_NS: &str = "http://www.w3.org/2005/Atom";
+
+/// A feed to keep downloading from
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct FeedSubscription {
+    pub url: String,
+    /// Folder name in the staging area. Defaults to the feed's own title.
+    pub title: Option<String>,
+    /// How many of the newest episodes to keep in staging. Keeps everything when unset.
+    pub keep: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct Episode {
+    pub title: String,
+    pub url: String,
+    /// Seconds since the Unix epoch
+    pub published: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct Feed {
+    pub title: String,
+    /// Newest first
+    pub episodes: Vec<Episode>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct FeedProgress {
+    pub feed_title: String,
+    pub episode_title: String,
+    pub completed: bool,
+    pub index: usize,
+    pub total: usize,
+}
+
+/// What refreshing one feed did to the staging area
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct FeedRefresh {
+    pub url: String,
+    pub title: String,
+    pub downloaded: Vec<String>,
+    pub removed: Vec<String>,
+    pub error: Option<String>,
+}
+
+fn parse_xml(xml: &str) -> Result<Document<'_>> {
+    Document::parse_with_options(
+        xml,
+        ParsingOptions {
+            allow_dtd: true,
+            ..Default::default()
+        },
+    )
+    .context("Failed to parse XML")
+}
+
+/// Finds a child element by name, ignoring extension namespaces like `itunes:title`
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|c| {
+        c.is_element()
+            && c.tag_name().name() == name
+            && c.tag_name().namespace().is_none_or(|ns| ns == ATOM_NS)
+    })
+}
+
+fn child_text(node: Node, name: &str) -> Option<String> {
+    child(node, name)
+        .and_then(|c| c.text())
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty())
+}
+
+/// Parses an RSS or Atom feed, keeping only entries with an audio enclosure
+pub fn parse_feed(xml: &str) -> Result<Feed> {
+    let doc = parse_xml(xml)?;
+    let root = doc.root_element();
+    let (title, mut episodes): (Option<String>, Vec<Episode>) = match root.tag_name().name() {
+        "rss" => {
+            let channel = child(root, "channel").context("RSS feed has no channel")?;
+            let episodes = channel
+                .children()
+                .filter(|c| c.has_tag_name("item"))
+                .filter_map(|item| {
+                    let url = child(item, "enclosure")?.attribute("url")?.to_string();
+                    Some(Episode {
+                        title: child_text(item, "title").unwrap_or_else(|| url.clone()),
+                        url,
+                        published: child_text(item, "pubDate")
+                            .and_then(|d| DateTime::parse_from_rfc2822(&d).ok())
+                            .map(|d| d.timestamp()),
+                    })
+                })
+                .collect();
+            (child_text(channel, "title"), episodes)
+        }
+        "feed" => {
+            let episodes = root
+                .children()
+                .filter(|c| c.has_tag_name((ATOM_NS, "entry")))
+                .filter_map(|entry| {
+                    let url = entry
+                        .children()
+                        .find(|c| {
+                            c.has_tag_name((ATOM_NS, "link"))
+                                && c.attribute("rel") == Some("enclosure")
+                        })?
+                        .attribute("href")?
+                        .to_string();
+                    Some(Episode {
+                        title: child_text(entry, "title").unwrap_or_else(|| url.clone()),
+                        url,
+                        published: child_text(entry, "published")
+                            .or_else(|| child_text(entry, "updated"))
+                            .and_then(|d| DateTime::parse_from_rfc3339(&d).ok())
+                            .map(|d| d.timestamp()),
+                    })
+                })
+                .collect();
+            (child_text(root, "title"), episodes)
+        }
+        other => return Err(anyhow!("Not an RSS or Atom feed (root element {})", other)),
+    };
+    // Undated episodes keep their feed order, after the dated ones
+    episodes.sort_by_key(|e| std::cmp::Reverse(e.published.unwrap_or(i64::MIN)));
+    Ok(Feed {
+        title: title.unwrap_or_else(|| "Untitled feed".to_string()),
+        episodes,
+    })
+}
+
+/// Reads the feeds out of an OPML subscription list, as exported by most podcast apps
+pub fn parse_opml(xml: &str) -> Result<Vec<FeedSubscription>> {
+    let doc = parse_xml(xml)?;
+    if !doc.root_element().has_tag_name("opml") {
+        return Err(anyhow!("Not an OPML file"));
+    }
+    Ok(doc
+        .descendants()
+        .filter(|n| n.has_tag_name("outline"))
+        .filter_map(|outline| {
+            Some(FeedSubscription {
+                url: outline.attribute("xmlUrl")?.to_string(),
+                title: outline
+                    .attribute("title")
+                    .or_else(|| outline.attribute("text"))
+                    .map(str::to_string),
+                keep: None,
+            })
+        })
+        .collect())
+}
+
+/// The file name an episode is staged under, e.g. `2024-03-01 Episode title.mp3`
+pub fn episode_file_name(episode: &Episode) -> String {
+    let date = episode
+        .published
+        .and_then(|t| DateTime::from_timestamp(t, 0))
+        .map(|d| d.format("%Y-%m-%d").to_string())
+        .unwrap_or_else(|| "undated".to_string());
+    let path = episode.url.split(['?', '#']).next().unwrap_or_default();
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .filter(|e| e.len() <= 4 && e.chars().all(|c| c.is_ascii_alphanumeric()))
+        .unwrap_or("mp3");
+    format!(
+        "{} {}.{}",
+        date,
+        safe_file_name(&episode.title),
+        extension.to_ascii_lowercase()
+    )
+}
+
+fn agent() -> ureq::Agent {
+    ureq::AgentBuilder::new()
+        .timeout_connect(Duration::from_secs(15))
+        .timeout_read(Duration::from_secs(60))
+        .build()
+}
+
+pub fn fetch_feed(url: &str) -> Result<Feed> {
+    let body = agent()
+        .get(url)
+        .call()
+        .with_context(|| format!("Failed to fetch {}", url))?
+        .into_string()
+        .with_context(|| format!("Failed to read {}", url))?;
+    parse_feed(&body)
+}
+
+/// Downloads to a temporary name first, so an interrupted download isn't mistaken for an
+/// episode that's already staged
+fn download(agent: &ureq::Agent, url: &str, dest: &Path) -> Result<()> {
+    let partial = dest.with_extension("part");
+    let result = (|| -> Result<()> {
+        let response = agent.get(url).call()?;
+        let mut out = File::create(&partial)?;
+        io::copy(&mut response.into_reader(), &mut out)?;
+        out.sync_all()?;
+        fs::rename(&partial, dest)?;
+        Ok(())
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&partial);
+    }
+    result.with_context(|| format!("Failed to download {}", url))
+}
+
+/// Where a staged file falls in its feed's age order, or None if it isn't an episode. Undated
+/// episodes count as older than any dated one, as in the feed's own order, and among
+/// themselves go by when they were staged.
+fn episode_age(folder: &Path, name: &str) -> Option<(Option<NaiveDate>, SystemTime)> {
+    let (prefix, _) = name.split_once(' ')?;
+    let date = match prefix {
+        "undated" => None,
+        date => Some(NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?),
+    };
+    // Left behind by an interrupted download
+    if name.ends_with(".part") {
+        return None;
+    }
+    let metadata = fs::metadata(folder.join(name)).ok()?;
+    if !metadata.is_file() {
+        return None;
+    }
+    Some((date, metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH)))
+}
+
+/// Deletes all but the `keep` newest episodes in a feed's folder. Files that aren't named
+/// like episodes are left alone.
+fn apply_retention(folder: &Path, keep: usize) -> Result<Vec<String>> {
+    let mut episodes: Vec<_> = fs::read_dir(folder)?
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.file_name().to_str().map(str::to_string))
+        .filter_map(|name| Some((episode_age(folder, &name)?, name)))
+        .collect();
+    episodes.sort();
+    let excess = episodes.len().saturating_sub(keep);
+    let removed: Vec<String> = episodes
+        .into_iter()
+        .take(excess)
+        .map(|(_, name)| name)
+        .collect();
+    for name in &removed {
+        fs::remove_file(folder.join(name))?;
+    }
+    Ok(removed)
+}
+
+fn refresh_feed(
+    subscription: &FeedSubscription,
+    staging_path: &Path,
+    progress: &impl ProgressSink,
+    refresh: &mut FeedRefresh,
+) -> Result<()> {
+    let feed = fetch_feed(&subscription.url)?;
+    refresh.title = subscription.title.clone().unwrap_or(feed.title);
+    let folder: PathBuf = staging_path.join(safe_file_name(&refresh.title));
+    fs::create_dir_all(&folder)?;
+
+    let wanted = subscription.keep.unwrap_or(feed.episodes.len());
+    let new_episodes: Vec<(&Episode, PathBuf)> = feed
+        .episodes
+        .iter()
+        .take(wanted)
+        .map(|episode| (episode, folder.join(episode_file_name(episode))))
+        .filter(|(_, path)| !path.exists())
+        .collect();
+
+    let agent = agent();
+    let total = new_episodes.len();
+    // Oldest first, so a failure part way leaves the earlier episodes in place
+    for (index, (episode, path)) in new_episodes.into_iter().rev().enumerate() {
+        progress.feed_progress(FeedProgress {
+            feed_title: refresh.title.clone(),
+            episode_title: episode.title.clone(),
+            completed: false,
+            index,
+            total,
+        });
+        download(&agent, &episode.url, &path)?;
+        refresh
+            .downloaded
+            .push(path.file_name().unwrap().to_string_lossy().to_string());
+        progress.feed_progress(FeedProgress {
+            feed_title: refresh.title.clone(),
+            episode_title: episode.title.clone(),
+            completed: true,
+            index,
+            total,
+        });
+    }
+
+    if let Some(keep) = subscription.keep {
+        refresh.removed = apply_retention(&folder, keep)?;
+    }
+    Ok(())
+}
+
+/// Downloads new episodes of every feed into `staging_path`, then trims each feed's folder
+/// to its retention limit. A feed that fails doesn't stop the others.
+pub fn refresh_feeds(
+    subscriptions: &[FeedSubscription],
+    staging_path: &str,
+    progress: &impl ProgressSink,
+) -> Vec<FeedRefresh> {
+    subscriptions
+        .iter()
+        .map(|subscription| {
+            let mut refresh = FeedRefresh {
+                url: subscription.url.clone(),
+                title: subscription
+                    .title
+                    .clone()
+                    .unwrap_or_else(|| subscription.url.clone()),
+                ..Default::default()
+            };
+            if let Err(e) = refresh_feed(
+                subscription,
+                Path::new(staging_path),
+                progress,
+                &mut refresh,
+            ) {
+                refresh.error = Some(format!("{:#}", e));
+            }
+            refresh
+        })
+        .collect()
+}
+
+#[test]
+fn test_parse_rss_atom_and_opml() {
+    let rss = r#"<?xml version="1.0"?>
+        <rss version="2.0" xmlns:itunes="http://www.itunes.com/dtds/podcast-1.0.dtd">
+          <channel>
+            <title>Swim Talk</title>
+            <item>
+              <title>Older</title>
+              <itunes:title>Ignored</itunes:title>
+              <pubDate>Mon, 01 Jan 2024 08:00:00 GMT</pubDate>
+              <enclosure url="https://example.com/older.mp3?id=1" type="audio/mpeg"/>
+            </item>
+            <item><title>No audio</title></item>
+            <item>
+              <title>Newer: part 2</title>
+              <pubDate>Fri, 01 Mar 2024 08:00:00 +0000</pubDate>
+              <enclosure url="https://example.com/newer.M4A" type="audio/mp4"/>
+            </item>
+          </channel>
+        </rss>"#;
+    let feed = parse_feed(rss).unwrap();
+    assert_eq!(feed.title, "Swim Talk");
+    let names: Vec<_> = feed.episodes.iter().map(episode_file_name).collect();
+    assert_eq!(
+        names,
+        vec!["2024-03-01 Newer_ part 2.m4a", "2024-01-01 Older.mp3"]
+    );
+
+    let atom = r#"<feed xmlns="http://www.w3.org/2005/Atom">
+          <title>Atom Cast</title>
+          <entry>
+            <title>First</title>
+            <updated>2023-12-31T23:00:00-02:00</updated>
+            <link rel="alternate" href="https://example.com/first"/>
+            <link rel="enclosure" href="https://example.com/first.mp3"/>
+          </entry>
+        </feed>"#;
+    let feed = parse_feed(atom).unwrap();
+    assert_eq!(feed.title, "Atom Cast");
+    assert_eq!(feed.episodes[0].url, "https://example.com/first.mp3");
+    assert_eq!(episode_file_name(&feed.episodes[0]), "2024-01-01 First.mp3");
+
+    let opml = r#"<opml version="2.0"><body>
+          <outline text="Podcasts">
+            <outline type="rss" text="Swim Talk" xmlUrl="https://example.com/feed.xml"/>
+          </outline>
+        </body></opml>"#;
+    assert_eq!(
+        parse_opml(opml).unwrap(),
+        vec![FeedSubscription {
+            url: "https://example.com/feed.xml".to_string(),
+            title: Some("Swim Talk".to_string()),
+            keep: None,
+        }]
+    );
+}
+
+#[test]
+fn test_refresh_downloads_new_episodes_and_applies_retention() {
+    use super::progress::NoProgress;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    // A stand-in HTTP server that serves a feed and the episodes it links to
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    let feed = format!(
+        r#"<rss><channel><title>Laps</title>
+            <item><title>Three</title><pubDate>Wed, 03 Jan 2024 00:00:00 GMT</pubDate>
+              <enclosure url="{base}/3.mp3"/></item>
+            <item><title>Two</title><pubDate>Tue, 02 Jan 2024 00:00:00 GMT</pubDate>
+              <enclosure url="{base}/2.mp3"/></item>
+            <item><title>One</title><pubDate>Mon, 01 Jan 2024 00:00:00 GMT</pubDate>
+              <enclosure url="{base}/1.mp3"/></item>
+        </channel></rss>"#
+    );
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut request_line = String::new();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            reader.read_line(&mut request_line).unwrap();
+            let mut header = String::new();
+            while reader.read_line(&mut header).unwrap() > 2 {
+                header.clear();
+            }
+            let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+            let body = match path {
+                "/feed.xml" => feed.clone(),
+                episode => format!("audio for {}", episode),
+            };
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .unwrap();
+        }
+    });
+
+    let staging = std::env::temp_dir().join("sync-and-swim-feeds-test");
+    let _ = fs::remove_dir_all(&staging);
+    fs::create_dir_all(staging.join("Laps")).unwrap();
+    fs::write(staging.join("Laps").join("2023-12-25 Old.mp3"), "old").unwrap();
+    fs::write(staging.join("Laps").join("2024-01-03 Three.mp3"), "kept").unwrap();
+    fs::write(staging.join("Laps").join("undated Mystery.mp3"), "undated").unwrap();
+    fs::write(staging.join("Laps").join("notes.txt"), "not an episode").unwrap();
+
+    let subscriptions = [FeedSubscription {
+        url: format!("{}/feed.xml", base),
+        title: None,
+        keep: Some(2),
+    }];
+    let results = refresh_feeds(&subscriptions, staging.to_str().unwrap(), &NoProgress);
+    assert_eq!(results[0].error, None);
+    assert_eq!(results[0].downloaded, vec!["2024-01-02 Two.mp3"]);
+    assert_eq!(
+        results[0].removed,
+        vec!["undated Mystery.mp3", "2023-12-25 Old.mp3"]
+    );
+
+    let staged = super::files::list_audio_files(staging.to_str().unwrap()).unwrap();
+    let names: Vec<_> = staged.iter().map(|f| f.name.as_str()).collect();
+    assert_eq!(
+        names,
+        vec!["2024-01-02 Two.mp3", "2024-01-03 Three.mp3", "notes.txt"]
+    );
+    assert_eq!(
+        fs::read_to_string(&staged[0].path).unwrap(),
+        "audio for /2.mp3"
+    );
+}
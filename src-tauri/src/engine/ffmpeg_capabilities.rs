@@ -0,0 +1,261 @@
+//! What the ffmpeg in use can do, so features its build can't support are switched off
+//! with a reason instead of failing partway through a job.
+
+use serde::Serialize;
+use std::collections::HashSet;
+use std::path::Path;
+
+use super::find_ffmpeg::{locate_ffmpeg, locate_ffprobe, FfmpegPaths, FfmpegSource, FoundFfmpeg};
+use super::process::ProcessRunner;
+
+const MUXERS: &[&str] = &["segment"];
+const FILTERS: &[&str] = &["silencedetect", "atempo", "sine"];
+/// Encoders for MP3 (presets, marker tones and conversion) and the other output formats
+const ENCODERS: &[&str] = &["libmp3lame", "aac", "flac", "pcm_s16le"];
+
+/// Something the app does that needs particular parts of ffmpeg
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Feature {
+    /// Splitting anything other than an MP3 that goes out unchanged
+    Splitting,
+    ChangeTempo,
+    EncodingPresets,
+    MarkerTones,
+    ConvertToMp3,
+    ConvertToM4a,
+    ConvertToFlac,
+    ConvertToWav,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 8] = [
+        Feature::Splitting,
+        Feature::ChangeTempo,
+        Feature::EncodingPresets,
+        Feature::MarkerTones,
+        Feature::ConvertToMp3,
+        Feature::ConvertToM4a,
+        Feature::ConvertToFlac,
+        Feature::ConvertToWav,
+    ];
+
+    /// The muxers, filters and encoders the feature can't work without
+    fn requires(self) -> &'static [&'static str] {
+        match self {
+            Feature::Splitting => &["segment"],
+            Feature::ChangeTempo => &["atempo"],
+            Feature::EncodingPresets | Feature::ConvertToMp3 => &["libmp3lame"],
+            Feature::MarkerTones => &["sine", "libmp3lame"],
+            Feature::ConvertToM4a => &["aac"],
+            Feature::ConvertToFlac => &["flac"],
+            Feature::ConvertToWav => &["pcm_s16le"],
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct Capability {
+    pub name: &'static str,
+    pub available: bool,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct FeatureStatus {
+    pub feature: Feature,
+    pub available: bool,
+    /// Why the feature is off, when it is
+    pub reason: Option<String>,
+}
+
+/// The ffmpeg and ffprobe in use, and what they can do
+#[derive(Debug, Serialize, Clone)]
+pub struct FfmpegReport {
+    pub ffmpeg: Option<FoundFfmpeg>,
+    /// None when no ffprobe runs, in which case ffmpeg describes inputs instead
+    pub ffprobe: Option<FoundFfmpeg>,
+    pub version: Option<String>,
+    /// The `configuration:` line, listing the options ffmpeg was built with
+    pub configuration: Option<String>,
+    pub muxers: Vec<Capability>,
+    pub filters: Vec<Capability>,
+    pub encoders: Vec<Capability>,
+    pub features: Vec<FeatureStatus>,
+    /// Problems with the configured paths, which were passed over
+    pub warnings: Vec<String>,
+}
+
+/// The version number from `ffmpeg -version`, e.g. `6.1.1`
+fn parse_version(output: &str) -> Option<String> {
+    output
+        .lines()
+        .next()?
+        .strip_prefix("ffmpeg version ")?
+        .split_whitespace()
+        .next()
+        .map(str::to_string)
+}
+
+fn parse_configuration(output: &str) -> Option<String> {
+    output
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("configuration:"))
+        .map(|config| config.trim().to_string())
+}
+
+/// Names in the listing printed by `-muxers`, `-filters` or `-encoders`, where each entry is
+/// a column of flags followed by the name
+fn listed_names(output: &str) -> HashSet<&str> {
+    output
+        .lines()
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .flat_map(|names| names.split(','))
+        .collect()
+}
+
+fn capabilities(wanted: &[&'static str], listed: &HashSet<&str>) -> Vec<Capability> {
+    wanted
+        .iter()
+        .map(|name| Capability {
+            name,
+            available: listed.contains(name),
+        })
+        .collect()
+}
+
+fn feature_statuses(found: bool, capabilities: &[&Capability]) -> Vec<FeatureStatus> {
+    Feature::ALL
+        .iter()
+        .map(|&feature| {
+            let missing: Vec<&str> = feature
+                .requires()
+                .iter()
+                .filter(|name| !capabilities.iter().any(|c| c.name == **name && c.available))
+                .copied()
+                .collect();
+            let reason = if !found {
+                Some("ffmpeg wasn't found".to_string())
+            } else if !missing.is_empty() {
+                Some(format!(
+                    "This ffmpeg was built without {}",
+                    missing.join(" or ")
+                ))
+            } else {
+                None
+            };
+            FeatureStatus {
+                feature,
+                available: reason.is_none(),
+                reason,
+            }
+        })
+        .collect()
+}
+
+async fn stdout_of<R: ProcessRunner>(runner: &R, program: &Path, arg: &str) -> Option<String> {
+    let output = runner
+        .output(program, &["-hide_banner".to_string(), arg.to_string()])
+        .await
+        .ok()?;
+    output
+        .success
+        .then(|| String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Finds ffmpeg and ffprobe, each on its own, and asks ffmpeg what it was built with.
+///
+/// The window uses the report to switch off features this ffmpeg can't do. The commands
+/// themselves don't check it, so one run anyway fails with ffmpeg's own error.
+pub async fn check_ffmpeg<R: ProcessRunner>(runner: &R, paths: &FfmpegPaths) -> FfmpegReport {
+    let configured = paths.with_env_overrides();
+    let found = locate_ffmpeg(paths);
+    let mut warnings = Vec::new();
+    let ffprobe = locate_ffprobe(paths);
+    for (configured, found, name) in [
+        (&configured.ffmpeg, &found, "ffmpeg"),
+        (&configured.ffprobe, &ffprobe, "ffprobe"),
+    ] {
+        if let Some(path) = configured {
+            if found.as_ref().map(|f| f.source) != Some(FfmpegSource::UserOverride) {
+                warnings.push(format!("{} doesn't run as {}", path.display(), name));
+            }
+        }
+    }
+
+    let (mut version, mut configuration) = (None, None);
+    let (mut muxers, mut filters, mut encoders) = (String::new(), String::new(), String::new());
+    if let Some(found) = &found {
+        if let Some(output) = stdout_of(runner, &found.path, "-version").await {
+            version = parse_version(&output);
+            configuration = parse_configuration(&output);
+        }
+        muxers = stdout_of(runner, &found.path, "-muxers")
+            .await
+            .unwrap_or_default();
+        filters = stdout_of(runner, &found.path, "-filters")
+            .await
+            .unwrap_or_default();
+        encoders = stdout_of(runner, &found.path, "-encoders")
+            .await
+            .unwrap_or_default();
+    }
+
+    let muxers = capabilities(MUXERS, &listed_names(&muxers));
+    let filters = capabilities(FILTERS, &listed_names(&filters));
+    let encoders = capabilities(ENCODERS, &listed_names(&encoders));
+    let all: Vec<&Capability> = muxers.iter().chain(&filters).chain(&encoders).collect();
+    FfmpegReport {
+        features: feature_statuses(found.is_some(), &all),
+        ffmpeg: found,
+        ffprobe,
+        version,
+        configuration,
+        muxers,
+        filters,
+        encoders,
+        warnings,
+    }
+}
+
+#[test]
+fn test_listings_decide_which_features_are_available() {
+    let version = "ffmpeg version 6.1.1 Copyright (c) 2000-2023 the FFmpeg developers\n\
+        built with Apple clang version 15.0.0\n\
+        configuration: --prefix=/opt/homebrew --enable-gpl --enable-libmp3lame\n\
+        libavutil      58. 29.100 / 58. 29.100\n";
+    assert_eq!(parse_version(version).as_deref(), Some("6.1.1"));
+    assert_eq!(
+        parse_configuration(version).as_deref(),
+        Some("--prefix=/opt/homebrew --enable-gpl --enable-libmp3lame")
+    );
+
+    let muxers = listed_names(
+        "File formats:\n D. = Demuxing supported\n .E = Muxing supported\n --\n  E mp3             MP3 (MPEG audio layer 3)\n  E segment         segment\n",
+    );
+    let filters = listed_names(
+        "Filters:\n  T.. = Timeline support\n  ------\n ... atempo            A->A       Adjust audio tempo.\n ... silencedetect     A->A       Detect silence.\n ... sine              |->A       Generate sine wave audio signal.\n",
+    );
+    let encoders = listed_names(
+        "Encoders:\n A..... = Audio\n ------\n A....D aac                  AAC (Advanced Audio Coding)\n A....D flac                 FLAC (Free Lossless Audio Codec)\n A....D pcm_s16le            PCM signed 16-bit little-endian\n",
+    );
+    let muxers = capabilities(MUXERS, &muxers);
+    let filters = capabilities(FILTERS, &filters);
+    let encoders = capabilities(ENCODERS, &encoders);
+    assert!(muxers[0].available);
+    assert!(filters.iter().all(|c| c.available));
+
+    let all: Vec<&Capability> = muxers.iter().chain(&filters).chain(&encoders).collect();
+    let statuses = feature_statuses(true, &all);
+    let status = |feature| statuses.iter().find(|s| s.feature == feature).unwrap();
+    assert!(status(Feature::Splitting).available);
+    assert!(status(Feature::ChangeTempo).available);
+    assert!(status(Feature::ConvertToM4a).available);
+    assert_eq!(
+        status(Feature::MarkerTones).reason.as_deref(),
+        Some("This ffmpeg was built without libmp3lame")
+    );
+
+    assert!(feature_statuses(false, &[])
+        .iter()
+        .all(|s| s.reason.as_deref() == Some("ffmpeg wasn't found")));
+}
@@ -0,0 +1,87 @@
+//! Reads the `key=value` blocks ffmpeg writes with `-progress`, which say how much output
+//! it has written and how fast it's going.
+
+use super::media_info::parse_timestamp;
+
+/// One block of `-progress` output
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FfmpegProgress {
+    /// Seconds of output written so far
+    pub out_time: f64,
+    /// How many times faster than real time ffmpeg is going, once it knows
+    pub speed: Option<f64>,
+}
+
+/// Collects `-progress` lines into updates. Other lines, like ffmpeg's log on the same
+/// pipe, are passed over.
+#[derive(Debug, Default)]
+pub struct ProgressReader {
+    current: FfmpegProgress,
+}
+
+impl ProgressReader {
+    /// Takes one line of output, returning an update when the line ends a block
+    pub fn line(&mut self, line: &str) -> Option<FfmpegProgress> {
+        let (key, value) = line.trim().split_once('=')?;
+        let value = value.trim();
+        match key {
+            // Values are N/A until the first frame is written, and briefly negative
+            "out_time_us" => {
+                if let Ok(micros) = value.parse::<f64>() {
+                    self.current.out_time = (micros / 1_000_000.0).max(0.0);
+                }
+            }
+            "out_time" => {
+                if let Some(seconds) = parse_timestamp(value) {
+                    self.current.out_time = seconds.max(0.0);
+                }
+            }
+            "speed" => {
+                self.current.speed = value
+                    .trim_end_matches('x')
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|speed| *speed > 0.0);
+            }
+            "progress" => return Some(self.current),
+            _ => {}
+        }
+        None
+    }
+}
+
+#[test]
+fn test_reader_reports_each_block_and_skips_log_lines() {
+    let mut reader = ProgressReader::default();
+    let output = "[segment @ 0x2] Opening 'out/book_part_0000.mp3' for writing\n\
+        out_time_us=N/A\n\
+        out_time=N/A\n\
+        speed=N/A\n\
+        progress=continue\n\
+        bitrate= 128.0kbits/s\n\
+        out_time_us=61500000\n\
+        out_time=00:01:01.500000\n\
+        speed=24.6x\n\
+        progress=continue\n\
+        out_time=00:05:00.000000\n\
+        speed=25x\n\
+        progress=end";
+    let updates: Vec<FfmpegProgress> = output.lines().filter_map(|l| reader.line(l)).collect();
+    assert_eq!(
+        updates,
+        vec![
+            FfmpegProgress {
+                out_time: 0.0,
+                speed: None
+            },
+            FfmpegProgress {
+                out_time: 61.5,
+                speed: Some(24.6)
+            },
+            FfmpegProgress {
+                out_time: 300.0,
+                speed: Some(25.0)
+            },
+        ]
+    );
+}
@@ -0,0 +1,495 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::fs::{remove_file, File};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::Arc;
+use std::thread;
+
+use sha2::{Digest, Sha256};
+
+use super::disk_image;
+use super::history::to_hex;
+use super::media_info::MediaInfo;
+use super::progress::ProgressSink;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AudioFile {
+    pub name: String,
+    pub path: String,
+    pub relative_path: String,
+    /// What's in the file, when it's been probed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media: Option<Box<MediaInfo>>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct CopyProgress {
+    pub file_name: String,
+    /// Which destination this update is about
+    pub dest_path: String,
+    pub completed: bool,
+    /// The file's place in the list
+    pub index: usize,
+    /// How many files are done, counting this one once it's completed
+    pub finished: usize,
+    pub total: usize,
+}
+
+fn visit_dirs(dir: &PathBuf, base_path: &PathBuf) -> Result<Vec<AudioFile>, String> {
+    let mut files = Vec::new();
+
+    if dir.is_dir() {
+        for entry in fs::read_dir(dir).map_err(|e| e.to_string())? {
+            let entry = entry.map_err(|e| e.to_string())?;
+            let path = entry.path();
+
+            if path.is_dir() {
+                files.extend(visit_dirs(&path, base_path)?);
+            } else if path.is_file() {
+                if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
+                    // Skip .DS_Store files
+                    if name == ".DS_Store" {
+                        continue;
+                    }
+                    // Get the relative path by stripping the base path
+                    let relative = path
+                        .strip_prefix(base_path)
+                        .map_err(|e| e.to_string())?
+                        .parent()
+                        .and_then(|p| p.to_str())
+                        .unwrap_or("")
+                        .to_string();
+
+                    files.push(AudioFile {
+                        name: name.to_string(),
+                        path: path.to_string_lossy().to_string(),
+                        relative_path: relative,
+                        media: None,
+                    });
+                }
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Lists every file under `path`, in directory order
+pub fn deep_list_files(path: &str) -> Result<Vec<AudioFile>, String> {
+    let base_path = PathBuf::from(path);
+    visit_dirs(&base_path, &base_path)
+}
+
+/// Lists the files directly inside `path`, sorted by name
+pub fn shallow_list_files(path: &str) -> Result<Vec<AudioFile>, String> {
+    let dir = PathBuf::from(path);
+    let mut files = Vec::new();
+
+    if dir.is_dir() {
+        for entry in fs::read_dir(&dir).map_err(|e| e.to_string())? {
+            let entry = entry.map_err(|e| e.to_string())?;
+            let path = entry.path();
+
+            if path.is_file() {
+                if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
+                    if name == ".DS_Store" {
+                        continue;
+                    }
+                    files.push(AudioFile {
+                        name: name.to_string(),
+                        path: path.to_string_lossy().to_string(),
+                        relative_path: String::new(), // No subdirs for this function
+                        media: None,
+                    });
+                }
+            }
+        }
+    }
+
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(files)
+}
+
+/// Lists every file under `path`, sorted by folder and then by name
+pub fn list_audio_files(path: &str) -> Result<Vec<AudioFile>, String> {
+    let base_path = PathBuf::from(path);
+    let mut files = visit_dirs(&base_path, &base_path)?;
+    sort_audio_files(&mut files);
+    Ok(files)
+}
+
+pub fn sort_audio_files(files: &mut [AudioFile]) {
+    files.sort_by(|a, b| {
+        // First compare by relative path
+        let path_cmp = a.relative_path.cmp(&b.relative_path);
+        if path_cmp == std::cmp::Ordering::Equal {
+            // If paths are equal, compare by name
+            a.name.cmp(&b.name)
+        } else {
+            path_cmp
+        }
+    });
+}
+
+/// Makes a title safe to use as a file or folder name on the headphones' FAT filesystem
+pub fn safe_file_name(title: &str) -> String {
+    let name: String = title
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .take(100)
+        .collect();
+    let name = name.trim().trim_end_matches('.').to_string();
+    if name.is_empty() {
+        "Untitled".to_string()
+    } else {
+        name
+    }
+}
+
+pub fn delete_files(files: Vec<AudioFile>) -> Result<(), String> {
+    for file in files {
+        remove_file(&file.path).map_err(|e| format!("Failed to delete {}: {}", file.name, e))?;
+    }
+    Ok(())
+}
+
+/// How copying went for one destination
+#[derive(Debug, Serialize, Clone)]
+pub struct DestinationResult {
+    pub dest_path: String,
+    pub error: Option<String>,
+}
+
+/// How copying to every destination went, with what was learned reading the sources
+#[derive(Debug, Clone)]
+pub struct CopyReport {
+    pub destinations: Vec<DestinationResult>,
+    /// Each source file's SHA-256, in order, or None if it wasn't read to the end
+    pub sha256: Vec<Option<String>>,
+    /// Why reading the sources stopped partway, which stops every destination with it
+    pub source_error: Option<String>,
+}
+
+/// Size of the pieces each source file is read in before being handed to every destination
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+/// How many chunks a slow destination may fall behind before reading waits for it
+const CHUNKS_IN_FLIGHT: usize = 8;
+
+pub(super) enum CopyMessage {
+    Start(usize, AudioFile),
+    Data(Arc<Vec<u8>>),
+    End,
+    /// The file being sent couldn't be read to the end, so its partial copy is removed
+    Abort,
+}
+
+/// Deletes the destination directory if mode is "replace"
+pub(super) fn prepare_destination(dest_path: &str, mode: &str) -> Result<(), String> {
+    if mode == "replace" {
+        if let Err(e) = fs::remove_dir_all(dest_path) {
+            // Ignore if directory doesn't exist
+            if e.kind() != std::io::ErrorKind::NotFound {
+                return Err(e.to_string());
+            }
+        }
+        fs::create_dir_all(dest_path).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Writes the files sent by the reader into one destination, one at a time and in order
+fn write_destination(
+    dest_path: &str,
+    mode: &str,
+    total: usize,
+    messages: Receiver<CopyMessage>,
+    progress: &impl ProgressSink,
+) -> Result<(), String> {
+    prepare_destination(dest_path, mode)?;
+
+    let mut current: Option<(usize, AudioFile, PathBuf, File)> = None;
+    for message in messages {
+        match message {
+            CopyMessage::Start(index, file) => {
+                // Emit progress start
+                progress.copy_progress(CopyProgress {
+                    file_name: file.name.clone(),
+                    dest_path: dest_path.to_string(),
+                    completed: false,
+                    index,
+                    finished: index,
+                    total,
+                });
+
+                let mut dest_file = PathBuf::from(dest_path);
+
+                // Create subdirectory if relative_path is not empty
+                if !file.relative_path.is_empty() {
+                    dest_file = dest_file.join(&file.relative_path);
+                    fs::create_dir_all(&dest_file).map_err(|e| e.to_string())?;
+                }
+
+                dest_file = dest_file.join(&file.name);
+                let out = File::create(&dest_file)
+                    .map_err(|e| format!("Failed to copy {}: {}", file.name, e))?;
+                current = Some((index, file, dest_file, out));
+            }
+            CopyMessage::Data(chunk) => {
+                if let Some((_, file, _, out)) = current.as_mut() {
+                    out.write_all(&chunk)
+                        .map_err(|e| format!("Failed to copy {}: {}", file.name, e))?;
+                }
+            }
+            CopyMessage::End => {
+                let Some((index, file, _, out)) = current.take() else {
+                    continue;
+                };
+                // Make sure the file has arrived before sending the next
+                out.sync_all()
+                    .map_err(|e| format!("Failed to copy {}: {}", file.name, e))?;
+                drop(out);
+
+                // Small delay to ensure files have distinct timestamps
+                std::thread::sleep(std::time::Duration::from_millis(100));
+
+                // Emit progress completion
+                progress.copy_progress(CopyProgress {
+                    file_name: file.name,
+                    dest_path: dest_path.to_string(),
+                    completed: true,
+                    index,
+                    finished: index + 1,
+                    total,
+                });
+            }
+            CopyMessage::Abort => {
+                if let Some((_, file, dest_file, out)) = current.take() {
+                    drop(out);
+                    remove_file(&dest_file)
+                        .map_err(|e| format!("Failed to remove partial {}: {}", file.name, e))?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Copies files to several destinations at once. Each source file is read once and handed to
+/// every destination; each destination writes the files one at a time, in order, so its play
+/// order matches the list. A failing destination doesn't stop the others, but a source that
+/// can't be read stops them all, leaving no partial copy of it behind. Each source is hashed
+/// as it's read, so its history can be recorded without reading it again.
+///
+/// A destination ending in `.img` is written into as a FAT32 disk image, which is created if
+/// it doesn't exist yet.
+pub fn copy_files_to_destinations(
+    files: Vec<AudioFile>,
+    dest_paths: &[String],
+    mode: &str,
+    progress: &impl ProgressSink,
+) -> CopyReport {
+    let total = files.len();
+    let mut sha256 = vec![None; total];
+
+    thread::scope(|scope| {
+        let mut senders = Vec::new();
+        let mut writers = Vec::new();
+        for dest_path in dest_paths {
+            let (sender, receiver) = sync_channel(CHUNKS_IN_FLIGHT);
+            let handle = scope.spawn(move || {
+                if disk_image::is_disk_image(dest_path) {
+                    disk_image::write_image(dest_path, mode, total, receiver, progress)
+                } else {
+                    write_destination(dest_path, mode, total, receiver, progress)
+                }
+            });
+            senders.push(Some(sender));
+            writers.push((dest_path, handle));
+        }
+
+        let mut source_error = None;
+        'files: for (index, file) in files.into_iter().enumerate() {
+            let mut source = match File::open(&file.path) {
+                Ok(source) => source,
+                Err(e) => {
+                    source_error = Some(format!("Failed to read {}: {}", file.name, e));
+                    break;
+                }
+            };
+            send_to_all(&mut senders, || CopyMessage::Start(index, file.clone()));
+            let mut hasher = Sha256::new();
+            loop {
+                let mut chunk = vec![0; CHUNK_SIZE];
+                match source.read(&mut chunk) {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        chunk.truncate(n);
+                        hasher.update(&chunk);
+                        let chunk = Arc::new(chunk);
+                        send_to_all(&mut senders, || CopyMessage::Data(chunk.clone()));
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => {
+                        send_to_all(&mut senders, || CopyMessage::Abort);
+                        source_error = Some(format!("Failed to read {}: {}", file.name, e));
+                        break 'files;
+                    }
+                }
+            }
+            send_to_all(&mut senders, || CopyMessage::End);
+            sha256[index] = Some(to_hex(&hasher.finalize()));
+            if senders.iter().all(Option::is_none) {
+                break;
+            }
+        }
+        // Hanging up lets the writers finish
+        drop(senders);
+
+        let destinations = writers
+            .into_iter()
+            .map(|(dest_path, handle)| {
+                let result = handle
+                    .join()
+                    .unwrap_or_else(|_| Err("Copy thread panicked".to_string()));
+                DestinationResult {
+                    dest_path: dest_path.clone(),
+                    error: result.err(),
+                }
+            })
+            .collect();
+        CopyReport {
+            destinations,
+            sha256,
+            source_error,
+        }
+    })
+}
+
+/// Hands a message to every destination still accepting them. A destination that hung up
+/// has failed, and reports why when its thread is joined.
+fn send_to_all(senders: &mut [Option<SyncSender<CopyMessage>>], message: impl Fn() -> CopyMessage) {
+    for slot in senders.iter_mut() {
+        if let Some(sender) = slot {
+            if sender.send(message()).is_err() {
+                *slot = None;
+            }
+        }
+    }
+}
+
+/// Copies files one at a time, in order, so the device's play order matches the list.
+pub fn copy_files(
+    files: Vec<AudioFile>,
+    dest_path: &str,
+    mode: &str,
+    progress: &impl ProgressSink,
+) -> Result<(), String> {
+    let report = copy_files_to_destinations(files, &[dest_path.to_string()], mode, progress);
+    if let Some(error) = report.source_error {
+        return Err(error);
+    }
+    match report.destinations.into_iter().next().and_then(|r| r.error) {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
+}
+
+#[test]
+fn test_copy_files_to_destinations_writes_every_destination_in_order() {
+    struct Order(std::sync::Mutex<Vec<(String, String)>>);
+    impl ProgressSink for Order {
+        fn copy_progress(&self, progress: CopyProgress) {
+            if progress.completed {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push((progress.dest_path, progress.file_name));
+            }
+        }
+    }
+
+    let root = std::env::temp_dir().join("sync-and-swim-fan-out-test");
+    let _ = fs::remove_dir_all(&root);
+    let source = root.join("source");
+    fs::create_dir_all(source.join("book")).unwrap();
+    fs::write(source.join("book/01.mp3"), b"first").unwrap();
+    fs::write(source.join("book/02.mp3"), b"second").unwrap();
+    let files = list_audio_files(source.to_str().unwrap()).unwrap();
+    let dests: Vec<String> = ["a", "b"]
+        .iter()
+        .map(|d| root.join(d).to_string_lossy().to_string())
+        .collect();
+
+    let order = Order(Default::default());
+    let report = copy_files_to_destinations(files, &dests, "replace", &order);
+
+    assert!(report.destinations.iter().all(|r| r.error.is_none()));
+    assert_eq!(
+        report.sha256[1].as_deref(),
+        Some("16367aacb67a4a017c8da8ab95682ccb390863780f7114dda0a0e0c55644c7c4")
+    );
+    for dest in &dests {
+        let copied = fs::read(PathBuf::from(dest).join("book/02.mp3")).unwrap();
+        assert_eq!(copied, b"second");
+        let names: Vec<_> = order
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(d, _)| d == dest)
+            .map(|(_, name)| name.clone())
+            .collect();
+        assert_eq!(names, vec!["01.mp3", "02.mp3"]);
+    }
+}
+
+#[test]
+fn test_unreadable_source_leaves_no_partial_copy() {
+    use super::progress::NoProgress;
+
+    let root = std::env::temp_dir().join("sync-and-swim-unreadable-source-test");
+    let _ = fs::remove_dir_all(&root);
+    let source = root.join("source");
+    fs::create_dir_all(&source).unwrap();
+    fs::write(source.join("01.mp3"), b"first").unwrap();
+    // A folder opens like a file but fails on the first read
+    fs::create_dir_all(source.join("02.mp3")).unwrap();
+    let files: Vec<AudioFile> = ["01.mp3", "02.mp3"]
+        .iter()
+        .map(|name| AudioFile {
+            name: name.to_string(),
+            path: source.join(name).to_string_lossy().to_string(),
+            relative_path: String::new(),
+            media: None,
+        })
+        .collect();
+    let folder = root.join("device").to_string_lossy().to_string();
+    let image = root.join("device.img").to_string_lossy().to_string();
+
+    let report = copy_files_to_destinations(
+        files,
+        &[folder.clone(), image.clone()],
+        "replace",
+        &NoProgress,
+    );
+
+    assert!(report
+        .source_error
+        .as_deref()
+        .is_some_and(|e| e.starts_with("Failed to read 02.mp3")));
+    assert!(report.destinations.iter().all(|r| r.error.is_none()));
+    assert!(PathBuf::from(&folder).join("01.mp3").exists());
+    assert!(!PathBuf::from(&folder).join("02.mp3").exists());
+    let in_image: Vec<String> = disk_image::list_disk_image(&image)
+        .unwrap()
+        .into_iter()
+        .map(|f| f.name)
+        .collect();
+    assert_eq!(in_image, vec!["01.mp3"]);
+}
@@ -0,0 +1,258 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use super::process::ProcessRunner;
+
+/// Common locations where ffmpeg might be installed
+const COMMON_PATHS: &[&str] = &[
+    "/usr/local/bin",
+    "/usr/bin",
+    "/opt/homebrew/bin",                       // Homebrew on Apple Silicon
+    "/usr/local/opt/ffmpeg/bin",               // Homebrew Intel
+    "/opt/homebrew/opt/ffmpeg/bin",            // Homebrew Apple Silicon
+    "/Applications/ffmpeg.app/Contents/MacOS", // macOS app bundle
+];
+
+/// Checks if a path points to an executable ffmpeg or ffprobe
+fn is_executable_ffmpeg(path: &Path) -> bool {
+    if !path.exists() || !path.is_file() {
+        return false;
+    }
+
+    // On Unix-like systems, check if the file is executable
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if let Ok(metadata) = std::fs::metadata(path) {
+            if !metadata.permissions().mode() & 0o111 != 0 {
+                return false;
+            }
+        }
+    }
+
+    // Try to run -version to verify it's actually ffmpeg or ffprobe
+    Command::new(path)
+        .arg("-version")
+        .output()
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
+
+/// Name of the ffmpeg executable on this platform
+const FFMPEG_EXECUTABLE: &str = if cfg!(windows) {
+    "ffmpeg.exe"
+} else {
+    "ffmpeg"
+};
+
+const FFPROBE_EXECUTABLE: &str = if cfg!(windows) {
+    "ffprobe.exe"
+} else {
+    "ffprobe"
+};
+
+/// Environment variable that points at a particular ffmpeg, ahead of any other
+pub const FFMPEG_ENV_VAR: &str = "SYNC_AND_SWIM_FFMPEG";
+/// Environment variable that points at a particular ffprobe
+pub const FFPROBE_ENV_VAR: &str = "SYNC_AND_SWIM_FFPROBE";
+
+/// ffmpeg and ffprobe executables chosen in the app's settings. Unset paths are searched for.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct FfmpegPaths {
+    pub ffmpeg: Option<PathBuf>,
+    pub ffprobe: Option<PathBuf>,
+}
+
+impl FfmpegPaths {
+    /// These paths, with any set in [`FFMPEG_ENV_VAR`] or [`FFPROBE_ENV_VAR`] taking their place
+    pub fn with_env_overrides(&self) -> FfmpegPaths {
+        FfmpegPaths {
+            ffmpeg: env::var_os(FFMPEG_ENV_VAR)
+                .map(PathBuf::from)
+                .or_else(|| self.ffmpeg.clone()),
+            ffprobe: env::var_os(FFPROBE_ENV_VAR)
+                .map(PathBuf::from)
+                .or_else(|| self.ffprobe.clone()),
+        }
+    }
+}
+
+/// Where the ffmpeg or ffprobe in use was found
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FfmpegSource {
+    /// Named by the user, in the settings or through [`FFMPEG_ENV_VAR`]
+    UserOverride,
+    /// Shipped with the app, next to its executable
+    Sidecar,
+    Path,
+    CommonPath,
+}
+
+impl FfmpegSource {
+    pub fn describe(self) -> &'static str {
+        match self {
+            FfmpegSource::UserOverride => "chosen by you",
+            FfmpegSource::Sidecar => "bundled with the app",
+            FfmpegSource::Path => "on your PATH",
+            FfmpegSource::CommonPath => "in a common install location",
+        }
+    }
+}
+
+/// An ffmpeg or ffprobe executable, and where it was found
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct FoundFfmpeg {
+    pub path: PathBuf,
+    pub source: FfmpegSource,
+}
+
+/// The folder holding the app's own executable, where Tauri puts sidecar binaries
+fn sidecar_dir() -> Option<PathBuf> {
+    env::current_exe().ok()?.parent().map(Path::to_path_buf)
+}
+
+/// Tries each place `executable` might be, in order: an explicit override, the app's
+/// sidecar, then PATH and common install locations
+fn locate_in(
+    executable: &str,
+    user_override: Option<PathBuf>,
+    sidecar_dir: Option<PathBuf>,
+    path_var: Option<OsString>,
+) -> Option<FoundFfmpeg> {
+    let path_dirs = path_var
+        .map(|paths| env::split_paths(&paths).collect::<Vec<_>>())
+        .unwrap_or_default();
+    let candidates = user_override
+        .map(|path| (path, FfmpegSource::UserOverride))
+        .into_iter()
+        .chain(sidecar_dir.map(|dir| (dir.join(executable), FfmpegSource::Sidecar)))
+        .chain(
+            path_dirs
+                .into_iter()
+                .map(|dir| (dir.join(executable), FfmpegSource::Path)),
+        )
+        .chain(COMMON_PATHS.iter().map(|dir| {
+            (
+                PathBuf::from(dir).join(executable),
+                FfmpegSource::CommonPath,
+            )
+        }));
+    for (path, source) in candidates {
+        if is_executable_ffmpeg(&path) {
+            return Some(FoundFfmpeg { path, source });
+        }
+    }
+    None
+}
+
+/// Finds ffmpeg, reporting where it came from. A configured path that doesn't run is
+/// skipped in favor of the other places.
+pub fn locate_ffmpeg(paths: &FfmpegPaths) -> Option<FoundFfmpeg> {
+    locate_in(
+        FFMPEG_EXECUTABLE,
+        paths.with_env_overrides().ffmpeg,
+        sidecar_dir(),
+        env::var_os("PATH"),
+    )
+}
+
+/// Finds ffprobe the same way as ffmpeg, without assuming the two are installed together
+pub fn locate_ffprobe(paths: &FfmpegPaths) -> Option<FoundFfmpeg> {
+    locate_in(
+        FFPROBE_EXECUTABLE,
+        paths.with_env_overrides().ffprobe,
+        sidecar_dir(),
+        env::var_os("PATH"),
+    )
+}
+
+/// A located ffmpeg and ffprobe, and the runner used to invoke them
+pub struct FfmpegTools<R: ProcessRunner> {
+    pub runner: R,
+    pub ffmpeg: PathBuf,
+    /// None when ffprobe isn't installed, in which case ffmpeg's own description of the
+    /// input stands in for it
+    pub ffprobe: Option<PathBuf>,
+}
+
+impl<R: ProcessRunner> FfmpegTools<R> {
+    /// Uses the ffmpeg and ffprobe found in the app's sidecar, PATH or common locations
+    pub fn locate(runner: R) -> Result<Self> {
+        Self::locate_with(runner, &FfmpegPaths::default())
+    }
+
+    /// Uses the configured paths where they're set and work, searching for the rest
+    pub fn locate_with(runner: R, paths: &FfmpegPaths) -> Result<Self> {
+        let ffmpeg = locate_ffmpeg(paths).context("FFmpeg not found")?.path;
+        let ffprobe = locate_ffprobe(paths).map(|found| found.path);
+        Ok(FfmpegTools {
+            runner,
+            ffmpeg,
+            ffprobe,
+        })
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_override_then_sidecar_then_path() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let root = std::env::temp_dir().join("sync-and-swim-find-ffmpeg-test");
+    let _ = std::fs::remove_dir_all(&root);
+    let fake = |dir: &str, executable: &str| {
+        let dir = root.join(dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(executable);
+        std::fs::write(&path, "#!/bin/sh\nexit 0\n").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        dir
+    };
+    let app = fake("app", FFMPEG_EXECUTABLE);
+    let on_path = fake("bin", FFMPEG_EXECUTABLE);
+    let custom = fake("custom", FFMPEG_EXECUTABLE);
+    // Only on the PATH, away from the ffmpeg that's found first
+    fake("bin", FFPROBE_EXECUTABLE);
+
+    let found = locate_in(
+        FFMPEG_EXECUTABLE,
+        None,
+        Some(app.clone()),
+        Some(on_path.clone().into()),
+    )
+    .unwrap();
+    assert_eq!(found.source, FfmpegSource::Sidecar);
+    assert_eq!(found.path, app.join(FFMPEG_EXECUTABLE));
+
+    let found = locate_in(
+        FFMPEG_EXECUTABLE,
+        None,
+        Some(root.join("nothing here")),
+        Some(on_path.clone().into()),
+    )
+    .unwrap();
+    assert_eq!(found.source, FfmpegSource::Path);
+
+    let found = locate_in(
+        FFMPEG_EXECUTABLE,
+        Some(custom.join(FFMPEG_EXECUTABLE)),
+        Some(app.clone()),
+        None,
+    )
+    .unwrap();
+    assert_eq!(found.source, FfmpegSource::UserOverride);
+
+    let found = locate_in(
+        FFPROBE_EXECUTABLE,
+        None,
+        Some(app),
+        Some(on_path.clone().into()),
+    )
+    .unwrap();
+    assert_eq!(found.path, on_path.join(FFPROBE_EXECUTABLE));
+}
@@ -0,0 +1,416 @@
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::files::AudioFile;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY,
+    kind TEXT NOT NULL,
+    started_at INTEGER NOT NULL,
+    finished_at INTEGER,
+    destination TEXT,
+    volume TEXT,
+    settings TEXT NOT NULL,
+    outcome TEXT NOT NULL,
+    error TEXT
+);
+CREATE TABLE IF NOT EXISTS run_files (
+    run_id INTEGER NOT NULL REFERENCES runs(id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    path TEXT NOT NULL,
+    relative_path TEXT NOT NULL,
+    sha256 TEXT
+);
+CREATE INDEX IF NOT EXISTS run_files_sha256 ON run_files(sha256);
+CREATE INDEX IF NOT EXISTS runs_volume ON runs(volume, kind, started_at);
+";
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RunKind {
+    Copy,
+    Split,
+    Delete,
+}
+
+impl RunKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            RunKind::Copy => "copy",
+            RunKind::Split => "split",
+            RunKind::Delete => "delete",
+        }
+    }
+
+    fn parse(kind: &str) -> Result<Self> {
+        match kind {
+            "copy" => Ok(RunKind::Copy),
+            "split" => Ok(RunKind::Split),
+            "delete" => Ok(RunKind::Delete),
+            other => Err(anyhow::anyhow!("Unknown run kind {}", other)),
+        }
+    }
+}
+
+/// A file that took part in a run, with its content hash when it could be read
+#[derive(Debug, Serialize, Clone)]
+pub struct RecordedFile {
+    pub name: String,
+    pub path: String,
+    pub relative_path: String,
+    pub sha256: Option<String>,
+}
+
+impl RecordedFile {
+    /// Hashes the file's contents. Unreadable files are recorded without a hash.
+    pub fn hashed(file: &AudioFile) -> Self {
+        RecordedFile {
+            name: file.name.clone(),
+            path: file.path.clone(),
+            relative_path: file.relative_path.clone(),
+            sha256: hash_file(Path::new(&file.path)).ok(),
+        }
+    }
+
+    /// Records the file without reading it, for work that hashes it along the way
+    pub fn unhashed(file: &AudioFile) -> Self {
+        RecordedFile {
+            name: file.name.clone(),
+            path: file.path.clone(),
+            relative_path: file.relative_path.clone(),
+            sha256: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct HistoryRun {
+    pub id: i64,
+    pub kind: RunKind,
+    /// Seconds since the Unix epoch
+    pub started_at: i64,
+    pub finished_at: Option<i64>,
+    pub destination: Option<String>,
+    pub volume: Option<String>,
+    pub settings: serde_json::Value,
+    /// "running", "succeeded" or "failed"
+    pub outcome: String,
+    pub error: Option<String>,
+    pub files: Vec<RecordedFile>,
+}
+
+/// Narrows a history query. Unset fields match everything.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct HistoryQuery {
+    pub kind: Option<RunKind>,
+    pub volume: Option<String>,
+    /// Matches runs that include a file whose name contains this text
+    pub file_name: Option<String>,
+    pub limit: Option<u32>,
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+pub fn hash_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(to_hex(&hasher.finalize()))
+}
+
+/// Writes a digest the way hashes are stored, in lowercase hex
+pub(super) fn to_hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Names the volume `path` lives on, so loads to the same headphones can be grouped
+/// even if they're mounted somewhere different next time.
+pub fn destination_volume(path: &Path) -> String {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let Ok(device) = std::fs::metadata(path).map(|m| m.dev()) else {
+            return path.to_string_lossy().to_string();
+        };
+        // Walk up until the parent is on a different device: that's the mount point
+        let mut mount_point = path;
+        while let Some(parent) = mount_point.parent() {
+            match std::fs::metadata(parent) {
+                Ok(metadata) if metadata.dev() == device => mount_point = parent,
+                _ => break,
+            }
+        }
+        mount_point
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| mount_point.to_string_lossy().to_string())
+    }
+    #[cfg(not(unix))]
+    {
+        path.components()
+            .next()
+            .map(|prefix| prefix.as_os_str().to_string_lossy().to_string())
+            .unwrap_or_else(|| path.to_string_lossy().to_string())
+    }
+}
+
+pub struct HistoryDb {
+    conn: Connection,
+}
+
+impl HistoryDb {
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let conn = Connection::open(path).context("Failed to open history database")?;
+        Self::init(conn)
+    }
+
+    pub fn open_in_memory() -> Result<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Result<Self> {
+        conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+        conn.execute_batch(SCHEMA)
+            .context("Failed to create history tables")?;
+        Ok(HistoryDb { conn })
+    }
+
+    /// Records the start of a run and returns its id
+    pub fn start_run(
+        &mut self,
+        kind: RunKind,
+        destination: Option<&str>,
+        settings: &serde_json::Value,
+        files: &[RecordedFile],
+    ) -> Result<i64> {
+        let volume = destination.map(|d| destination_volume(&PathBuf::from(d)));
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "INSERT INTO runs (kind, started_at, destination, volume, settings, outcome)
+             VALUES (?1, ?2, ?3, ?4, ?5, 'running')",
+            params![
+                kind.as_str(),
+                now(),
+                destination,
+                volume,
+                settings.to_string()
+            ],
+        )?;
+        let run_id = tx.last_insert_rowid();
+        {
+            let mut insert = tx.prepare(
+                "INSERT INTO run_files (run_id, position, name, path, relative_path, sha256)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            )?;
+            for (position, file) in files.iter().enumerate() {
+                insert.execute(params![
+                    run_id,
+                    position as i64,
+                    file.name,
+                    file.path,
+                    file.relative_path,
+                    file.sha256
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(run_id)
+    }
+
+    pub fn finish_run(&self, run_id: i64, outcome: &Result<(), String>) -> Result<()> {
+        let (status, error) = match outcome {
+            Ok(()) => ("succeeded", None),
+            Err(e) => ("failed", Some(e.as_str())),
+        };
+        self.conn.execute(
+            "UPDATE runs SET finished_at = ?1, outcome = ?2, error = ?3 WHERE id = ?4",
+            params![now(), status, error, run_id],
+        )?;
+        Ok(())
+    }
+
+    /// Fills in the hashes of a run's files, in the order they were recorded
+    pub fn set_hashes(&self, run_id: i64, hashes: &[Option<String>]) -> Result<()> {
+        let mut update = self
+            .conn
+            .prepare("UPDATE run_files SET sha256 = ?1 WHERE run_id = ?2 AND position = ?3")?;
+        for (position, hash) in hashes.iter().enumerate() {
+            if hash.is_some() {
+                update.execute(params![hash, run_id, position as i64])?;
+            }
+        }
+        Ok(())
+    }
+
+    fn run_files(&self, run_id: i64) -> Result<Vec<RecordedFile>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT name, path, relative_path, sha256 FROM run_files
+             WHERE run_id = ?1 ORDER BY position",
+        )?;
+        let files = stmt
+            .query_map([run_id], |row| {
+                Ok(RecordedFile {
+                    name: row.get(0)?,
+                    path: row.get(1)?,
+                    relative_path: row.get(2)?,
+                    sha256: row.get(3)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(files)
+    }
+
+    /// Lists matching runs, newest first
+    pub fn query(&self, query: &HistoryQuery) -> Result<Vec<HistoryRun>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, kind, started_at, finished_at, destination, volume, settings, outcome, error
+             FROM runs
+             WHERE (?1 IS NULL OR kind = ?1)
+               AND (?2 IS NULL OR volume = ?2)
+               AND (?3 IS NULL OR EXISTS (
+                   SELECT 1 FROM run_files
+                   WHERE run_files.run_id = runs.id AND instr(run_files.name, ?3) > 0))
+             ORDER BY started_at DESC, id DESC
+             LIMIT ?4",
+        )?;
+        let rows = stmt
+            .query_map(
+                params![
+                    query.kind.map(RunKind::as_str),
+                    query.volume,
+                    query.file_name,
+                    query.limit.map(i64::from).unwrap_or(-1)
+                ],
+                |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, i64>(2)?,
+                        row.get::<_, Option<i64>>(3)?,
+                        row.get::<_, Option<String>>(4)?,
+                        row.get::<_, Option<String>>(5)?,
+                        row.get::<_, String>(6)?,
+                        row.get::<_, String>(7)?,
+                        row.get::<_, Option<String>>(8)?,
+                    ))
+                },
+            )?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        rows.into_iter()
+            .map(
+                |(
+                    id,
+                    kind,
+                    started_at,
+                    finished_at,
+                    destination,
+                    volume,
+                    settings,
+                    outcome,
+                    error,
+                )| {
+                    Ok(HistoryRun {
+                        id,
+                        kind: RunKind::parse(&kind)?,
+                        started_at,
+                        finished_at,
+                        destination,
+                        volume,
+                        settings: serde_json::from_str(&settings).unwrap_or_default(),
+                        outcome,
+                        error,
+                        files: self.run_files(id)?,
+                    })
+                },
+            )
+            .collect()
+    }
+
+    /// Returns the files that weren't part of the last successful load onto the volume
+    /// holding `dest_path`, matching by content hash.
+    pub fn new_since_last_load(
+        &self,
+        files: &[RecordedFile],
+        dest_path: &str,
+    ) -> Result<Vec<RecordedFile>> {
+        let volume = destination_volume(Path::new(dest_path));
+        let last_load: Option<i64> = self
+            .conn
+            .query_row(
+                "SELECT id FROM runs
+                 WHERE kind = 'copy' AND outcome = 'succeeded' AND volume = ?1
+                 ORDER BY started_at DESC, id DESC LIMIT 1",
+                [&volume],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let Some(last_load) = last_load else {
+            return Ok(files.to_vec());
+        };
+
+        let loaded: Vec<Option<String>> = self
+            .run_files(last_load)?
+            .into_iter()
+            .map(|f| f.sha256)
+            .collect();
+        Ok(files
+            .iter()
+            .filter(|file| file.sha256.is_none() || !loaded.contains(&file.sha256))
+            .cloned()
+            .collect())
+    }
+}
+
+#[test]
+fn test_new_since_last_load_skips_loaded_files() {
+    let mut db = HistoryDb::open_in_memory().unwrap();
+    let file = |name: &str, hash: &str| RecordedFile {
+        name: name.to_string(),
+        path: format!("/staging/{}", name),
+        relative_path: String::new(),
+        sha256: Some(hash.to_string()),
+    };
+    let dest = std::env::temp_dir();
+    let dest = dest.to_str().unwrap();
+
+    let run = db
+        .start_run(
+            RunKind::Copy,
+            Some(dest),
+            &serde_json::json!({ "mode": "append" }),
+            &[file("episode-1.mp3", "aaa")],
+        )
+        .unwrap();
+    db.finish_run(run, &Ok(())).unwrap();
+
+    let candidates = [file("episode-1.mp3", "aaa"), file("episode-2.mp3", "bbb")];
+    let new_files = db.new_since_last_load(&candidates, dest).unwrap();
+    assert_eq!(new_files.len(), 1);
+    assert_eq!(new_files[0].name, "episode-2.mp3");
+
+    let runs = db
+        .query(&HistoryQuery {
+            file_name: Some("episode-1".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(runs.len(), 1);
+    assert_eq!(runs[0].outcome, "succeeded");
+}
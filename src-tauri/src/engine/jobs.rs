@@ -0,0 +1,555 @@
+//! A persistent queue of long-running jobs, each with a state and a log of the events it
+//! reported, so a reloaded window can catch up and an app restart doesn't lose work.
+
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS jobs (
+    id INTEGER PRIMARY KEY,
+    kind TEXT NOT NULL,
+    spec TEXT NOT NULL,
+    state TEXT NOT NULL,
+    error TEXT,
+    created_at INTEGER NOT NULL,
+    started_at INTEGER,
+    finished_at INTEGER
+);
+CREATE TABLE IF NOT EXISTS job_events (
+    job_id INTEGER NOT NULL REFERENCES jobs(id) ON DELETE CASCADE,
+    seq INTEGER NOT NULL,
+    at INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    PRIMARY KEY (job_id, seq)
+);
+CREATE INDEX IF NOT EXISTS jobs_state ON jobs(state, id);
+";
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobKind {
+    Copy,
+    Split,
+    Transcode,
+}
+
+impl JobKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            JobKind::Copy => "copy",
+            JobKind::Split => "split",
+            JobKind::Transcode => "transcode",
+        }
+    }
+
+    fn parse(kind: &str) -> Result<Self> {
+        match kind {
+            "copy" => Ok(JobKind::Copy),
+            "split" => Ok(JobKind::Split),
+            "transcode" => Ok(JobKind::Transcode),
+            other => Err(anyhow::anyhow!("Unknown job kind {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Queued,
+    Running,
+    Done,
+    Failed,
+    /// Was running when the app last quit, and waits to be resumed or discarded
+    Interrupted,
+}
+
+impl JobState {
+    fn as_str(self) -> &'static str {
+        match self {
+            JobState::Queued => "queued",
+            JobState::Running => "running",
+            JobState::Done => "done",
+            JobState::Failed => "failed",
+            JobState::Interrupted => "interrupted",
+        }
+    }
+
+    fn parse(state: &str) -> Result<Self> {
+        match state {
+            "queued" => Ok(JobState::Queued),
+            "running" => Ok(JobState::Running),
+            "done" => Ok(JobState::Done),
+            "failed" => Ok(JobState::Failed),
+            "interrupted" => Ok(JobState::Interrupted),
+            other => Err(anyhow::anyhow!("Unknown job state {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct Job {
+    pub id: i64,
+    pub kind: JobKind,
+    /// What to do, as it was submitted
+    pub spec: serde_json::Value,
+    pub state: JobState,
+    pub error: Option<String>,
+    /// Seconds since the Unix epoch
+    pub created_at: i64,
+    pub started_at: Option<i64>,
+    pub finished_at: Option<i64>,
+}
+
+/// Something a job reported, numbered in the order it happened
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct JobEvent {
+    pub job_id: i64,
+    pub seq: i64,
+    pub at: i64,
+    /// The event's name, e.g. `state` or `segment-progress`
+    pub name: String,
+    pub payload: serde_json::Value,
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+type JobRow = (
+    i64,
+    String,
+    String,
+    String,
+    Option<String>,
+    i64,
+    Option<i64>,
+    Option<i64>,
+);
+
+const JOB_COLUMNS: &str = "id, kind, spec, state, error, created_at, started_at, finished_at";
+
+fn job_row(row: &rusqlite::Row) -> rusqlite::Result<JobRow> {
+    Ok((
+        row.get(0)?,
+        row.get(1)?,
+        row.get(2)?,
+        row.get(3)?,
+        row.get(4)?,
+        row.get(5)?,
+        row.get(6)?,
+        row.get(7)?,
+    ))
+}
+
+fn job_from_row(
+    (id, kind, spec, state, error, created_at, started_at, finished_at): JobRow,
+) -> Result<Job> {
+    Ok(Job {
+        id,
+        kind: JobKind::parse(&kind)?,
+        spec: serde_json::from_str(&spec).context("Bad job spec")?,
+        state: JobState::parse(&state)?,
+        error,
+        created_at,
+        started_at,
+        finished_at,
+    })
+}
+
+/// Reads an event from `seq, at, name, payload` columns
+fn event_row(job_id: i64, row: &rusqlite::Row) -> rusqlite::Result<JobEvent> {
+    let payload: String = row.get(3)?;
+    Ok(JobEvent {
+        job_id,
+        seq: row.get(0)?,
+        at: row.get(1)?,
+        name: row.get(2)?,
+        payload: serde_json::from_str(&payload).unwrap_or_default(),
+    })
+}
+
+pub struct JobStore {
+    conn: Connection,
+}
+
+impl JobStore {
+    /// Opens the store, marking jobs left running by the last session as interrupted
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let conn = Connection::open(path).context("Failed to open jobs database")?;
+        let store = Self::init(conn)?;
+        store.interrupt_running()?;
+        Ok(store)
+    }
+
+    pub fn open_in_memory() -> Result<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Result<Self> {
+        conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+        conn.execute_batch(SCHEMA)
+            .context("Failed to create job tables")?;
+        Ok(JobStore { conn })
+    }
+
+    fn interrupt_running(&self) -> Result<()> {
+        let running: Vec<i64> = self
+            .conn
+            .prepare("SELECT id FROM jobs WHERE state = 'running'")?
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        for id in running {
+            self.set_state(id, JobState::Interrupted, None)?;
+        }
+        Ok(())
+    }
+
+    /// Queues a job, returning it along with the logged `state` event
+    pub fn submit(&self, kind: JobKind, spec: &serde_json::Value) -> Result<(Job, JobEvent)> {
+        self.conn.execute(
+            "INSERT INTO jobs (kind, spec, state, created_at) VALUES (?1, ?2, 'queued', ?3)",
+            params![kind.as_str(), spec.to_string(), now()],
+        )?;
+        let id = self.conn.last_insert_rowid();
+        let queued = self.record(
+            id,
+            "state",
+            &serde_json::json!({ "state": JobState::Queued, "error": null }),
+        )?;
+        Ok((self.job(id)?.context("Job vanished")?, queued))
+    }
+
+    pub fn job(&self, id: i64) -> Result<Option<Job>> {
+        self.conn
+            .query_row(
+                &format!("SELECT {} FROM jobs WHERE id = ?1", JOB_COLUMNS),
+                [id],
+                job_row,
+            )
+            .optional()?
+            .map(job_from_row)
+            .transpose()
+    }
+
+    /// Lists jobs, newest first, optionally only those in one state
+    pub fn jobs(&self, state: Option<JobState>) -> Result<Vec<Job>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM jobs WHERE (?1 IS NULL OR state = ?1) ORDER BY id DESC",
+            JOB_COLUMNS
+        ))?;
+        let rows = stmt
+            .query_map([state.map(JobState::as_str)], job_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        rows.into_iter().map(job_from_row).collect()
+    }
+
+    /// Marks the oldest queued job as running and returns it, with the logged `state` event
+    pub fn start_next(&self) -> Result<Option<(Job, JobEvent)>> {
+        let next: Option<i64> = self
+            .conn
+            .query_row(
+                "SELECT id FROM jobs WHERE state = 'queued' ORDER BY id LIMIT 1",
+                [],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let Some(id) = next else {
+            return Ok(None);
+        };
+        let started = self.set_state(id, JobState::Running, None)?;
+        Ok(self.job(id)?.map(|job| (job, started)))
+    }
+
+    /// Moves a job to `state`, logging the change as a `state` event, which is returned
+    pub fn set_state(&self, id: i64, state: JobState, error: Option<&str>) -> Result<JobEvent> {
+        match state {
+            JobState::Queued => self.conn.execute(
+                "UPDATE jobs SET state = 'queued', error = NULL, started_at = NULL,
+                     finished_at = NULL
+                 WHERE id = ?1",
+                [id],
+            )?,
+            JobState::Running => self.conn.execute(
+                "UPDATE jobs SET state = 'running', error = NULL, started_at = ?1,
+                     finished_at = NULL
+                 WHERE id = ?2",
+                params![now(), id],
+            )?,
+            JobState::Done | JobState::Failed | JobState::Interrupted => self.conn.execute(
+                "UPDATE jobs SET state = ?1, error = ?2, finished_at = ?3 WHERE id = ?4",
+                params![state.as_str(), error, now(), id],
+            )?,
+        };
+        self.record(
+            id,
+            "state",
+            &serde_json::json!({ "state": state, "error": error }),
+        )
+    }
+
+    fn next_seq(&self, id: i64) -> Result<i64> {
+        Ok(self.conn.query_row(
+            "SELECT COALESCE(MAX(seq), 0) + 1 FROM job_events WHERE job_id = ?1",
+            [id],
+            |row| row.get(0),
+        )?)
+    }
+
+    fn insert_event(
+        &self,
+        id: i64,
+        seq: i64,
+        name: &str,
+        payload: &serde_json::Value,
+    ) -> Result<JobEvent> {
+        let at = now();
+        self.conn.execute(
+            "INSERT INTO job_events (job_id, seq, at, name, payload) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![id, seq, at, name, payload.to_string()],
+        )?;
+        Ok(JobEvent {
+            job_id: id,
+            seq,
+            at,
+            name: name.to_string(),
+            payload: payload.clone(),
+        })
+    }
+
+    /// Appends an event to the job's log
+    pub fn record(&self, id: i64, name: &str, payload: &serde_json::Value) -> Result<JobEvent> {
+        let tx = self.conn.unchecked_transaction()?;
+        let event = self.insert_event(id, self.next_seq(id)?, name, payload)?;
+        tx.commit()?;
+        Ok(event)
+    }
+
+    /// Logs a progress report in place of the job's last one with the same name, so the log
+    /// holds the state changes and only the latest of each kind of progress. It still gets a
+    /// number after everything logged so far.
+    pub fn record_progress(
+        &self,
+        id: i64,
+        name: &str,
+        payload: &serde_json::Value,
+    ) -> Result<JobEvent> {
+        let tx = self.conn.unchecked_transaction()?;
+        let seq = self.next_seq(id)?;
+        tx.execute(
+            "DELETE FROM job_events WHERE job_id = ?1 AND name = ?2",
+            params![id, name],
+        )?;
+        let event = self.insert_event(id, seq, name, payload)?;
+        tx.commit()?;
+        Ok(event)
+    }
+
+    /// The job's most recent progress report, if it has made any
+    pub fn latest_progress(&self, id: i64) -> Result<Option<JobEvent>> {
+        self.conn
+            .query_row(
+                "SELECT seq, at, name, payload FROM job_events
+                 WHERE job_id = ?1 AND name != 'state' ORDER BY seq DESC LIMIT 1",
+                [id],
+                |row| event_row(id, row),
+            )
+            .optional()
+            .map_err(Into::into)
+    }
+
+    /// The job's events in order, after `after_seq` when given, to replay what was missed
+    pub fn events(&self, id: i64, after_seq: Option<i64>) -> Result<Vec<JobEvent>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT seq, at, name, payload FROM job_events
+             WHERE job_id = ?1 AND seq > ?2 ORDER BY seq",
+        )?;
+        let events = stmt
+            .query_map(params![id, after_seq.unwrap_or(0)], |row| {
+                event_row(id, row)
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(events)
+    }
+
+    /// Queues an interrupted or failed job to run again from the start
+    pub fn resume(&self, id: i64) -> Result<JobEvent> {
+        let job = self.job(id)?.context("No such job")?;
+        if !matches!(job.state, JobState::Interrupted | JobState::Failed) {
+            return Err(anyhow::anyhow!(
+                "Only interrupted or failed jobs can be resumed"
+            ));
+        }
+        self.set_state(id, JobState::Queued, None)
+    }
+
+    /// Forgets a job that isn't running, along with its events
+    pub fn discard(&self, id: i64) -> Result<()> {
+        let deleted = self.conn.execute(
+            "DELETE FROM jobs WHERE id = ?1 AND state != 'running'",
+            [id],
+        )?;
+        if deleted == 0 {
+            return Err(anyhow::anyhow!("No such job, or it's still running"));
+        }
+        Ok(())
+    }
+}
+
+/// Thins out a running job's progress reports, so each kind is logged at most once every
+/// `interval`. The latest report of each kind that was held back is kept, to be logged when
+/// the job ends, so the log doesn't leave a finished job partway through.
+pub struct ProgressThrottle {
+    interval: Duration,
+    last_logged: HashMap<String, Instant>,
+    /// Reports not logged yet, the most recently held back last
+    held_back: Vec<(String, serde_json::Value)>,
+}
+
+impl ProgressThrottle {
+    pub fn new(interval: Duration) -> Self {
+        ProgressThrottle {
+            interval,
+            last_logged: HashMap::new(),
+            held_back: Vec::new(),
+        }
+    }
+
+    /// Gives back the report if it should be logged now, and otherwise holds on to it.
+    /// With `always`, it's logged however recently the last one was.
+    pub fn offer(
+        &mut self,
+        name: &str,
+        payload: serde_json::Value,
+        always: bool,
+    ) -> Option<serde_json::Value> {
+        self.held_back.retain(|(held, _)| held != name);
+        let recent = self
+            .last_logged
+            .get(name)
+            .is_some_and(|at| at.elapsed() < self.interval);
+        if recent && !always {
+            self.held_back.push((name.to_string(), payload));
+            return None;
+        }
+        self.last_logged.insert(name.to_string(), Instant::now());
+        Some(payload)
+    }
+
+    /// The reports held back since each kind was last logged, in the order they came in
+    pub fn take_held_back(&mut self) -> Vec<(String, serde_json::Value)> {
+        std::mem::take(&mut self.held_back)
+    }
+}
+
+#[test]
+fn test_jobs_survive_a_restart_as_interrupted() {
+    let path = std::env::temp_dir().join("sync-and-swim-jobs-test.sqlite3");
+    let _ = std::fs::remove_file(&path);
+    let spec = serde_json::json!({ "kind": "split", "destPath": "/parts" });
+
+    let store = JobStore::open(&path).unwrap();
+    let (first, _) = store.submit(JobKind::Split, &spec).unwrap();
+    let (second, _) = store.submit(JobKind::Copy, &spec).unwrap();
+    assert_eq!(first.state, JobState::Queued);
+    let (started, event) = store.start_next().unwrap().unwrap();
+    assert_eq!((started.id, started.state), (first.id, JobState::Running));
+    assert_eq!(event.seq, 2);
+    for progress in [50.0, 60.0] {
+        store
+            .record_progress(
+                first.id,
+                "segment-progress",
+                &serde_json::json!({ "progress": progress }),
+            )
+            .unwrap();
+    }
+    drop(store);
+
+    let store = JobStore::open(&path).unwrap();
+    let first = store.job(first.id).unwrap().unwrap();
+    assert_eq!(first.state, JobState::Interrupted);
+    assert_eq!(first.spec, spec);
+    let names: Vec<String> = store
+        .events(first.id, None)
+        .unwrap()
+        .into_iter()
+        .map(|e| e.name)
+        .collect();
+    // Only the latest progress is kept
+    assert_eq!(names, ["state", "state", "segment-progress", "state"]);
+    assert_eq!(store.events(first.id, Some(4)).unwrap().len(), 1);
+    let latest = store.latest_progress(first.id).unwrap().unwrap();
+    assert_eq!(
+        (latest.seq, latest.payload["progress"].as_f64()),
+        (4, Some(60.0))
+    );
+
+    // The queue carries on with the job that never started
+    assert_eq!(store.start_next().unwrap().unwrap().0.id, second.id);
+    assert!(store.discard(second.id).is_err());
+    store.resume(first.id).unwrap();
+    assert_eq!(store.jobs(Some(JobState::Queued)).unwrap()[0].id, first.id);
+    store.discard(first.id).unwrap();
+    assert!(store.job(first.id).unwrap().is_none());
+    assert!(store.events(first.id, None).unwrap().is_empty());
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_throttled_progress_ends_on_the_final_report() {
+    let store = JobStore::open_in_memory().unwrap();
+    let (job, _) = store
+        .submit(JobKind::Copy, &serde_json::json!({ "kind": "copy" }))
+        .unwrap();
+    store.start_next().unwrap();
+    let mut throttle = ProgressThrottle::new(Duration::from_secs(3600));
+    let mut log = |name: &str, payload: serde_json::Value, always: bool| {
+        if let Some(payload) = throttle.offer(name, payload, always) {
+            store.record_progress(job.id, name, &payload).unwrap();
+        }
+    };
+
+    log("copy-progress", serde_json::json!({ "finished": 0 }), false);
+    // Each kind is thinned out on its own, so splitting doesn't hold back copying
+    log(
+        "segment-progress",
+        serde_json::json!({ "progress": 50 }),
+        false,
+    );
+    log(
+        "segment-progress",
+        serde_json::json!({ "progress": 100 }),
+        false,
+    );
+    log("copy-progress", serde_json::json!({ "finished": 1 }), true);
+    log("copy-progress", serde_json::json!({ "finished": 2 }), false);
+    let latest = store.latest_progress(job.id).unwrap().unwrap();
+    assert_eq!(latest.payload["finished"], 1);
+
+    for (name, payload) in throttle.take_held_back() {
+        store.record_progress(job.id, &name, &payload).unwrap();
+    }
+    let progress: HashMap<String, serde_json::Value> = store
+        .events(job.id, None)
+        .unwrap()
+        .into_iter()
+        .filter(|e| e.name != "state")
+        .map(|e| (e.name, e.payload))
+        .collect();
+    assert_eq!(progress["segment-progress"]["progress"], 100);
+    assert_eq!(progress["copy-progress"]["finished"], 2);
+    let latest = store.latest_progress(job.id).unwrap().unwrap();
+    assert_eq!(latest.payload["finished"], 2);
+    assert!(throttle.take_held_back().is_empty());
+}
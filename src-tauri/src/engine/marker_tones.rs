@@ -0,0 +1,202 @@
+//! Short generated beeps played between folders, so you can hear where one book ends and
+//! the next begins.
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+use super::files::AudioFile;
+use super::find_ffmpeg::FfmpegTools;
+use super::process::ProcessRunner;
+
+/// Name given to each cue on the device. The leading zeros sort it ahead of the folder's
+/// own tracks on players that go by name.
+const CUE_FILE_NAME: &str = "000 Next folder.mp3";
+
+/// Pitches cycled through by [`MarkerTones::Pattern`], in Hz
+const PATTERN_FREQUENCIES: [u32; 4] = [523, 659, 784, 988];
+
+/// Time from the start of one beep to the start of the next, in seconds
+const BEEP_PERIOD: f64 = 0.4;
+const BEEP_LENGTH: f64 = 0.15;
+const MAX_BEEPS: u32 = 20;
+
+/// Which cue to play before each folder after the first
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum MarkerTones {
+    #[default]
+    None,
+    /// A pitch and rhythm that differs from one folder to the next
+    Pattern,
+    /// As many beeps as the folder's number: two before the second folder, and so on
+    Count,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tone {
+    pub frequency: u32,
+    pub beeps: u32,
+}
+
+impl Tone {
+    fn file_name(&self) -> String {
+        format!("tone-{}hz-{}.mp3", self.frequency, self.beeps)
+    }
+}
+
+fn tone_for(style: MarkerTones, folder_number: usize) -> Option<Tone> {
+    match style {
+        MarkerTones::None => None,
+        MarkerTones::Pattern => {
+            let i = folder_number - 1;
+            Some(Tone {
+                frequency: PATTERN_FREQUENCIES[i % PATTERN_FREQUENCIES.len()],
+                beeps: 1 + (i / PATTERN_FREQUENCIES.len()) as u32 % 3,
+            })
+        }
+        MarkerTones::Count => Some(Tone {
+            frequency: 880,
+            beeps: (folder_number as u32).min(MAX_BEEPS),
+        }),
+    }
+}
+
+/// Finds where each folder group after the first starts in the copy order, with the tone
+/// that should play there
+fn marker_positions(files: &[AudioFile], style: MarkerTones) -> Vec<(usize, Tone)> {
+    let mut positions = Vec::new();
+    let mut folder_number = 1;
+    for (i, pair) in files.windows(2).enumerate() {
+        if pair[0].relative_path != pair[1].relative_path {
+            folder_number += 1;
+            if let Some(tone) = tone_for(style, folder_number) {
+                positions.push((i + 1, tone));
+            }
+        }
+    }
+    positions
+}
+
+/// Synthesizes a tone with ffmpeg's sine source, gated into beeps
+async fn generate_tone<R: ProcessRunner>(
+    tools: &FfmpegTools<R>,
+    tone: Tone,
+    output: &Path,
+) -> Result<()> {
+    let duration = tone.beeps as f64 * BEEP_PERIOD + 0.3;
+    let output = tools
+        .runner
+        .output(
+            &tools.ffmpeg,
+            &[
+                "-y".to_string(),
+                "-f".to_string(),
+                "lavfi".to_string(),
+                "-i".to_string(),
+                format!(
+                    "sine=frequency={}:sample_rate=44100:duration={}",
+                    tone.frequency, duration
+                ),
+                "-af".to_string(),
+                format!(
+                    "volume='0.6*lt(mod(t,{}),{})*lt(t,{})':eval=frame",
+                    BEEP_PERIOD,
+                    BEEP_LENGTH,
+                    tone.beeps as f64 * BEEP_PERIOD
+                ),
+                "-c:a".to_string(),
+                "libmp3lame".to_string(),
+                "-b:a".to_string(),
+                "128k".to_string(),
+                output.to_string_lossy().to_string(),
+            ],
+        )
+        .await
+        .context("Failed to execute ffmpeg")?;
+    if !output.success {
+        return Err(anyhow!(
+            "ffmpeg failed to generate a marker tone: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    Ok(())
+}
+
+/// Returns `files` with a cue inserted at the start of each folder after the first. Tones
+/// are generated into `cue_dir` and reused from there on later loads.
+pub async fn insert_marker_tones<R: ProcessRunner>(
+    tools: &FfmpegTools<R>,
+    files: Vec<AudioFile>,
+    style: MarkerTones,
+    cue_dir: &Path,
+) -> Result<Vec<AudioFile>> {
+    let positions = marker_positions(&files, style);
+    if positions.is_empty() {
+        return Ok(files);
+    }
+    fs::create_dir_all(cue_dir)?;
+
+    let mut with_cues = Vec::with_capacity(files.len() + positions.len());
+    let mut positions = positions.into_iter().peekable();
+    for (i, file) in files.into_iter().enumerate() {
+        if let Some((_, tone)) = positions.next_if(|(position, _)| *position == i) {
+            let path = cue_dir.join(tone.file_name());
+            if !path.exists() {
+                generate_tone(tools, tone, &path).await?;
+            }
+            with_cues.push(AudioFile {
+                name: CUE_FILE_NAME.to_string(),
+                path: path.to_string_lossy().to_string(),
+                relative_path: file.relative_path.clone(),
+                media: None,
+            });
+        }
+        with_cues.push(file);
+    }
+    Ok(with_cues)
+}
+
+#[test]
+fn test_marker_positions_at_each_new_folder() {
+    let file = |folder: &str, name: &str| AudioFile {
+        name: name.to_string(),
+        path: format!("/staging/{}/{}", folder, name),
+        relative_path: folder.to_string(),
+        media: None,
+    };
+    let files = vec![
+        file("Book", "1.mp3"),
+        file("Book", "2.mp3"),
+        file("Podcast", "a.mp3"),
+        file("Music", "x.mp3"),
+        file("Music", "y.mp3"),
+    ];
+
+    assert_eq!(marker_positions(&files, MarkerTones::None), vec![]);
+    assert_eq!(
+        marker_positions(&files, MarkerTones::Count),
+        vec![
+            (
+                2,
+                Tone {
+                    frequency: 880,
+                    beeps: 2
+                }
+            ),
+            (
+                3,
+                Tone {
+                    frequency: 880,
+                    beeps: 3
+                }
+            ),
+        ]
+    );
+    let pattern: Vec<u32> = marker_positions(&files, MarkerTones::Pattern)
+        .into_iter()
+        .map(|(_, tone)| tone.frequency)
+        .collect();
+    assert_eq!(pattern, vec![659, 784]);
+}
@@ -0,0 +1,541 @@
+//! What's in an audio file: its length, format, tags and chapters. Read with ffprobe when
+//! it's installed, and otherwise from the description `ffmpeg -i` prints or by reading the
+//! file natively.
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use symphonia::core::codecs::CODEC_TYPE_NULL;
+use symphonia::core::errors::Error as DecodeError;
+use symphonia::core::formats::{FormatOptions, FormatReader};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::{MetadataOptions, MetadataRevision, StandardTagKey};
+use symphonia::core::probe::{Hint, ProbeResult};
+
+use super::audio_segment::{probed_chapters, Chapter, ProbedChapter};
+use super::files::AudioFile;
+use super::find_ffmpeg::FfmpegTools;
+use super::process::ProcessRunner;
+
+/// Everything the app can tell about a file's audio
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct MediaInfo {
+    /// Seconds
+    pub duration: Option<f64>,
+    /// The first audio stream's codec, as ffmpeg names it
+    pub codec: Option<String>,
+    /// Bits per second
+    pub bit_rate: Option<u64>,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u32>,
+    /// Tags like `title`, `artist` and `album`, with lowercase names
+    pub tags: BTreeMap<String, String>,
+    pub chapters: Vec<Chapter>,
+    /// Size of the embedded cover art, when there is some
+    pub art_bytes: Option<u64>,
+}
+
+/// What probing one of several files found
+#[derive(Debug, Serialize, Clone)]
+pub struct ProbedFile {
+    pub path: String,
+    pub info: Option<MediaInfo>,
+    pub error: Option<String>,
+}
+
+/// An input as ffmpeg describes it
+#[derive(Debug, Default, PartialEq)]
+pub struct InputInfo {
+    /// Seconds, unless ffmpeg printed `N/A`
+    pub duration: Option<f64>,
+    /// The first audio stream's codec, as ffmpeg names it
+    pub codec: Option<String>,
+    pub chapters: Vec<Chapter>,
+}
+
+/// Seconds in an `HH:MM:SS.ss` timestamp
+pub(super) fn parse_timestamp(timestamp: &str) -> Option<f64> {
+    let mut seconds = 0.0;
+    for part in timestamp.split(':') {
+        seconds = seconds * 60.0 + part.parse::<f64>().ok()?;
+    }
+    Some(seconds)
+}
+
+/// Reads the description of the input from ffmpeg's stderr
+fn parse_ffmpeg_input(stderr: &str) -> InputInfo {
+    let mut info = InputInfo::default();
+    let mut titles: Vec<Option<String>> = Vec::new();
+    // Metadata lines belong to the latest chapter until the streams start
+    let mut in_chapters = false;
+    for line in stderr.lines().map(str::trim) {
+        if let Some(rest) = line.strip_prefix("Duration:") {
+            let timestamp = rest.split(',').next().unwrap_or("").trim();
+            info.duration = parse_timestamp(timestamp);
+        } else if let Some(rest) = line.strip_prefix("Chapter #") {
+            in_chapters = true;
+            let times = rest.split_once(": ").map(|(_, times)| times).unwrap_or("");
+            let time = |name: &str| {
+                times
+                    .split(',')
+                    .find_map(|t| t.trim().strip_prefix(name))
+                    .and_then(|t| t.trim().parse::<f64>().ok())
+            };
+            if let (Some(start), Some(end)) = (time("start"), time("end")) {
+                info.chapters.push(Chapter {
+                    start,
+                    end,
+                    title: String::new(),
+                });
+                titles.push(None);
+            }
+        } else if line.starts_with("Stream #") {
+            in_chapters = false;
+            if info.codec.is_none() {
+                if let Some((_, rest)) = line.split_once("Audio: ") {
+                    info.codec = rest
+                        .split([' ', ','])
+                        .next()
+                        .filter(|codec| !codec.is_empty())
+                        .map(str::to_string);
+                }
+            }
+        } else if in_chapters {
+            if let Some((key, value)) = line.split_once(':') {
+                if key.trim() == "title" && !value.trim().is_empty() {
+                    if let Some(title) = titles.last_mut() {
+                        *title = Some(value.trim().to_string());
+                    }
+                }
+            }
+        }
+    }
+    for (i, (chapter, title)) in info.chapters.iter_mut().zip(titles).enumerate() {
+        chapter.title = title.unwrap_or_else(|| format!("Chapter {}", i + 1));
+    }
+    info
+}
+
+/// Asks ffmpeg to describe the input. With no output given ffmpeg exits with an error,
+/// but only after printing the description.
+pub(super) async fn describe_with_ffmpeg<R: ProcessRunner>(
+    tools: &FfmpegTools<R>,
+    input_filename: &str,
+) -> Result<InputInfo> {
+    let output = tools
+        .runner
+        .output(
+            &tools.ffmpeg,
+            &[
+                "-hide_banner".to_string(),
+                "-i".to_string(),
+                input_filename.to_string(),
+            ],
+        )
+        .await
+        .context("Failed to execute ffmpeg")?;
+    Ok(parse_ffmpeg_input(&String::from_utf8_lossy(&output.stderr)))
+}
+
+fn open_native(path: &Path) -> Result<ProbeResult> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let source = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(extension);
+    }
+    symphonia::default::get_probe()
+        .format(
+            &hint,
+            source,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .context("Unsupported audio format")
+}
+
+/// The length of the format's first audio track, from its headers or, when they don't say,
+/// by reading through every packet
+fn track_duration(format: &mut dyn FormatReader) -> Result<f64> {
+    let track = format
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .context("No audio track found")?;
+    let track_id = track.id;
+    let time_base = track
+        .codec_params
+        .time_base
+        .context("Audio track has no time base")?;
+    let frames = match track.codec_params.n_frames {
+        Some(frames) => frames,
+        None => {
+            let mut frames = 0;
+            loop {
+                match format.next_packet() {
+                    Ok(packet) if packet.track_id() == track_id => frames += packet.dur,
+                    Ok(_) => {}
+                    Err(DecodeError::IoError(e))
+                        if e.kind() == std::io::ErrorKind::UnexpectedEof =>
+                    {
+                        break
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            frames
+        }
+    };
+    let time = time_base.calc_time(frames);
+    let seconds = time.seconds as f64 + time.frac;
+    if seconds <= 0.0 {
+        return Err(anyhow!("Could not tell the length"));
+    }
+    Ok(seconds)
+}
+
+/// The length of the file's first audio track, read natively
+pub fn native_duration(path: &Path) -> Result<f64> {
+    let mut probed = open_native(path)?;
+    track_duration(probed.format.as_mut())
+        .with_context(|| format!("Could not tell the length of {}", path.display()))
+}
+
+/// `native_duration` on a blocking thread, since files without a length in their headers
+/// are read to the end
+pub(super) async fn native_duration_in_background(path: &str) -> Result<f64> {
+    let path = PathBuf::from(path);
+    tokio::task::spawn_blocking(move || native_duration(&path)).await?
+}
+
+/// The tag names ffprobe uses for the common tags
+fn tag_name(key: Option<StandardTagKey>, raw: &str) -> String {
+    match key {
+        Some(StandardTagKey::TrackTitle) => "title".to_string(),
+        Some(StandardTagKey::Artist) => "artist".to_string(),
+        Some(StandardTagKey::Album) => "album".to_string(),
+        Some(StandardTagKey::AlbumArtist) => "album_artist".to_string(),
+        Some(StandardTagKey::TrackNumber) => "track".to_string(),
+        Some(StandardTagKey::DiscNumber) => "disc".to_string(),
+        Some(StandardTagKey::Date) => "date".to_string(),
+        Some(StandardTagKey::Genre) => "genre".to_string(),
+        Some(StandardTagKey::Comment) => "comment".to_string(),
+        Some(StandardTagKey::Composer) => "composer".to_string(),
+        _ => raw.to_lowercase(),
+    }
+}
+
+fn add_revision(info: &mut MediaInfo, revision: &MetadataRevision) {
+    for tag in revision.tags() {
+        info.tags
+            .entry(tag_name(tag.std_key, &tag.key))
+            .or_insert_with(|| tag.value.to_string());
+    }
+    if let Some(bytes) = art_bytes(revision) {
+        info.art_bytes.get_or_insert(bytes);
+    }
+}
+
+/// The size of the first picture in a set of tags, usually the cover
+fn art_bytes(revision: &MetadataRevision) -> Option<u64> {
+    revision
+        .visuals()
+        .first()
+        .map(|visual| visual.data.len() as u64)
+}
+
+/// The size of the file's cover art, read from its tags alone, without measuring its length
+fn native_art_bytes(path: &Path) -> Result<Option<u64>> {
+    let mut probed = open_native(path)?;
+    let before_container = probed
+        .metadata
+        .get()
+        .as_ref()
+        .and_then(|m| m.current())
+        .and_then(art_bytes);
+    Ok(before_container.or_else(|| probed.format.metadata().current().and_then(art_bytes)))
+}
+
+/// Reads the file's headers and tags, without ffmpeg. Chapters aren't read this way.
+pub fn native_media_info(path: &Path) -> Result<MediaInfo> {
+    let mut probed = open_native(path)?;
+    let mut info = MediaInfo::default();
+    if let Some(params) = probed
+        .format
+        .tracks()
+        .iter()
+        .map(|t| &t.codec_params)
+        .find(|params| params.codec != CODEC_TYPE_NULL)
+    {
+        info.codec = symphonia::default::get_codecs()
+            .get_codec(params.codec)
+            .map(|codec| codec.short_name.to_string());
+        info.sample_rate = params.sample_rate;
+        info.channels = params.channels.map(|c| c.count() as u32);
+    }
+    // Tags read before the container, like ID3v2, then the container's own
+    if let Some(revision) = probed.metadata.get().as_ref().and_then(|m| m.current()) {
+        add_revision(&mut info, revision);
+    }
+    if let Some(revision) = probed.format.metadata().current() {
+        add_revision(&mut info, revision);
+    }
+    info.duration = track_duration(probed.format.as_mut()).ok();
+    if let Some(duration) = info.duration {
+        let bytes = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+        info.bit_rate = Some((bytes as f64 * 8.0 / duration) as u64);
+    }
+    Ok(info)
+}
+
+#[derive(Deserialize)]
+struct ProbedMedia {
+    #[serde(default)]
+    streams: Vec<ProbedStream>,
+    #[serde(default)]
+    chapters: Vec<ProbedChapter>,
+    format: Option<ProbedFormat>,
+}
+
+#[derive(Deserialize)]
+struct ProbedStream {
+    codec_type: Option<String>,
+    codec_name: Option<String>,
+    sample_rate: Option<String>,
+    channels: Option<u32>,
+    bit_rate: Option<String>,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct ProbedFormat {
+    duration: Option<String>,
+    bit_rate: Option<String>,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+}
+
+/// Reads `ffprobe -print_format json -show_format -show_streams -show_chapters` output
+fn parse_ffprobe_json(json: &[u8]) -> Result<MediaInfo> {
+    let probed: ProbedMedia =
+        serde_json::from_slice(json).context("Failed to parse ffprobe output")?;
+    let audio = probed
+        .streams
+        .iter()
+        .find(|s| s.codec_type.as_deref() == Some("audio"))
+        .context("No audio stream found")?;
+    let format = probed.format.as_ref();
+    let mut tags = BTreeMap::new();
+    // Some containers (Ogg, for one) tag the stream rather than the file
+    for (key, value) in format
+        .map(|f| &f.tags)
+        .into_iter()
+        .chain([&audio.tags])
+        .flatten()
+    {
+        tags.entry(key.to_lowercase())
+            .or_insert_with(|| value.clone());
+    }
+    Ok(MediaInfo {
+        duration: format.and_then(|f| f.duration.as_ref()?.parse().ok()),
+        codec: audio.codec_name.clone(),
+        bit_rate: format
+            .and_then(|f| f.bit_rate.as_ref())
+            .or(audio.bit_rate.as_ref())
+            .and_then(|rate| rate.parse().ok()),
+        sample_rate: audio.sample_rate.as_ref().and_then(|r| r.parse().ok()),
+        channels: audio.channels,
+        tags,
+        chapters: probed_chapters(probed.chapters)?,
+        art_bytes: None,
+    })
+}
+
+async fn ffprobe_media_info<R: ProcessRunner>(
+    tools: &FfmpegTools<R>,
+    ffprobe: &Path,
+    path: &str,
+) -> Result<MediaInfo> {
+    let output = tools
+        .runner
+        .output(
+            ffprobe,
+            &[
+                "-v".to_string(),
+                "error".to_string(),
+                "-print_format".to_string(),
+                "json".to_string(),
+                "-show_format".to_string(),
+                "-show_streams".to_string(),
+                "-show_chapters".to_string(),
+                path.to_string(),
+            ],
+        )
+        .await
+        .context("Failed to execute ffprobe")?;
+    if !output.success {
+        return Err(anyhow!(
+            "ffprobe couldn't read {}: {}",
+            path,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    let mut info = parse_ffprobe_json(&output.stdout)?;
+    // ffprobe lists cover art as a stream but doesn't say how big it is
+    let path = PathBuf::from(path);
+    info.art_bytes = tokio::task::spawn_blocking(move || native_art_bytes(&path))
+        .await
+        .ok()
+        .and_then(Result::ok)
+        .flatten();
+    Ok(info)
+}
+
+/// Describes a file with ffprobe when there is one. Without it, or when it can't read the
+/// file, the file is read natively, falling back to ffmpeg's description for formats the
+/// app can't read itself.
+pub async fn probe_file<R: ProcessRunner>(
+    tools: Option<&FfmpegTools<R>>,
+    path: &str,
+) -> Result<MediaInfo> {
+    if let Some((tools, ffprobe)) = tools.and_then(|t| Some((t, t.ffprobe.as_ref()?))) {
+        match ffprobe_media_info(tools, ffprobe, path).await {
+            Ok(info) => return Ok(info),
+            Err(e) => log::info!("Probing {} without ffprobe: {:#}", path, e),
+        }
+    }
+    // Reading natively can mean reading the whole file, for formats without a length in
+    // their headers
+    let native_path = PathBuf::from(path);
+    let native = tokio::task::spawn_blocking(move || native_media_info(&native_path)).await?;
+    match (native, tools) {
+        (Ok(info), _) => Ok(info),
+        (Err(e), None) => Err(e),
+        (Err(_), Some(tools)) => {
+            let described = describe_with_ffmpeg(tools, path).await?;
+            Ok(MediaInfo {
+                duration: described.duration,
+                codec: described.codec,
+                chapters: described.chapters,
+                ..MediaInfo::default()
+            })
+        }
+    }
+}
+
+/// Probes each file, noting which couldn't be read
+pub async fn probe_files<R: ProcessRunner>(
+    tools: Option<&FfmpegTools<R>>,
+    paths: &[String],
+) -> Vec<ProbedFile> {
+    let mut probed = Vec::with_capacity(paths.len());
+    for path in paths {
+        let (info, error) = match probe_file(tools, path).await {
+            Ok(info) => (Some(info), None),
+            Err(e) => (None, Some(format!("{:#}", e))),
+        };
+        probed.push(ProbedFile {
+            path: path.clone(),
+            info,
+            error,
+        });
+    }
+    probed
+}
+
+/// Fills in each file's `media`, leaving it empty for files that can't be read
+pub async fn with_media_info<R: ProcessRunner>(
+    tools: Option<&FfmpegTools<R>>,
+    mut files: Vec<AudioFile>,
+) -> Vec<AudioFile> {
+    for file in &mut files {
+        match probe_file(tools, &file.path).await {
+            Ok(info) => file.media = Some(Box::new(info)),
+            Err(e) => log::info!("Couldn't probe {}: {:#}", file.path, e),
+        }
+    }
+    files
+}
+
+/// The input's length, for when there's no ffprobe to ask
+pub(super) async fn duration_without_ffprobe<R: ProcessRunner>(
+    tools: &FfmpegTools<R>,
+    input_filename: &str,
+) -> Result<f64> {
+    match describe_with_ffmpeg(tools, input_filename).await {
+        Ok(InputInfo {
+            duration: Some(duration),
+            ..
+        }) => Ok(duration),
+        _ => native_duration_in_background(input_filename).await,
+    }
+}
+
+#[test]
+fn test_parse_ffmpeg_input_description() {
+    let stderr = "Input #0, mov,mp4,m4a,3gp,3g2,mj2, from 'book.m4b':
+  Metadata:
+    title           : The Whole Book
+  Duration: 01:02:03.45, start: 0.000000, bitrate: 64 kb/s
+  Chapters:
+    Chapter #0:0: start 0.000000, end 1800.000000
+      Metadata:
+        title           : Part One
+    Chapter #0:1: start 1800.000000, end 3723.450000
+  Stream #0:0[0x1](und): Audio: aac (LC) (mp4a / 0x6134706D), 44100 Hz, stereo, fltp, 64 kb/s (default)
+      Metadata:
+        title           : Stereo
+At least one output file must be specified
+";
+    let info = parse_ffmpeg_input(stderr);
+    assert!((info.duration.unwrap() - 3723.45).abs() < 1e-9);
+    assert_eq!(info.codec.as_deref(), Some("aac"));
+    assert_eq!(
+        info.chapters,
+        vec![
+            Chapter {
+                start: 0.0,
+                end: 1800.0,
+                title: "Part One".to_string()
+            },
+            Chapter {
+                start: 1800.0,
+                end: 3723.45,
+                title: "Chapter 2".to_string()
+            },
+        ]
+    );
+
+    let wav = parse_ffmpeg_input(
+        "Input #0, wav, from 'a.wav':\n  Duration: N/A, bitrate: 1411 kb/s\n  Stream #0:0: Audio: pcm_s16le ([1][0][0][0] / 0x0001), 44100 Hz, 2 channels, s16, 1411 kb/s\n",
+    );
+    assert_eq!(wav.duration, None);
+    assert_eq!(wav.codec.as_deref(), Some("pcm_s16le"));
+}
+
+#[test]
+fn test_parse_ffprobe_json() {
+    let json = br#"{
+        "streams": [
+            {"codec_type": "audio", "codec_name": "aac", "sample_rate": "44100", "channels": 1,
+             "bit_rate": "63999", "tags": {"language": "eng"}},
+            {"codec_type": "video", "codec_name": "mjpeg", "disposition": {"attached_pic": 1}}
+        ],
+        "chapters": [
+            {"start_time": "0.000000", "end_time": "600.000000", "tags": {"title": "Opening"}}
+        ],
+        "format": {"duration": "600.023220", "bit_rate": "65021",
+                   "tags": {"TITLE": "A Book", "artist": "Someone"}}
+    }"#;
+    let info = parse_ffprobe_json(json).unwrap();
+    assert_eq!(info.codec.as_deref(), Some("aac"));
+    assert_eq!(info.duration, Some(600.02322));
+    assert_eq!(info.bit_rate, Some(65021));
+    assert_eq!((info.sample_rate, info.channels), (Some(44100), Some(1)));
+    assert_eq!(info.tags["title"], "A Book");
+    assert_eq!(info.tags["language"], "eng");
+    assert_eq!(info.chapters[0].title, "Opening");
+}
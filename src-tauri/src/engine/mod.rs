@@ -0,0 +1,31 @@
+//! Scanning, planning, splitting and copying, with no dependency on Tauri.
+//!
+//! Long-running operations report through a [`ProgressSink`] and run ffmpeg through a
+//! [`ProcessRunner`]. The Tauri commands and the CLI are thin adapters over this module.
+
+pub mod audio_format;
+pub mod audio_segment;
+pub mod device_report;
+pub mod disk_image;
+pub mod encoding;
+pub mod feeds;
+pub mod ffmpeg_capabilities;
+pub mod ffmpeg_progress;
+pub mod files;
+pub mod find_ffmpeg;
+pub mod history;
+pub mod jobs;
+pub mod marker_tones;
+pub mod media_info;
+pub mod mp3_split;
+pub mod plan;
+pub mod playlist;
+pub mod process;
+pub mod progress;
+pub mod silence;
+
+pub use audio_segment::{SegmentOptions, SegmentProgress};
+pub use files::{AudioFile, CopyProgress};
+pub use find_ffmpeg::FfmpegTools;
+pub use process::{ProcessOutput, ProcessRunner, SystemRunner};
+pub use progress::{NoProgress, ProgressSink};
@@ -0,0 +1,618 @@
+//! Splitting MP3 files without ffmpeg, by cutting between MPEG frames. Nothing is decoded
+//! or re-encoded, so each part sounds exactly like that stretch of the original.
+//!
+//! Each part gets its own ID3 tag (with its track number), a fresh Xing/Info header with
+//! the part's frame count and seek table, and a LAME tag carrying the gapless playback
+//! delay and padding.
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::fs::{self, File};
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// Layer III bitrates in kbps, by bitrate index, for MPEG-1 and for MPEG-2/2.5
+const BITRATES_MPEG1: [u32; 15] = [
+    0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+];
+const BITRATES_MPEG2: [u32; 15] = [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+const SAMPLE_RATES_MPEG1: [u32; 3] = [44100, 48000, 32000];
+
+/// Size of the Xing fields we write: tag, flags, frame count, byte count, TOC and quality
+const XING_LEN: usize = 120;
+const LAME_LEN: usize = 36;
+/// The largest delay or padding the LAME tag's 12-bit fields can hold
+const MAX_GAPLESS_SAMPLES: u32 = 4095;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Version {
+    Mpeg1,
+    Mpeg2,
+    Mpeg25,
+}
+
+/// The 4-byte header in front of every MPEG audio frame
+#[derive(Debug, Clone, Copy)]
+struct FrameHeader {
+    raw: u32,
+    version: Version,
+    bitrate_index: usize,
+    sample_rate: u32,
+    padding: bool,
+    /// Followed by a 16-bit CRC
+    protected: bool,
+    mono: bool,
+}
+
+impl FrameHeader {
+    /// Parses a Layer III header. Free-format bitrates aren't supported.
+    fn parse(bytes: [u8; 4]) -> Option<Self> {
+        let raw = u32::from_be_bytes(bytes);
+        if raw >> 21 != 0x7FF || (raw >> 17) & 3 != 1 {
+            return None;
+        }
+        let version = match (raw >> 19) & 3 {
+            0 => Version::Mpeg25,
+            2 => Version::Mpeg2,
+            3 => Version::Mpeg1,
+            _ => return None,
+        };
+        let bitrate_index = ((raw >> 12) & 0xF) as usize;
+        let sample_rate_index = ((raw >> 10) & 3) as usize;
+        if bitrate_index == 0 || bitrate_index == 15 || sample_rate_index == 3 {
+            return None;
+        }
+        let sample_rate = SAMPLE_RATES_MPEG1[sample_rate_index]
+            >> match version {
+                Version::Mpeg1 => 0,
+                Version::Mpeg2 => 1,
+                Version::Mpeg25 => 2,
+            };
+        Some(FrameHeader {
+            raw,
+            version,
+            bitrate_index,
+            sample_rate,
+            padding: (raw >> 9) & 1 == 1,
+            protected: (raw >> 16) & 1 == 0,
+            mono: (raw >> 6) & 3 == 3,
+        })
+    }
+
+    /// The same stream parameters at another bitrate, without padding or CRC
+    fn with_bitrate_index(self, bitrate_index: usize) -> Self {
+        let raw = (self.raw & !(0xF << 12) & !(1 << 9)) | (bitrate_index as u32) << 12 | 1 << 16;
+        FrameHeader::parse(raw.to_be_bytes()).expect("valid header")
+    }
+
+    fn bitrate_kbps(&self) -> u32 {
+        match self.version {
+            Version::Mpeg1 => BITRATES_MPEG1[self.bitrate_index],
+            _ => BITRATES_MPEG2[self.bitrate_index],
+        }
+    }
+
+    fn samples(&self) -> u32 {
+        match self.version {
+            Version::Mpeg1 => 1152,
+            _ => 576,
+        }
+    }
+
+    fn len(&self) -> usize {
+        (self.samples() / 8 * self.bitrate_kbps() * 1000 / self.sample_rate) as usize
+            + self.padding as usize
+    }
+
+    fn side_info_len(&self) -> usize {
+        match (self.version == Version::Mpeg1, self.mono) {
+            (true, false) => 32,
+            (true, true) => 17,
+            (false, false) => 17,
+            (false, true) => 9,
+        }
+    }
+
+    /// Where the side information starts, after the header and CRC
+    fn side_info_offset(&self) -> usize {
+        4 + if self.protected { 2 } else { 0 }
+    }
+
+    /// Bytes of the frame given over to main data, which later frames may borrow
+    fn main_data_len(&self) -> usize {
+        self.len()
+            .saturating_sub(self.side_info_offset() + self.side_info_len())
+    }
+
+    /// How many bytes of this frame's audio data sit at the end of earlier frames (the
+    /// bit reservoir)
+    fn main_data_begin(&self, frame: &[u8]) -> usize {
+        let side_info = &frame[self.side_info_offset()..];
+        match self.version {
+            Version::Mpeg1 => (side_info[0] as usize) << 1 | (side_info[1] as usize) >> 7,
+            _ => side_info[0] as usize,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    offset: u64,
+    header: FrameHeader,
+    main_data_begin: usize,
+}
+
+/// CRC-16 as used in the LAME tag (the ARC variant)
+fn crc16(data: &[u8], mut crc: u16) -> u16 {
+    for byte in data {
+        crc ^= *byte as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xA001
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+fn synchsafe(bytes: &[u8]) -> usize {
+    bytes
+        .iter()
+        .fold(0, |size, b| size << 7 | (*b & 0x7F) as usize)
+}
+
+fn to_synchsafe(size: usize) -> [u8; 4] {
+    [
+        (size >> 21) as u8 & 0x7F,
+        (size >> 14) as u8 & 0x7F,
+        (size >> 7) as u8 & 0x7F,
+        size as u8 & 0x7F,
+    ]
+}
+
+/// Copies an ID3v2 tag with its track number replaced by `track`. Tags we can't safely
+/// edit (unsynchronised, or with an extended header) are kept as they are. Without a tag,
+/// a minimal one holding just the track number is written.
+fn retag_id3v2(tag: Option<&[u8]>, track: &str) -> Vec<u8> {
+    let (version, mut frames) = match tag {
+        Some(tag) if (tag[3] == 3 || tag[3] == 4) && tag[5] & 0xC0 == 0 => (tag[3], Vec::new()),
+        Some(tag) => return tag.to_vec(),
+        None => (3, Vec::new()),
+    };
+    let frame_size = |bytes: &[u8]| match version {
+        4 => synchsafe(bytes),
+        _ => u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize,
+    };
+    if let Some(tag) = tag {
+        let end = (10 + synchsafe(&tag[6..10])).min(tag.len());
+        let mut pos = 10;
+        while pos + 10 <= end && tag[pos] != 0 {
+            let frame_end = pos + 10 + frame_size(&tag[pos + 4..pos + 8]);
+            if frame_end > end {
+                break;
+            }
+            if &tag[pos..pos + 4] != b"TRCK" {
+                frames.extend_from_slice(&tag[pos..frame_end]);
+            }
+            pos = frame_end;
+        }
+    }
+    // A text frame: ISO-8859-1 encoding byte, then the text
+    let body_len = 1 + track.len();
+    frames.extend_from_slice(b"TRCK");
+    match version {
+        4 => frames.extend_from_slice(&to_synchsafe(body_len)),
+        _ => frames.extend_from_slice(&(body_len as u32).to_be_bytes()),
+    }
+    frames.extend_from_slice(&[0, 0, 0]);
+    frames.extend_from_slice(track.as_bytes());
+
+    let mut out = vec![b'I', b'D', b'3', version, 0, 0];
+    out.extend_from_slice(&to_synchsafe(frames.len()));
+    out.extend(frames);
+    out
+}
+
+/// An MP3 file indexed by frame, ready to be cut into parts
+pub struct Mp3File {
+    path: PathBuf,
+    id3v2: Option<Vec<u8>>,
+    id3v1: Option<Vec<u8>>,
+    frames: Vec<Frame>,
+    /// The original LAME tag, kept so each part carries the same encoder details
+    lame: Option<Vec<u8>>,
+    /// Samples of encoder delay at the start and padding at the end, from the LAME tag
+    delay: u32,
+    padding: u32,
+}
+
+impl Mp3File {
+    /// Reads the file's tags and finds every frame. Fails for anything that isn't MPEG
+    /// Layer III audio.
+    pub fn open(path: &Path) -> Result<Self> {
+        let file =
+            File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        let file_len = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+
+        let mut id3v2 = None;
+        let mut head = [0u8; 10];
+        reader.read_exact(&mut head).context("File is too short")?;
+        let mut pos = 0;
+        if &head[..3] == b"ID3" {
+            let footer = if head[5] & 0x10 != 0 { 10 } else { 0 };
+            let size = 10 + synchsafe(&head[6..10]) + footer;
+            let mut tag = head.to_vec();
+            tag.resize(size, 0);
+            reader.read_exact(&mut tag[10..])?;
+            id3v2 = Some(tag);
+            pos = size as u64;
+        } else {
+            reader.seek(SeekFrom::Start(0))?;
+        }
+
+        // Tags at the end: ID3v1, possibly with an APEv2 tag in front of it
+        let mut end = file_len;
+        let mut id3v1 = None;
+        if end >= pos + 128 {
+            let mut tail = vec![0u8; 128];
+            reader.seek(SeekFrom::Start(end - 128))?;
+            reader.read_exact(&mut tail)?;
+            if &tail[..3] == b"TAG" {
+                id3v1 = Some(tail);
+                end -= 128;
+            }
+        }
+        if end >= pos + 32 {
+            let mut footer = [0u8; 32];
+            reader.seek(SeekFrom::Start(end - 32))?;
+            reader.read_exact(&mut footer)?;
+            if &footer[..8] == b"APETAGEX" {
+                let size = u32::from_le_bytes(footer[12..16].try_into()?) as u64;
+                let has_header = footer[23] & 0x80 != 0;
+                end = end.saturating_sub(size + if has_header { 32 } else { 0 });
+            }
+        }
+        reader.seek(SeekFrom::Start(pos))?;
+
+        let mut frames: Vec<Frame> = Vec::new();
+        let mut lame = None;
+        let (mut delay, mut padding) = (0, 0);
+        let mut seen_first_frame = false;
+        let mut window = [0u8; 4];
+        let mut filled = 0;
+        let mut buf = Vec::new();
+        while pos + (4 - filled) as u64 <= end {
+            // Slide a byte at a time until a header turns up, to step over junk
+            if filled == 4 {
+                window.rotate_left(1);
+                filled = 3;
+            }
+            reader.read_exact(&mut window[filled..])?;
+            pos += (4 - filled) as u64;
+            filled = 4;
+            let Some(header) = FrameHeader::parse(window) else {
+                continue;
+            };
+            let consistent = frames.first().is_none_or(|first| {
+                first.header.version == header.version
+                    && first.header.sample_rate == header.sample_rate
+            });
+            let offset = pos - 4;
+            if !consistent || offset + header.len() as u64 > end {
+                continue;
+            }
+            buf.clear();
+            buf.extend_from_slice(&window);
+            buf.resize(header.len(), 0);
+            reader.read_exact(&mut buf[4..])?;
+            pos = offset + header.len() as u64;
+            filled = 0;
+
+            if !seen_first_frame {
+                seen_first_frame = true;
+                if let Some(info) = parse_info_frame(&header, &buf) {
+                    lame = info.lame;
+                    delay = info.delay;
+                    padding = info.padding;
+                    continue;
+                }
+            }
+            frames.push(Frame {
+                offset,
+                header,
+                main_data_begin: header.main_data_begin(&buf),
+            });
+        }
+        if frames.is_empty() {
+            bail!("No MP3 frames found in {}", path.display());
+        }
+        Ok(Mp3File {
+            path: path.to_path_buf(),
+            id3v2,
+            id3v1,
+            frames,
+            lame,
+            delay,
+            padding,
+        })
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.frames[0].header.sample_rate
+    }
+
+    fn samples_per_frame(&self) -> u32 {
+        self.frames[0].header.samples()
+    }
+
+    /// Playing time in seconds, without the encoder's delay and padding
+    pub fn duration(&self) -> f64 {
+        let samples = self.frames.len() as u64 * self.samples_per_frame() as u64;
+        samples.saturating_sub((self.delay + self.padding) as u64) as f64
+            / self.sample_rate() as f64
+    }
+
+    /// The frame boundary nearest a point in time. The start of the file is always frame 0,
+    /// delay or no delay.
+    fn frame_at(&self, seconds: f64) -> usize {
+        if seconds <= 0.0 {
+            return 0;
+        }
+        let sample = seconds * self.sample_rate() as f64 + self.delay as f64;
+        ((sample / self.samples_per_frame() as f64).round().max(0.0) as usize)
+            .min(self.frames.len())
+    }
+
+    /// How many frames before `first` hold the bit reservoir data that `first` starts with.
+    /// They're included at the start of a part so its first frame decodes properly. No more
+    /// are taken than the gapless delay can skip, since any past that would be heard: at very
+    /// low bitrates the first frame may then decode imperfectly instead.
+    fn priming_frames(&self, first: usize) -> usize {
+        let needed = self.frames[first].main_data_begin;
+        let most = (MAX_GAPLESS_SAMPLES / self.samples_per_frame()) as usize;
+        let mut available = 0;
+        let mut count = 0;
+        while available < needed && count < first.min(most) {
+            count += 1;
+            available += self.frames[first - count].header.main_data_len();
+        }
+        count
+    }
+
+    /// Writes the stretch from `start` to `end` (or the end of the file) as a part of its
+    /// own. `part` and `parts` become the track number.
+    pub fn write_part(
+        &self,
+        start: f64,
+        end: Option<f64>,
+        part: usize,
+        parts: usize,
+        dest: &Path,
+    ) -> Result<()> {
+        let first = self.frame_at(start);
+        let last = end.map_or(self.frames.len(), |end| self.frame_at(end));
+        if first >= last {
+            return Err(anyhow!("Part {} of {} is empty", part, parts));
+        }
+        let priming = self.priming_frames(first);
+        let begin = first - priming;
+        let spf = self.samples_per_frame();
+        // The priming frames are decoded and then skipped, like the encoder's own delay
+        let delay = if first == 0 {
+            self.delay
+        } else {
+            priming as u32 * spf
+        };
+        let padding = if last == self.frames.len() {
+            self.padding
+        } else {
+            0
+        };
+
+        let audio_start = self.frames[begin].offset;
+        let last_frame = &self.frames[last - 1];
+        let audio_end = last_frame.offset + last_frame.header.len() as u64;
+        let mut audio = vec![0u8; (audio_end - audio_start) as usize];
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(audio_start))?;
+        file.read_exact(&mut audio)?;
+
+        let info = self.info_frame(&self.frames[begin..last], &audio, delay, padding);
+        let track = format!("{}/{}", part, parts);
+        let mut out = retag_id3v2(self.id3v2.as_deref(), &track);
+        out.extend(info);
+        out.extend(audio);
+        if let Some(id3v1) = &self.id3v1 {
+            let mut id3v1 = id3v1.clone();
+            // ID3v1.1 keeps the track number in the last byte of the comment
+            if id3v1[125] == 0 && part <= 255 {
+                id3v1[126] = part as u8;
+            }
+            out.extend(id3v1);
+        }
+        fs::write(dest, out).with_context(|| format!("Failed to write {}", dest.display()))
+    }
+
+    /// Builds the Xing/Info header frame for a part: its frame and byte counts, a seek
+    /// table, and a LAME tag with the gapless delay and padding
+    fn info_frame(&self, frames: &[Frame], audio: &[u8], delay: u32, padding: u32) -> Vec<u8> {
+        let template = frames[0].header;
+        let xing_offset = 4 + template.side_info_len();
+        let needed = xing_offset + XING_LEN + LAME_LEN;
+        let header = (1..15)
+            .map(|index| template.with_bitrate_index(index))
+            .find(|header| header.len() >= needed)
+            .unwrap_or_else(|| template.with_bitrate_index(14));
+        let mut frame = vec![0u8; header.len()];
+        frame[..4].copy_from_slice(&header.raw.to_be_bytes());
+
+        let total_bytes = frame.len() + audio.len();
+        let vbr = frames
+            .iter()
+            .any(|f| f.header.bitrate_index != template.bitrate_index);
+        let xing = &mut frame[xing_offset..xing_offset + XING_LEN];
+        xing[..4].copy_from_slice(if vbr { b"Xing" } else { b"Info" });
+        xing[4..8].copy_from_slice(&0x0Fu32.to_be_bytes());
+        xing[8..12].copy_from_slice(&(frames.len() as u32).to_be_bytes());
+        xing[12..16].copy_from_slice(&(total_bytes as u32).to_be_bytes());
+        for (i, entry) in xing[16..116].iter_mut().enumerate() {
+            let offset = frames[i * frames.len() / 100].offset - frames[0].offset;
+            *entry = ((offset as usize + header.len()) * 256 / total_bytes).min(255) as u8;
+        }
+
+        let lame_offset = xing_offset + XING_LEN;
+        let lame = &mut frame[lame_offset..lame_offset + LAME_LEN];
+        match &self.lame {
+            Some(original) => lame.copy_from_slice(original),
+            // Players only read the delay and padding from a tag that names an encoder
+            None => lame[..9].copy_from_slice(b"LAME3.100"),
+        }
+        let delay = delay.min(MAX_GAPLESS_SAMPLES);
+        let padding = padding.min(MAX_GAPLESS_SAMPLES);
+        lame[21] = (delay >> 4) as u8;
+        lame[22] = ((delay & 0xF) << 4) as u8 | (padding >> 8) as u8;
+        lame[23] = padding as u8;
+        lame[28..32].copy_from_slice(&(total_bytes as u32).to_be_bytes());
+        lame[32..34].copy_from_slice(&crc16(audio, 0).to_be_bytes());
+        let tag_crc = crc16(&frame[..lame_offset + 34], 0);
+        frame[lame_offset + 34..lame_offset + 36].copy_from_slice(&tag_crc.to_be_bytes());
+        frame
+    }
+}
+
+struct InfoFrame {
+    lame: Option<Vec<u8>>,
+    delay: u32,
+    padding: u32,
+}
+
+/// Recognizes a Xing/Info or VBRI header frame, which carries stream details rather
+/// than audio
+fn parse_info_frame(header: &FrameHeader, frame: &[u8]) -> Option<InfoFrame> {
+    if frame.get(36..40) == Some(&b"VBRI"[..]) {
+        return Some(InfoFrame {
+            lame: None,
+            delay: 0,
+            padding: 0,
+        });
+    }
+    let xing_offset = header.side_info_offset() + header.side_info_len();
+    let tag = frame.get(xing_offset..xing_offset + 8)?;
+    if &tag[..4] != b"Xing" && &tag[..4] != b"Info" {
+        return None;
+    }
+    let flags = u32::from_be_bytes(tag[4..8].try_into().ok()?);
+    let lame_offset = xing_offset
+        + 8
+        + [(1, 4), (2, 4), (4, 100), (8, 4)]
+            .iter()
+            .filter(|(flag, _)| flags & flag != 0)
+            .map(|(_, len)| len)
+            .sum::<usize>();
+    let lame = frame
+        .get(lame_offset..lame_offset + LAME_LEN)
+        .filter(|lame| [b"LAME", b"Lavf", b"Lavc"].iter().any(|e| &lame[..4] == *e));
+    let (delay, padding) = lame.map_or((0, 0), |lame| {
+        let bits = u32::from_be_bytes([0, lame[21], lame[22], lame[23]]);
+        (bits >> 12, bits & 0xFFF)
+    });
+    Some(InfoFrame {
+        lame: lame.map(<[u8]>::to_vec),
+        delay,
+        padding,
+    })
+}
+
+#[cfg(test)]
+fn synthetic_mp3(frames: usize, reservoir_from: usize) -> Vec<u8> {
+    // MPEG-1 Layer III, 128 kbps, 44.1 kHz, joint stereo, no CRC: 417-byte frames
+    let header = FrameHeader::parse([0xFF, 0xFB, 0x90, 0x44]).unwrap();
+    let audio_frame = |i: usize| {
+        let mut frame = vec![0u8; header.len()];
+        frame[..4].copy_from_slice(&header.raw.to_be_bytes());
+        frame[4] = if i >= reservoir_from { 150 } else { 0 };
+        frame[5] = i as u8;
+        frame
+    };
+    let audio: Vec<u8> = (0..frames).flat_map(audio_frame).collect();
+    // An Info frame like LAME writes, with 576 samples of delay and 1000 of padding
+    let info = Mp3File {
+        path: PathBuf::new(),
+        id3v2: None,
+        id3v1: None,
+        frames: (0..frames)
+            .map(|i| Frame {
+                offset: (i * header.len()) as u64,
+                header,
+                main_data_begin: 0,
+            })
+            .collect(),
+        lame: None,
+        delay: 0,
+        padding: 0,
+    };
+    let mut out = retag_id3v2(None, "7");
+    out.extend(info.info_frame(&info.frames, &audio, 576, 1000));
+    out.extend(audio);
+    out
+}
+
+#[test]
+fn test_split_mp3_at_frames_with_reservoir_and_headers() {
+    let dir = std::env::temp_dir().join("sync-and-swim-mp3-split-test");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("book.mp3");
+    // 100 frames of 1152 samples; from frame 30 on each borrows 300 bytes from the last
+    fs::write(&input, synthetic_mp3(100, 30)).unwrap();
+
+    let mp3 = Mp3File::open(&input).unwrap();
+    assert_eq!(mp3.frames.len(), 100);
+    assert_eq!((mp3.delay, mp3.padding), (576, 1000));
+    assert_eq!(mp3.frames[40].main_data_begin, 300);
+    assert!((mp3.duration() - (115200.0 - 1576.0) / 44100.0).abs() < 1e-9);
+
+    // 1s in is sample 44100 + 576, closest to the boundary before frame 39
+    let first = dir.join("part 1.mp3");
+    let second = dir.join("part 2.mp3");
+    mp3.write_part(0.0, Some(1.0), 1, 2, &first).unwrap();
+    mp3.write_part(1.0, None, 2, 2, &second).unwrap();
+
+    let first = Mp3File::open(&first).unwrap();
+    assert_eq!(first.frames.len(), 39);
+    assert_eq!((first.delay, first.padding), (576, 0));
+    // The second part repeats frame 38, which holds the start of frame 39's audio data
+    let second = Mp3File::open(&second).unwrap();
+    assert_eq!(second.frames.len(), 62);
+    assert_eq!(second.frames[0].header.raw, mp3.frames[0].header.raw);
+    assert_eq!((second.delay, second.padding), (1152, 1000));
+    let tag = second.id3v2.unwrap();
+    assert!(tag.ends_with(b"TRCK\0\0\0\x04\0\0\x002/2"));
+    assert_eq!(tag.windows(4).filter(|w| w == b"TRCK").count(), 1);
+}
+
+#[test]
+fn test_priming_stops_where_the_gapless_delay_does() {
+    // 32 kbps frames hold 68 bytes of audio data, so a full reservoir spans 8 of them
+    let header = FrameHeader::parse([0xFF, 0xFB, 0x10, 0x44]).unwrap();
+    let mp3 = Mp3File {
+        path: PathBuf::new(),
+        id3v2: None,
+        id3v1: None,
+        frames: (0..20)
+            .map(|i| Frame {
+                offset: (i * header.len()) as u64,
+                header,
+                main_data_begin: 511,
+            })
+            .collect(),
+        lame: None,
+        delay: 0,
+        padding: 0,
+    };
+    assert_eq!(header.main_data_len(), 68);
+    assert_eq!(mp3.priming_frames(10), 3);
+    assert_eq!(mp3.priming_frames(2), 2);
+}
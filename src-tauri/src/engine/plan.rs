@@ -0,0 +1,144 @@
+use anyhow::Result;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use super::files::{deep_list_files, sort_audio_files, AudioFile};
+
+/// How the files within one folder should be ordered on the device
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum FolderOrder {
+    Sort,
+    /// The seed is stored so the same profile produces the same order every time
+    Shuffle {
+        seed: u64,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct FileFilter {
+    /// Extensions (without the dot) to include. Empty means every file.
+    #[serde(default)]
+    pub extensions: Vec<String>,
+    /// Folders, relative to a source root, to leave out along with their subfolders
+    #[serde(default)]
+    pub exclude_folders: Vec<String>,
+}
+
+impl FileFilter {
+    fn matches(&self, file: &AudioFile) -> bool {
+        let folder = Path::new(&file.relative_path);
+        if self
+            .exclude_folders
+            .iter()
+            .any(|excluded| folder.starts_with(excluded))
+        {
+            return false;
+        }
+        if self.extensions.is_empty() {
+            return true;
+        }
+        Path::new(&file.name)
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|ext| self.extensions.iter().any(|e| e.eq_ignore_ascii_case(ext)))
+            .unwrap_or(false)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TransferProfile {
+    pub name: String,
+    pub source_roots: Vec<String>,
+    /// Ordering rules keyed by folder path relative to its source root.
+    /// Folders without a rule are sorted by name.
+    #[serde(default)]
+    pub folder_order: BTreeMap<String, FolderOrder>,
+    #[serde(default)]
+    pub filter: FileFilter,
+    pub dest_path: String,
+    /// Either "append" or "replace", as accepted by `copy_files`
+    pub mode: String,
+}
+
+/// Reorders each folder's files according to `rules`, keeping folders in the order they appear
+fn apply_folder_order(
+    files: Vec<AudioFile>,
+    rules: &BTreeMap<String, FolderOrder>,
+) -> Vec<AudioFile> {
+    let mut folders: Vec<(String, Vec<AudioFile>)> = Vec::new();
+    for file in files {
+        match folders.last_mut() {
+            Some((dir, dir_files)) if *dir == file.relative_path => dir_files.push(file),
+            _ => folders.push((file.relative_path.clone(), vec![file])),
+        }
+    }
+
+    for (dir, dir_files) in folders.iter_mut() {
+        dir_files.sort_by(|a, b| a.name.cmp(&b.name));
+        if let Some(FolderOrder::Shuffle { seed }) = rules.get(dir) {
+            dir_files.shuffle(&mut ChaCha8Rng::seed_from_u64(*seed));
+        }
+    }
+
+    folders
+        .into_iter()
+        .flat_map(|(_, dir_files)| dir_files)
+        .collect()
+}
+
+/// Lists the files a profile would copy, in the order they'll be written
+pub fn plan_transfer(profile: &TransferProfile) -> Result<Vec<AudioFile>> {
+    let mut files = Vec::new();
+    for root in &profile.source_roots {
+        let mut listed = deep_list_files(root).map_err(anyhow::Error::msg)?;
+        sort_audio_files(&mut listed);
+        let listed = listed
+            .into_iter()
+            .filter(|file| profile.filter.matches(file))
+            .collect();
+        files.extend(apply_folder_order(listed, &profile.folder_order));
+    }
+    Ok(files)
+}
+
+#[test]
+fn test_apply_folder_order_shuffle_is_repeatable() {
+    let files: Vec<AudioFile> = ["a", "b", "c", "d", "e"]
+        .iter()
+        .map(|name| AudioFile {
+            name: name.to_string(),
+            path: format!("/music/{}", name),
+            relative_path: "album".to_string(),
+            media: None,
+        })
+        .collect();
+    let rules = BTreeMap::from([("album".to_string(), FolderOrder::Shuffle { seed: 7 })]);
+
+    let first = apply_folder_order(files.clone(), &rules);
+    let second = apply_folder_order(files, &rules);
+    let names = |files: &[AudioFile]| files.iter().map(|f| f.name.clone()).collect::<Vec<_>>();
+    assert_eq!(names(&first), names(&second));
+    assert_ne!(names(&first), vec!["a", "b", "c", "d", "e"]);
+}
+
+#[test]
+fn test_file_filter_excludes_folders_and_extensions() {
+    let filter = FileFilter {
+        extensions: vec!["mp3".to_string()],
+        exclude_folders: vec!["podcasts".to_string()],
+    };
+    let file = |relative_path: &str, name: &str| AudioFile {
+        name: name.to_string(),
+        path: String::new(),
+        relative_path: relative_path.to_string(),
+        media: None,
+    };
+    assert!(filter.matches(&file("books", "chapter.MP3")));
+    assert!(!filter.matches(&file("books", "cover.jpg")));
+    assert!(!filter.matches(&file("podcasts/weekly", "episode.mp3")));
+}
@@ -0,0 +1,250 @@
+//! Playlists from other players (M3U, M3U8, PLS and XSPF) as a source of files to copy.
+
+use anyhow::{anyhow, Context, Result};
+use roxmltree::Document;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::files::AudioFile;
+
+/// The files a playlist refers to, in playlist order, ready for `copy_files`
+#[derive(Debug, Serialize, Clone)]
+pub struct PlaylistLoad {
+    pub files: Vec<AudioFile>,
+    /// Entries that don't point at a readable local file, as written in the playlist
+    pub missing: Vec<String>,
+}
+
+fn parse_m3u(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .map(|line| line.trim_start_matches('\u{feff}').trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+/// PLS entries are numbered `File1=`, `File2=`, ... and may be listed in any order
+fn parse_pls(contents: &str) -> Vec<String> {
+    let mut entries: Vec<(u32, String)> = contents
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.trim().split_once('=')?;
+            let number = key.strip_prefix("File")?.parse().ok()?;
+            Some((number, value.trim().to_string()))
+        })
+        .collect();
+    entries.sort_by_key(|(number, _)| *number);
+    entries.into_iter().map(|(_, entry)| entry).collect()
+}
+
+fn parse_xspf(contents: &str) -> Result<Vec<String>> {
+    let doc = Document::parse(contents).context("Failed to parse XSPF playlist")?;
+    Ok(doc
+        .descendants()
+        .filter(|n| n.tag_name().name() == "track")
+        .filter_map(|track| {
+            track
+                .children()
+                .find(|c| c.tag_name().name() == "location")?
+                .text()
+                .map(|t| t.trim().to_string())
+        })
+        .collect())
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 3 <= bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("");
+            if let Ok(byte) = u8::from_str_radix(hex, 16) {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// Turns a playlist entry into a local path. Relative entries are relative to the folder the
+/// playlist is in. Returns None for remote URLs.
+fn resolve_entry(entry: &str, playlist_dir: &Path, is_uri: bool) -> Option<PathBuf> {
+    let path = if let Some(rest) = entry.strip_prefix("file://") {
+        // file:///C:/Music/a.mp3 on Windows, file:///home/me/a.mp3 elsewhere
+        let rest = rest.strip_prefix("localhost").unwrap_or(rest);
+        let rest = percent_decode(rest);
+        if cfg!(windows) {
+            PathBuf::from(rest.trim_start_matches('/'))
+        } else {
+            PathBuf::from(rest)
+        }
+    } else if entry.contains("://") {
+        return None;
+    } else if is_uri {
+        PathBuf::from(percent_decode(entry))
+    } else {
+        PathBuf::from(entry.replace('\\', std::path::MAIN_SEPARATOR_STR))
+    };
+    Some(if path.is_absolute() {
+        path
+    } else {
+        playlist_dir.join(path)
+    })
+}
+
+/// Adds " (2)", " (3)", ... before the extension until the name is unused
+pub(super) fn unique_name(name: &str, used: &mut HashSet<String>) -> String {
+    let mut candidate = name.to_string();
+    let path = Path::new(name);
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or(name);
+    let extension = path.extension().and_then(|e| e.to_str());
+    let mut n = 2;
+    while !used.insert(candidate.to_lowercase()) {
+        candidate = match extension {
+            Some(extension) => format!("{} ({}).{}", stem, n, extension),
+            None => format!("{} ({})", stem, n),
+        };
+        n += 1;
+    }
+    candidate
+}
+
+/// Reads a playlist and resolves its entries to files, in playlist order.
+///
+/// Each file keeps the name of the folder it's in as its `relative_path`, unless `folder` is
+/// given, in which case the whole playlist goes into that one folder on the device (renaming
+/// files that would clash).
+pub fn load_playlist(playlist_path: &str, folder: Option<&str>) -> Result<PlaylistLoad> {
+    let path = Path::new(playlist_path);
+    let bytes = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    // .m3u files are often Latin-1; lossy decoding keeps the ASCII paths usable at least
+    let contents = String::from_utf8_lossy(&bytes);
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase);
+    let (entries, is_uri) = match extension.as_deref() {
+        Some("m3u") | Some("m3u8") => (parse_m3u(&contents), false),
+        Some("pls") => (parse_pls(&contents), false),
+        Some("xspf") => (parse_xspf(&contents)?, true),
+        _ => return Err(anyhow!("Unsupported playlist format: {}", path.display())),
+    };
+
+    let playlist_dir = path.parent().unwrap_or(Path::new(""));
+    let mut files = Vec::new();
+    let mut missing = Vec::new();
+    let mut used_names = HashSet::new();
+    for entry in entries {
+        let Some(file_path) = resolve_entry(&entry, playlist_dir, is_uri).filter(|p| p.is_file())
+        else {
+            missing.push(entry);
+            continue;
+        };
+        let name = file_path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let (name, relative_path) = match folder {
+            Some(folder) => (unique_name(&name, &mut used_names), folder.to_string()),
+            None => (
+                name,
+                file_path
+                    .parent()
+                    .and_then(|p| p.file_name())
+                    .map(|n| n.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+            ),
+        };
+        files.push(AudioFile {
+            name,
+            path: file_path.to_string_lossy().to_string(),
+            relative_path,
+            media: None,
+        });
+    }
+    Ok(PlaylistLoad { files, missing })
+}
+
+#[test]
+fn test_load_playlists_in_order_and_report_missing() {
+    let root = std::env::temp_dir().join("sync-and-swim-playlist-test");
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join("Album A")).unwrap();
+    fs::create_dir_all(root.join("Album B")).unwrap();
+    fs::write(root.join("Album A").join("song.mp3"), "a").unwrap();
+    fs::write(root.join("Album B").join("song.mp3"), "b").unwrap();
+    fs::write(root.join("Album B").join("other song.mp3"), "c").unwrap();
+    fs::write(root.join("Album B").join("encore (live)"), "d").unwrap();
+    let absolute = root.join("Album A").join("song.mp3");
+
+    let m3u = root.join("swim.m3u8");
+    fs::write(
+        &m3u,
+        format!(
+            "#EXTM3U\n#EXTINF:123,Other\nAlbum B/other song.mp3\n{}\nAlbum B/song.mp3\ngone.mp3\nhttp://example.com/stream\n",
+            absolute.display()
+        ),
+    )
+    .unwrap();
+    let load = load_playlist(m3u.to_str().unwrap(), None).unwrap();
+    let files: Vec<_> = load
+        .files
+        .iter()
+        .map(|f| (f.relative_path.as_str(), f.name.as_str()))
+        .collect();
+    assert_eq!(
+        files,
+        vec![
+            ("Album B", "other song.mp3"),
+            ("Album A", "song.mp3"),
+            ("Album B", "song.mp3"),
+        ]
+    );
+    assert_eq!(load.missing, vec!["gone.mp3", "http://example.com/stream"]);
+
+    let load = load_playlist(m3u.to_str().unwrap(), Some("Swim")).unwrap();
+    let names: Vec<_> = load.files.iter().map(|f| f.name.as_str()).collect();
+    assert_eq!(names, vec!["other song.mp3", "song.mp3", "song (2).mp3"]);
+    assert!(load.files.iter().all(|f| f.relative_path == "Swim"));
+
+    let pls = root.join("swim.pls");
+    fs::write(
+        &pls,
+        "[playlist]\nFile2=Album A/song.mp3\nTitle2=Second\nFile1=Album B/song.mp3\nNumberOfEntries=2\n",
+    )
+    .unwrap();
+    let load = load_playlist(pls.to_str().unwrap(), None).unwrap();
+    let dirs: Vec<_> = load
+        .files
+        .iter()
+        .map(|f| f.relative_path.as_str())
+        .collect();
+    assert_eq!(dirs, vec!["Album B", "Album A"]);
+
+    let xspf = root.join("swim.xspf");
+    fs::write(
+        &xspf,
+        format!(
+            r#"<playlist version="1" xmlns="http://xspf.org/ns/0/"><trackList>
+                <track><location>Album%20B/other%20song.mp3</location></track>
+                <track><location>file://{}</location></track>
+                <track><location>Album%20B/encore%20%28live%29</location></track>
+            </trackList></playlist>"#,
+            absolute.to_str().unwrap().replace(' ', "%20")
+        ),
+    )
+    .unwrap();
+    let load = load_playlist(xspf.to_str().unwrap(), None).unwrap();
+    assert_eq!(load.missing, Vec::<String>::new());
+    assert_eq!(load.files[0].name, "other song.mp3");
+    assert_eq!(load.files[1].path, absolute.to_string_lossy());
+    assert_eq!(load.files[2].name, "encore (live)");
+}
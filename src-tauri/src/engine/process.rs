@@ -0,0 +1,78 @@
+use anyhow::{Context, Result};
+use std::future::Future;
+use std::path::Path;
+use std::process::Stdio;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+
+/// The captured result of a finished process
+#[derive(Debug, Clone, Default)]
+pub struct ProcessOutput {
+    pub success: bool,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+/// Runs external programs (ffmpeg and ffprobe).
+///
+/// Splitting goes through this trait rather than spawning processes directly, so it can be
+/// tested without ffmpeg installed.
+pub trait ProcessRunner: Send + Sync {
+    /// Runs a program to completion and captures its output
+    fn output(
+        &self,
+        program: &Path,
+        args: &[String],
+    ) -> impl Future<Output = Result<ProcessOutput>> + Send;
+
+    /// Runs a program, passing each line it writes to stderr to `on_line` as it arrives.
+    /// Returns whether the program exited successfully.
+    fn run_with_stderr(
+        &self,
+        program: &Path,
+        args: &[String],
+        on_line: &mut (dyn FnMut(&str) + Send),
+    ) -> impl Future<Output = Result<bool>> + Send;
+}
+
+/// Runs programs as child processes of this one
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemRunner;
+
+impl ProcessRunner for SystemRunner {
+    async fn output(&self, program: &Path, args: &[String]) -> Result<ProcessOutput> {
+        let output = Command::new(program)
+            .args(args)
+            .output()
+            .await
+            .with_context(|| format!("Failed to execute {}", program.display()))?;
+        Ok(ProcessOutput {
+            success: output.status.success(),
+            stdout: output.stdout,
+            stderr: output.stderr,
+        })
+    }
+
+    async fn run_with_stderr(
+        &self,
+        program: &Path,
+        args: &[String],
+        on_line: &mut (dyn FnMut(&str) + Send),
+    ) -> Result<bool> {
+        let mut child = Command::new(program)
+            .args(args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to execute {}", program.display()))?;
+
+        let stderr = child.stderr.take().context("Failed to capture output")?;
+        let mut lines = BufReader::new(stderr).lines();
+        while let Some(line) = lines.next_line().await? {
+            on_line(&line);
+        }
+
+        let status = child.wait().await.context("Failed to wait for process")?;
+        Ok(status.success())
+    }
+}
@@ -0,0 +1,23 @@
+use super::audio_segment::SegmentProgress;
+use super::encoding::EncodeProgress;
+use super::feeds::FeedProgress;
+use super::files::CopyProgress;
+
+/// Receives progress updates from long-running operations.
+///
+/// The app forwards these to the window as events, the CLI prints them. Every method has a
+/// no-op default so a sink only needs to handle the updates it cares about.
+pub trait ProgressSink: Send + Sync {
+    fn copy_progress(&self, _progress: CopyProgress) {}
+
+    fn segment_progress(&self, _progress: SegmentProgress) {}
+
+    fn encode_progress(&self, _progress: EncodeProgress) {}
+
+    fn feed_progress(&self, _progress: FeedProgress) {}
+}
+
+/// A sink that discards every update
+pub struct NoProgress;
+
+impl ProgressSink for NoProgress {}
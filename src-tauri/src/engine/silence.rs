@@ -0,0 +1,427 @@
+//! Finding pauses to cut at, with thresholds tuned to the kind of recording.
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::path::Path;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::errors::Error as DecodeError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+use super::find_ffmpeg::FfmpegTools;
+use super::process::ProcessRunner;
+
+/// How quiet audio has to be to count as silence
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SilenceThreshold {
+    /// A fixed level, in dB below full scale
+    Decibels(f64),
+    /// A level just above the file's own noise floor
+    Auto,
+}
+
+/// What counts as a silence worth cutting at
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct SilenceSettings {
+    pub threshold: SilenceThreshold,
+    /// Shortest pause that counts, in seconds
+    pub min_duration: f64,
+}
+
+impl Default for SilenceSettings {
+    fn default() -> Self {
+        SilenceSettings {
+            threshold: SilenceThreshold::Decibels(-30.0),
+            min_duration: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SilencePreset {
+    /// Clean, quiet recordings with short pauses between sentences
+    StudioNarration,
+    /// Room noise and audience sounds under the speaker
+    LiveLecture,
+    /// Only the gaps between tracks are quiet enough
+    Music,
+}
+
+/// A preset as shown to the user
+#[derive(Debug, Serialize, Clone)]
+pub struct SilencePresetInfo {
+    pub preset: SilencePreset,
+    pub label: &'static str,
+    pub settings: SilenceSettings,
+}
+
+impl SilencePreset {
+    pub const ALL: [SilencePreset; 3] = [
+        SilencePreset::StudioNarration,
+        SilencePreset::LiveLecture,
+        SilencePreset::Music,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            SilencePreset::StudioNarration => "Studio narration",
+            SilencePreset::LiveLecture => "Live lecture",
+            SilencePreset::Music => "Music",
+        }
+    }
+
+    pub fn settings(self) -> SilenceSettings {
+        match self {
+            SilencePreset::StudioNarration => SilenceSettings {
+                threshold: SilenceThreshold::Decibels(-40.0),
+                min_duration: 0.7,
+            },
+            SilencePreset::LiveLecture => SilenceSettings {
+                threshold: SilenceThreshold::Auto,
+                min_duration: 1.0,
+            },
+            SilencePreset::Music => SilenceSettings {
+                threshold: SilenceThreshold::Decibels(-50.0),
+                min_duration: 2.0,
+            },
+        }
+    }
+
+    pub fn info(self) -> SilencePresetInfo {
+        SilencePresetInfo {
+            preset: self,
+            label: self.label(),
+            settings: self.settings(),
+        }
+    }
+}
+
+/// A stretch of silence, in seconds from the start of the file
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+pub struct SilenceInterval {
+    pub start: f64,
+    pub end: f64,
+}
+
+/// How far above the noise floor a pause may be and still count as silence
+const NOISE_FLOOR_MARGIN_DB: f64 = 10.0;
+
+/// Length of the windows the native analysis measures loudness over
+const WINDOW_SECONDS: f64 = 0.05;
+
+fn threshold_above_noise_floor(noise_floor: f64) -> f64 {
+    (noise_floor + NOISE_FLOOR_MARGIN_DB).clamp(-60.0, -20.0)
+}
+
+/// Picks a threshold from astats output: the overall noise floor plus a margin, kept within
+/// a range where silencedetect still behaves. Digital silence reports -inf, which ends up
+/// at the bottom of the range.
+fn threshold_from_astats(stderr: &str) -> Option<f64> {
+    // Per-channel figures come first and the overall figure last
+    let noise_floor = stderr.lines().rev().find_map(|line| {
+        let (_, value) = line.split_once("Noise floor dB:")?;
+        value.trim().parse::<f64>().ok()
+    })?;
+    Some(threshold_above_noise_floor(noise_floor))
+}
+
+/// Measures the noise floor with ffmpeg's astats filter and derives a threshold from it
+async fn auto_threshold<R: ProcessRunner>(
+    tools: &FfmpegTools<R>,
+    input_filename: &str,
+) -> Result<f64> {
+    let output = tools
+        .runner
+        .output(
+            &tools.ffmpeg,
+            &[
+                "-i".to_string(),
+                input_filename.to_string(),
+                "-af".to_string(),
+                "astats".to_string(),
+                "-f".to_string(),
+                "null".to_string(),
+                "-".to_string(),
+            ],
+        )
+        .await
+        .context("Failed to execute ffmpeg")?;
+    threshold_from_astats(&String::from_utf8_lossy(&output.stderr))
+        .context("Failed to measure the noise floor")
+}
+
+/// Loudness of each window of a mono signal, as RMS in dB below full scale
+struct RmsWindows {
+    window_len: usize,
+    sum_of_squares: f64,
+    count: usize,
+    levels: Vec<f64>,
+}
+
+impl RmsWindows {
+    fn new(sample_rate: u32) -> Self {
+        RmsWindows {
+            window_len: ((sample_rate as f64 * WINDOW_SECONDS) as usize).max(1),
+            sum_of_squares: 0.0,
+            count: 0,
+            levels: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, sample: f32) {
+        self.sum_of_squares += (sample as f64).powi(2);
+        self.count += 1;
+        if self.count == self.window_len {
+            self.levels
+                .push(10.0 * (self.sum_of_squares / self.count as f64).log10());
+            self.sum_of_squares = 0.0;
+            self.count = 0;
+        }
+    }
+}
+
+/// The file's noise floor, taken as the level that only the quietest twentieth of
+/// windows fall below
+fn noise_floor(levels: &[f64]) -> Option<f64> {
+    let mut sorted = levels.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    sorted.get(sorted.len() / 20).copied()
+}
+
+/// Runs of windows quieter than `threshold` that last at least `min_duration`. A silence
+/// that runs to the end of the file ends there.
+fn silences_in_levels(levels: &[f64], threshold: f64, min_duration: f64) -> Vec<SilenceInterval> {
+    let mut silences = Vec::new();
+    let mut run_start = None;
+    for (i, level) in levels.iter().chain([f64::INFINITY].iter()).enumerate() {
+        match (run_start, *level < threshold) {
+            (None, true) => run_start = Some(i),
+            (Some(start), false) => {
+                let interval = SilenceInterval {
+                    start: start as f64 * WINDOW_SECONDS,
+                    end: i as f64 * WINDOW_SECONDS,
+                };
+                if interval.end - interval.start >= min_duration {
+                    silences.push(interval);
+                }
+                run_start = None;
+            }
+            _ => {}
+        }
+    }
+    silences
+}
+
+/// Decodes the file in-process and measures its loudness, without ffmpeg. Handles MP3,
+/// AAC (in MP4/M4A), FLAC and WAV.
+fn decode_levels(path: &Path) -> Result<Vec<f64>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let source = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(extension);
+    }
+    let mut format = symphonia::default::get_probe()
+        .format(
+            &hint,
+            source,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .context("Unsupported audio format")?
+        .format;
+    let track = format
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .context("No audio track found")?;
+    let track_id = track.id;
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .context("Unsupported audio codec")?;
+
+    let mut windows: Option<RmsWindows> = None;
+    let mut samples: Option<SampleBuffer<f32>> = None;
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(DecodeError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // A corrupt frame is skipped, as a player would
+            Err(DecodeError::DecodeError(_)) => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let spec = *decoded.spec();
+        let channels = spec.channels.count();
+        let buffer =
+            samples.get_or_insert_with(|| SampleBuffer::new(decoded.capacity() as u64, spec));
+        buffer.copy_interleaved_ref(decoded);
+        let windows = windows.get_or_insert_with(|| RmsWindows::new(spec.rate));
+        for frame in buffer.samples().chunks(channels) {
+            windows.push(frame.iter().sum::<f32>() / channels as f32);
+        }
+    }
+    windows
+        .map(|w| w.levels)
+        .ok_or_else(|| anyhow!("No audio decoded from {}", path.display()))
+}
+
+/// Finds silences by decoding the file in-process, without ffmpeg
+pub fn analyze_silences(path: &Path, settings: &SilenceSettings) -> Result<Vec<SilenceInterval>> {
+    let levels = decode_levels(path)?;
+    let threshold = match settings.threshold {
+        SilenceThreshold::Decibels(db) => db,
+        SilenceThreshold::Auto => {
+            threshold_above_noise_floor(noise_floor(&levels).context("File is empty")?)
+        }
+    };
+    Ok(silences_in_levels(
+        &levels,
+        threshold,
+        settings.min_duration,
+    ))
+}
+
+/// Start times of the silences in a file, in seconds. Decodes the file natively when it
+/// can, and otherwise has ffmpeg's silencedetect filter do it.
+pub(super) async fn silence_points<R: ProcessRunner>(
+    tools: &FfmpegTools<R>,
+    input_filename: &str,
+    settings: &SilenceSettings,
+) -> Result<Vec<f64>> {
+    let path = Path::new(input_filename).to_path_buf();
+    let native_settings = *settings;
+    let native =
+        tokio::task::spawn_blocking(move || analyze_silences(&path, &native_settings)).await?;
+    match native {
+        Ok(silences) => return Ok(silences.iter().map(|s| s.start).collect()),
+        Err(e) => log::info!(
+            "Using ffmpeg to find silences in {}: {:#}",
+            input_filename,
+            e
+        ),
+    }
+
+    let threshold = match settings.threshold {
+        SilenceThreshold::Decibels(db) => db,
+        SilenceThreshold::Auto => auto_threshold(tools, input_filename).await?,
+    };
+    // Run ffmpeg command with output capture
+    let output = tools
+        .runner
+        .output(
+            &tools.ffmpeg,
+            &[
+                "-i".to_string(),
+                input_filename.to_string(),
+                "-af".to_string(),
+                format!(
+                    "silencedetect=n={}dB:d={}",
+                    threshold, settings.min_duration
+                ),
+                "-f".to_string(),
+                "null".to_string(),
+                "-".to_string(),
+            ],
+        )
+        .await
+        .context("Failed to execute ffmpeg")?;
+
+    // Parse ffmpeg output to get split points
+    let output =
+        String::from_utf8(output.stderr.clone()).context("Failed to parse ffmpeg output")?;
+    let mut silences = Vec::new();
+    for line in output.lines() {
+        if let Some(start) = line.find("silence_start:") {
+            let start = line[start + 14..].trim();
+            let start = start
+                .parse::<f64>()
+                .context("Failed to parse split point")?;
+            silences.push(start);
+        }
+    }
+
+    Ok(silences)
+}
+
+#[test]
+fn test_auto_threshold_sits_above_noise_floor() {
+    let stderr = "[Parsed_astats_0 @ 0x1] Overall\n\
+                  [Parsed_astats_0 @ 0x1] Noise floor dB: -52.318\n";
+    assert!((threshold_from_astats(stderr).unwrap() + 42.318).abs() < 1e-9);
+    assert_eq!(
+        threshold_from_astats("[Parsed_astats_0 @ 0x1] Noise floor dB: -inf"),
+        Some(-60.0)
+    );
+    assert_eq!(threshold_from_astats("no stats here"), None);
+
+    let json = serde_json::to_string(&SilencePreset::LiveLecture.settings()).unwrap();
+    assert_eq!(json, r#"{"threshold":"auto","min_duration":1.0}"#);
+    let custom: SilenceSettings =
+        serde_json::from_str(r#"{"threshold":{"decibels":-35},"min_duration":0.5}"#).unwrap();
+    assert_eq!(custom.threshold, SilenceThreshold::Decibels(-35.0));
+}
+
+#[test]
+fn test_analyze_silences_in_synthetic_wav() {
+    // 1s of tone, 1.5s of silence, 1s of tone, a 0.3s pause, then 1s of tone, at 8kHz
+    let rate = 8000;
+    let tone = |seconds: f64| {
+        (0..(seconds * rate as f64) as usize)
+            .map(|i| {
+                ((i as f64 * 440.0 * std::f64::consts::TAU / rate as f64).sin() * 16000.0) as i16
+            })
+            .collect::<Vec<_>>()
+    };
+    let quiet = |seconds: f64| vec![0i16; (seconds * rate as f64) as usize];
+    let samples = [tone(1.0), quiet(1.5), tone(1.0), quiet(0.3), tone(1.0)].concat();
+
+    let mut wav = Vec::new();
+    let data_len = samples.len() as u32 * 2;
+    wav.extend(b"RIFF");
+    wav.extend((36 + data_len).to_le_bytes());
+    wav.extend(b"WAVEfmt ");
+    wav.extend(16u32.to_le_bytes());
+    wav.extend(1u16.to_le_bytes()); // PCM
+    wav.extend(1u16.to_le_bytes()); // mono
+    wav.extend((rate as u32).to_le_bytes());
+    wav.extend((rate as u32 * 2).to_le_bytes());
+    wav.extend(2u16.to_le_bytes());
+    wav.extend(16u16.to_le_bytes());
+    wav.extend(b"data");
+    wav.extend(data_len.to_le_bytes());
+    for sample in &samples {
+        wav.extend(sample.to_le_bytes());
+    }
+    let path = std::env::temp_dir().join("sync-and-swim-silence-test.wav");
+    std::fs::write(&path, wav).unwrap();
+
+    let silences = analyze_silences(&path, &SilenceSettings::default()).unwrap();
+    assert_eq!(
+        silences,
+        vec![SilenceInterval {
+            start: 1.0,
+            end: 2.5
+        }]
+    );
+    // With no noise at all, the automatic threshold lands at the bottom of its range
+    let auto = SilenceSettings {
+        threshold: SilenceThreshold::Auto,
+        min_duration: 0.25,
+    };
+    let silences = analyze_silences(&path, &auto).unwrap();
+    assert_eq!(silences.len(), 2);
+    assert!((silences[1].start - 3.5).abs() < 1e-9);
+}
@@ -0,0 +1,77 @@
+use anyhow::Result;
+use std::fs;
+
+use crate::config_file::ConfigFile;
+use crate::engine::feeds::{self, FeedRefresh, FeedSubscription};
+
+/// File in the app config directory holding the feed subscriptions
+const FEEDS_FILE: &str = "feeds.json";
+
+fn feeds_file(app: &tauri::AppHandle) -> Result<ConfigFile> {
+    ConfigFile::new(app, FEEDS_FILE, "feeds")
+}
+
+#[tauri::command]
+pub async fn list_feeds(app: tauri::AppHandle) -> Result<Vec<FeedSubscription>, String> {
+    let file = feeds_file(&app).map_err(|e| e.to_string())?;
+    file.read().map_err(|e| e.to_string())
+}
+
+/// Subscribes to a feed, replacing any existing subscription with the same URL
+#[tauri::command]
+pub async fn save_feed(
+    app: tauri::AppHandle,
+    subscription: FeedSubscription,
+) -> Result<(), String> {
+    let file = feeds_file(&app).map_err(|e| e.to_string())?;
+    let mut subscriptions: Vec<FeedSubscription> = file.read().map_err(|e| e.to_string())?;
+    match subscriptions.iter_mut().find(|s| s.url == subscription.url) {
+        Some(existing) => *existing = subscription,
+        None => subscriptions.push(subscription),
+    }
+    file.write(&subscriptions).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn delete_feed(app: tauri::AppHandle, url: &str) -> Result<(), String> {
+    let file = feeds_file(&app).map_err(|e| e.to_string())?;
+    let mut subscriptions: Vec<FeedSubscription> = file.read().map_err(|e| e.to_string())?;
+    subscriptions.retain(|s| s.url != url);
+    file.write(&subscriptions).map_err(|e| e.to_string())
+}
+
+/// Subscribes to every feed in an OPML file that isn't already subscribed, returning the
+/// updated list
+#[tauri::command]
+pub async fn import_opml(
+    app: tauri::AppHandle,
+    opml_path: &str,
+) -> Result<Vec<FeedSubscription>, String> {
+    let contents = fs::read_to_string(opml_path).map_err(|e| e.to_string())?;
+    let imported = feeds::parse_opml(&contents).map_err(|e| e.to_string())?;
+    let file = feeds_file(&app).map_err(|e| e.to_string())?;
+    let mut subscriptions: Vec<FeedSubscription> = file.read().map_err(|e| e.to_string())?;
+    for subscription in imported {
+        if !subscriptions.iter().any(|s| s.url == subscription.url) {
+            subscriptions.push(subscription);
+        }
+    }
+    file.write(&subscriptions).map_err(|e| e.to_string())?;
+    Ok(subscriptions)
+}
+
+/// Downloads new episodes of every subscribed feed into the staging folder. The downloads
+/// block, so they run on a blocking thread rather than holding up the async runtime.
+#[tauri::command]
+pub async fn refresh_feeds(
+    staging_path: String,
+    app: tauri::AppHandle,
+    window: tauri::Window,
+) -> Result<Vec<FeedRefresh>, String> {
+    let subscriptions = list_feeds(app).await?;
+    tokio::task::spawn_blocking(move || {
+        feeds::refresh_feeds(&subscriptions, &staging_path, &window)
+    })
+    .await
+    .map_err(|e| e.to_string())
+}
@@ -0,0 +1,45 @@
+use anyhow::Result;
+
+use crate::config_file::ConfigFile;
+use crate::engine::ffmpeg_capabilities::{self, FfmpegReport};
+use crate::engine::find_ffmpeg::FfmpegPaths;
+use crate::engine::{FfmpegTools, SystemRunner};
+
+/// File in the app config directory holding the chosen ffmpeg and ffprobe
+const FFMPEG_SETTINGS_FILE: &str = "ffmpeg.json";
+
+fn settings_file(app: &tauri::AppHandle) -> Result<ConfigFile> {
+    ConfigFile::new(app, FFMPEG_SETTINGS_FILE, "ffmpeg settings")
+}
+
+/// The configured paths, or none when the settings can't be read
+pub fn configured_paths(app: &tauri::AppHandle) -> FfmpegPaths {
+    settings_file(app)
+        .and_then(|file| file.read())
+        .unwrap_or_else(|e| {
+            log::error!("{:#}", e);
+            FfmpegPaths::default()
+        })
+}
+
+/// ffmpeg and ffprobe as configured, or wherever they can be found
+pub fn locate_tools(app: &tauri::AppHandle) -> Result<FfmpegTools<SystemRunner>> {
+    FfmpegTools::locate_with(SystemRunner, &configured_paths(app))
+}
+
+#[tauri::command]
+pub async fn get_ffmpeg_paths(app: tauri::AppHandle) -> Result<FfmpegPaths, String> {
+    let file = settings_file(&app).map_err(|e| e.to_string())?;
+    file.read().map_err(|e| e.to_string())
+}
+
+/// Saves the chosen paths and reports on the ffmpeg they lead to
+#[tauri::command]
+pub async fn save_ffmpeg_paths(
+    app: tauri::AppHandle,
+    paths: FfmpegPaths,
+) -> Result<FfmpegReport, String> {
+    let file = settings_file(&app).map_err(|e| e.to_string())?;
+    file.write(&paths).map_err(|e| e.to_string())?;
+    Ok(ffmpeg_capabilities::check_ffmpeg(&SystemRunner, &paths).await)
+}
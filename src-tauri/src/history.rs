@@ -0,0 +1,144 @@
+use anyhow::Result;
+use log::error;
+use std::future::Future;
+use std::sync::Mutex;
+use tauri::Manager;
+
+use crate::engine::files::{CopyReport, DestinationResult};
+use crate::engine::history::{HistoryDb, HistoryQuery, HistoryRun, RecordedFile, RunKind};
+use crate::engine::AudioFile;
+
+/// File in the app data directory holding the transfer and split history
+const HISTORY_FILE: &str = "history.sqlite3";
+
+/// The history database, shared between commands as Tauri state
+pub struct History(Mutex<HistoryDb>);
+
+pub fn init(app: &tauri::App) -> Result<()> {
+    let path = app.path().app_data_dir()?.join(HISTORY_FILE);
+    app.manage(History(Mutex::new(HistoryDb::open(&path)?)));
+    Ok(())
+}
+
+/// Records the start of a run, returning its id if the database accepted it.
+/// Problems with the database are logged rather than failing the work.
+pub fn start_run(
+    history: &History,
+    kind: RunKind,
+    destination: Option<&str>,
+    settings: &serde_json::Value,
+    files: &[RecordedFile],
+) -> Option<i64> {
+    let mut db = history.0.lock().unwrap();
+    db.start_run(kind, destination, settings, files)
+        .map_err(|e| error!("Failed to record run in history: {}", e))
+        .ok()
+}
+
+pub fn finish_run(history: &History, run_id: Option<i64>, outcome: &Result<(), String>) {
+    if let Some(run_id) = run_id {
+        let db = history.0.lock().unwrap();
+        if let Err(e) = db.finish_run(run_id, outcome) {
+            error!("Failed to record run outcome in history: {}", e);
+        }
+    }
+}
+
+/// Runs `work`, recording its files, settings and outcome in the history database
+pub async fn recorded<F>(
+    history: &History,
+    kind: RunKind,
+    destination: Option<&str>,
+    settings: serde_json::Value,
+    files: &[AudioFile],
+    work: F,
+) -> Result<(), String>
+where
+    F: Future<Output = Result<(), String>>,
+{
+    let recorded_files: Vec<RecordedFile> = files.iter().map(RecordedFile::hashed).collect();
+    let run_id = start_run(history, kind, destination, &settings, &recorded_files);
+    let outcome = work.await;
+    finish_run(history, run_id, &outcome);
+    outcome
+}
+
+/// Which files a copy's history records
+pub enum CopiedFiles<'a> {
+    /// Files copied as they are, hashed by the copy as it reads them
+    Unchanged(&'a [AudioFile]),
+    /// Sources that were re-encoded before copying. The sources are recorded rather than the
+    /// encoded files (or any cues added between them), which are made afresh for every load.
+    EncodedFrom(&'a [AudioFile]),
+}
+
+/// Runs `copy`, recording a run for each destination it copies to. Files copied unchanged are
+/// hashed by the copy as it reads them, so they aren't read a second time beforehand. A source
+/// that couldn't be read fails every run, and is returned once as the error.
+pub fn recorded_copy(
+    history: &History,
+    settings: serde_json::Value,
+    files: CopiedFiles,
+    dest_paths: &[String],
+    copy: impl FnOnce() -> CopyReport,
+) -> Result<Vec<DestinationResult>, String> {
+    let recorded_files: Vec<RecordedFile> = match files {
+        CopiedFiles::Unchanged(files) => files.iter().map(RecordedFile::unhashed).collect(),
+        CopiedFiles::EncodedFrom(sources) => sources.iter().map(RecordedFile::hashed).collect(),
+    };
+    let hashed_by_copy = matches!(files, CopiedFiles::Unchanged(_));
+    let run_ids: Vec<Option<i64>> = dest_paths
+        .iter()
+        .map(|dest_path| {
+            start_run(
+                history,
+                RunKind::Copy,
+                Some(dest_path),
+                &settings,
+                &recorded_files,
+            )
+        })
+        .collect();
+
+    let report = copy();
+
+    for (run_id, result) in run_ids.into_iter().zip(&report.destinations) {
+        if let (Some(run_id), true) = (run_id, hashed_by_copy) {
+            let db = history.0.lock().unwrap();
+            if let Err(e) = db.set_hashes(run_id, &report.sha256) {
+                error!("Failed to record file hashes in history: {}", e);
+            }
+        }
+        let outcome = match result.error.as_ref().or(report.source_error.as_ref()) {
+            Some(error) => Err(error.clone()),
+            None => Ok(()),
+        };
+        finish_run(history, run_id, &outcome);
+    }
+    match report.source_error {
+        Some(error) => Err(error),
+        None => Ok(report.destinations),
+    }
+}
+
+#[tauri::command]
+pub async fn query_history(
+    query: HistoryQuery,
+    history: tauri::State<'_, History>,
+) -> Result<Vec<HistoryRun>, String> {
+    let db = history.0.lock().unwrap();
+    db.query(&query).map_err(|e| e.to_string())
+}
+
+/// Lists which of `files` weren't part of the last load onto the device at `dest_path`
+#[tauri::command]
+pub async fn new_since_last_load(
+    files: Vec<AudioFile>,
+    dest_path: &str,
+    history: tauri::State<'_, History>,
+) -> Result<Vec<RecordedFile>, String> {
+    let recorded_files: Vec<RecordedFile> = files.iter().map(RecordedFile::hashed).collect();
+    let db = history.0.lock().unwrap();
+    db.new_since_last_load(&recorded_files, dest_path)
+        .map_err(|e| e.to_string())
+}
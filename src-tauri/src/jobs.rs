@@ -0,0 +1,267 @@
+use anyhow::Result;
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::time::Duration;
+use tauri::{Emitter, Manager};
+use tokio::sync::Notify;
+
+use crate::engine::encoding::EncodeProgress;
+use crate::engine::jobs::{Job, JobEvent, JobKind, JobState, JobStore, ProgressThrottle};
+use crate::engine::{CopyProgress, ProgressSink, SegmentProgress};
+use crate::history::History;
+use crate::{CopyRequest, SplitRequest, TranscodeRequest};
+
+/// File in the app data directory holding the job queue and each job's events
+const JOBS_FILE: &str = "jobs.sqlite3";
+
+/// How often a running job's progress is logged and passed on. ffmpeg reports twice a second
+/// for every file being split, which is more than the jobs page needs.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What a background job does, with the same settings as the command that does it directly
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum JobSpec {
+    Copy(CopyRequest),
+    Split(SplitRequest),
+    Transcode(TranscodeRequest),
+}
+
+impl JobSpec {
+    fn kind(&self) -> JobKind {
+        match self {
+            JobSpec::Copy(_) => JobKind::Copy,
+            JobSpec::Split(_) => JobKind::Split,
+            JobSpec::Transcode(_) => JobKind::Transcode,
+        }
+    }
+}
+
+/// The job queue, shared between the commands and the worker that runs the jobs
+pub struct Jobs {
+    store: Mutex<JobStore>,
+    /// Wakes the worker when a job is queued
+    queued: Notify,
+}
+
+/// Opens the queue, left as the last session had it, and starts working through it
+pub fn init(app: &tauri::App) -> Result<()> {
+    let path = app.path().app_data_dir()?.join(JOBS_FILE);
+    app.manage(Jobs {
+        store: Mutex::new(JobStore::open(&path)?),
+        queued: Notify::new(),
+    });
+    let app = app.handle().clone();
+    tauri::async_runtime::spawn(async move { run_queue(app).await });
+    Ok(())
+}
+
+/// Sends a job's event to every window, so any of them, reloaded or not, can follow along
+fn publish(app: &tauri::AppHandle, event: Result<JobEvent>) {
+    match event {
+        Ok(event) => {
+            if let Err(e) = app.emit("job-event", event) {
+                error!("Failed to emit job-event: {}", e);
+            }
+        }
+        Err(e) => error!("Failed to record job event: {}", e),
+    }
+}
+
+/// Logs a running job's progress and passes it on to the windows, each kind at most once
+/// every `PROGRESS_INTERVAL`
+struct JobProgress {
+    app: tauri::AppHandle,
+    job_id: i64,
+    throttle: Mutex<ProgressThrottle>,
+}
+
+impl JobProgress {
+    /// Logs the report unless one of its kind was logged recently. With `always` it's
+    /// logged regardless, for reports like a file finishing that shouldn't be skipped.
+    fn record<S: Serialize>(&self, name: &str, payload: S, always: bool) {
+        let payload = match serde_json::to_value(payload) {
+            Ok(payload) => payload,
+            Err(e) => return publish(&self.app, Err(e.into())),
+        };
+        let offered = self.throttle.lock().unwrap().offer(name, payload, always);
+        if let Some(payload) = offered {
+            self.log(name, &payload);
+        }
+    }
+
+    fn log(&self, name: &str, payload: &serde_json::Value) {
+        let jobs = self.app.state::<Jobs>();
+        let event = jobs
+            .store
+            .lock()
+            .unwrap()
+            .record_progress(self.job_id, name, payload);
+        publish(&self.app, event);
+    }
+
+    /// Logs the reports held back since each kind was last logged, so the log ends where
+    /// the job did
+    fn flush(&self) {
+        let held_back = self.throttle.lock().unwrap().take_held_back();
+        for (name, payload) in held_back {
+            self.log(&name, &payload);
+        }
+    }
+}
+
+impl ProgressSink for JobProgress {
+    fn copy_progress(&self, progress: CopyProgress) {
+        let completed = progress.completed;
+        self.record("copy-progress", progress, completed);
+    }
+
+    fn segment_progress(&self, progress: SegmentProgress) {
+        self.record("segment-progress", progress, false);
+    }
+
+    fn encode_progress(&self, progress: EncodeProgress) {
+        self.record("encode-progress", progress, false);
+    }
+}
+
+async fn run_job(app: &tauri::AppHandle, job: &Job) -> Result<(), String> {
+    let spec: JobSpec =
+        serde_json::from_value(job.spec.clone()).map_err(|e| format!("Bad job: {}", e))?;
+    let progress = JobProgress {
+        app: app.clone(),
+        job_id: job.id,
+        throttle: Mutex::new(ProgressThrottle::new(PROGRESS_INTERVAL)),
+    };
+    let history = app.state::<History>();
+    let outcome = match spec {
+        JobSpec::Copy(request) => crate::copy_with(app, &history, request, &progress)
+            .await
+            .and_then(|results| {
+                let failures: Vec<String> = results
+                    .iter()
+                    .filter_map(|result| {
+                        let error = result.error.as_ref()?;
+                        Some(format!("Copying to {} failed: {}", result.dest_path, error))
+                    })
+                    .collect();
+                match failures.is_empty() {
+                    true => Ok(()),
+                    false => Err(failures.join("\n")),
+                }
+            }),
+        JobSpec::Split(request) => crate::split_with(app, &history, request, &progress).await,
+        JobSpec::Transcode(request) => crate::transcode_with(app, request, &progress).await,
+    };
+    progress.flush();
+    outcome
+}
+
+/// Runs queued jobs one at a time, oldest first, for as long as the app is open
+async fn run_queue(app: tauri::AppHandle) {
+    let jobs = app.state::<Jobs>();
+    loop {
+        let next = jobs.store.lock().unwrap().start_next();
+        match next {
+            Ok(Some((job, started))) => {
+                publish(&app, Ok(started));
+                let outcome = run_job(&app, &job).await;
+                let state = match &outcome {
+                    Ok(()) => JobState::Done,
+                    Err(_) => JobState::Failed,
+                };
+                let finished =
+                    jobs.store
+                        .lock()
+                        .unwrap()
+                        .set_state(job.id, state, outcome.err().as_deref());
+                publish(&app, finished);
+            }
+            Ok(None) => jobs.queued.notified().await,
+            Err(e) => {
+                error!("Failed to start the next job: {}", e);
+                jobs.queued.notified().await;
+            }
+        }
+    }
+}
+
+/// Queues a copy, split or transcode to run in the background, returning it with its id
+#[tauri::command]
+pub async fn submit_job(
+    spec: JobSpec,
+    app: tauri::AppHandle,
+    jobs: tauri::State<'_, Jobs>,
+) -> Result<Job, String> {
+    if let JobSpec::Transcode(request) = &spec {
+        request.check()?;
+    }
+    let value = serde_json::to_value(&spec).map_err(|e| e.to_string())?;
+    let (job, queued) = jobs
+        .store
+        .lock()
+        .unwrap()
+        .submit(spec.kind(), &value)
+        .map_err(|e| e.to_string())?;
+    publish(&app, Ok(queued));
+    jobs.queued.notify_one();
+    Ok(job)
+}
+
+/// Lists jobs, newest first, optionally only those in one state
+#[tauri::command]
+pub async fn list_jobs(
+    state: Option<JobState>,
+    jobs: tauri::State<'_, Jobs>,
+) -> Result<Vec<Job>, String> {
+    let store = jobs.store.lock().unwrap();
+    store.jobs(state).map_err(|e| e.to_string())
+}
+
+/// A job's events in order, from after `after_seq` when given, to catch up on what a window
+/// missed
+#[tauri::command]
+pub async fn job_events(
+    job_id: i64,
+    after_seq: Option<i64>,
+    jobs: tauri::State<'_, Jobs>,
+) -> Result<Vec<JobEvent>, String> {
+    let store = jobs.store.lock().unwrap();
+    store.events(job_id, after_seq).map_err(|e| e.to_string())
+}
+
+/// A job's latest progress, for showing where a running job is without replaying its log
+#[tauri::command]
+pub async fn latest_job_progress(
+    job_id: i64,
+    jobs: tauri::State<'_, Jobs>,
+) -> Result<Option<JobEvent>, String> {
+    let store = jobs.store.lock().unwrap();
+    store.latest_progress(job_id).map_err(|e| e.to_string())
+}
+
+/// Queues an interrupted or failed job to run again from the start
+#[tauri::command]
+pub async fn resume_job(
+    job_id: i64,
+    app: tauri::AppHandle,
+    jobs: tauri::State<'_, Jobs>,
+) -> Result<(), String> {
+    let queued = jobs
+        .store
+        .lock()
+        .unwrap()
+        .resume(job_id)
+        .map_err(|e| e.to_string())?;
+    publish(&app, Ok(queued));
+    jobs.queued.notify_one();
+    Ok(())
+}
+
+/// Forgets a job that isn't running, along with its events
+#[tauri::command]
+pub async fn discard_job(job_id: i64, jobs: tauri::State<'_, Jobs>) -> Result<(), String> {
+    let store = jobs.store.lock().unwrap();
+    store.discard(job_id).map_err(|e| e.to_string())
+}
@@ -1,130 +1,125 @@
+use engine::audio_format::OutputFormat;
+use engine::device_report::DeviceReport;
+use engine::encoding::{EncodeProgress, EncodingPreset, FitEstimate, PresetInfo};
+use engine::feeds::FeedProgress;
+use engine::ffmpeg_capabilities::FfmpegReport;
+use engine::files::DestinationResult;
+use engine::history::RunKind;
+use engine::marker_tones::MarkerTones;
+use engine::media_info::{MediaInfo, ProbedFile};
+use engine::playlist::PlaylistLoad;
+use engine::silence::{SilencePreset, SilencePresetInfo, SilenceSettings};
+use engine::{
+    AudioFile, CopyProgress, ProgressSink, SegmentOptions, SegmentProgress, SystemRunner,
+};
+use history::{CopiedFiles, History};
 use log::{error, info};
 use serde::{Deserialize, Serialize};
 use std::env;
-use std::fs;
-use std::fs::remove_file;
-use std::path::PathBuf;
-use tauri::Emitter;
-use tauri_plugin_shell::ShellExt;
-mod audio_segment;
-mod find_ffmpeg;
+use tauri::{Emitter, Manager};
+mod config_file;
+pub mod engine;
+mod feeds;
+mod ffmpeg_settings;
+mod history;
+mod jobs;
+mod profiles;
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct AudioFile {
-    name: String,
-    path: String,
-    relative_path: String,
-}
-
-#[derive(Debug, Serialize, Clone)]
-pub struct CopyProgress {
-    file_name: String,
-    completed: bool,
-    index: usize,
-    total: usize,
+/// Sends a progress event to the window. A failed emit shouldn't abort the work it reports on.
+fn emit_progress<S: Serialize + Clone>(window: &tauri::Window, event: &str, payload: S) {
+    if let Err(e) = window.emit(event, payload) {
+        error!("Failed to emit {}: {}", event, e);
+    }
 }
 
-fn visit_dirs(dir: &PathBuf, base_path: &PathBuf) -> Result<Vec<AudioFile>, String> {
-    let mut files = Vec::new();
+impl ProgressSink for tauri::Window {
+    fn copy_progress(&self, progress: CopyProgress) {
+        emit_progress(self, "copy-progress", progress);
+    }
 
-    if dir.is_dir() {
-        for entry in fs::read_dir(dir).map_err(|e| e.to_string())? {
-            let entry = entry.map_err(|e| e.to_string())?;
-            let path = entry.path();
+    fn segment_progress(&self, progress: SegmentProgress) {
+        emit_progress(self, "segment-progress", progress);
+    }
 
-            if path.is_dir() {
-                files.extend(visit_dirs(&path, base_path)?);
-            } else if path.is_file() {
-                if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                    // Skip .DS_Store files
-                    if name == ".DS_Store" {
-                        continue;
-                    }
-                    // Get the relative path by stripping the base path
-                    let relative = path
-                        .strip_prefix(base_path)
-                        .map_err(|e| e.to_string())?
-                        .parent()
-                        .and_then(|p| p.to_str())
-                        .unwrap_or("")
-                        .to_string();
+    fn encode_progress(&self, progress: EncodeProgress) {
+        emit_progress(self, "encode-progress", progress);
+    }
 
-                    files.push(AudioFile {
-                        name: name.to_string(),
-                        path: path.to_string_lossy().to_string(),
-                        relative_path: relative,
-                    });
-                }
-            }
-        }
+    fn feed_progress(&self, progress: FeedProgress) {
+        emit_progress(self, "feed-progress", progress);
     }
-    Ok(files)
 }
 
 #[tauri::command]
 async fn deep_list_files(path: &str) -> Result<Vec<AudioFile>, String> {
-    let base_path = PathBuf::from(path);
-    visit_dirs(&base_path, &base_path)
+    engine::files::deep_list_files(path)
 }
 
 #[tauri::command]
 async fn shallow_list_files(path: &str) -> Result<Vec<AudioFile>, String> {
-    let dir = PathBuf::from(path);
-    let mut files = Vec::new();
-
-    if dir.is_dir() {
-        for entry in fs::read_dir(&dir).map_err(|e| e.to_string())? {
-            let entry = entry.map_err(|e| e.to_string())?;
-            let path = entry.path();
+    engine::files::shallow_list_files(path)
+}
 
-            if path.is_file() {
-                if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                    if name == ".DS_Store" {
-                        continue;
-                    }
-                    files.push(AudioFile {
-                        name: name.to_string(),
-                        path: path.to_string_lossy().to_string(),
-                        relative_path: String::new(), // No subdirs for this function
-                    });
-                }
-            }
-        }
+/// Lists the audio files in a folder. With `probe`, each file's length, format, tags and
+/// chapters are filled in too.
+#[tauri::command]
+async fn list_audio_files(
+    path: &str,
+    probe: Option<bool>,
+    app: tauri::AppHandle,
+) -> Result<Vec<AudioFile>, String> {
+    let files = engine::files::list_audio_files(path)?;
+    if !probe.unwrap_or(false) {
+        return Ok(files);
     }
+    let tools = ffmpeg_settings::locate_tools(&app).ok();
+    Ok(engine::media_info::with_media_info(tools.as_ref(), files).await)
+}
 
-    files.sort_by(|a, b| a.name.cmp(&b.name));
-    Ok(files)
+/// Describes one file: its length, format, tags, chapters and cover art
+#[tauri::command]
+async fn probe_audio_file(path: &str, app: tauri::AppHandle) -> Result<MediaInfo, String> {
+    let tools = ffmpeg_settings::locate_tools(&app).ok();
+    engine::media_info::probe_file(tools.as_ref(), path)
+        .await
+        .map_err(|e| format!("{:#}", e))
 }
 
+/// Describes each file, noting the ones that couldn't be read
 #[tauri::command]
-async fn list_audio_files(path: &str) -> Result<Vec<AudioFile>, String> {
-    let base_path = PathBuf::from(path);
-    let mut files = visit_dirs(&base_path, &base_path)?;
-    files.sort_by(|a, b| {
-        // First compare by relative path
-        let path_cmp = a.relative_path.cmp(&b.relative_path);
-        if path_cmp == std::cmp::Ordering::Equal {
-            // If paths are equal, compare by name
-            a.name.cmp(&b.name)
-        } else {
-            path_cmp
-        }
-    });
-    Ok(files)
+async fn probe_audio_files(paths: Vec<String>, app: tauri::AppHandle) -> Vec<ProbedFile> {
+    let tools = ffmpeg_settings::locate_tools(&app).ok();
+    engine::media_info::probe_files(tools.as_ref(), &paths).await
 }
 
+/// Resolves a playlist's entries to files, optionally all in one folder on the device
 #[tauri::command]
-async fn delete_files(files: Vec<AudioFile>) -> Result<(), String> {
-    for file in files {
-        remove_file(&file.path).map_err(|e| format!("Failed to delete {}: {}", file.name, e))?;
-    }
-    Ok(())
+async fn load_playlist(
+    playlist_path: &str,
+    folder: Option<String>,
+) -> Result<PlaylistLoad, String> {
+    engine::playlist::load_playlist(playlist_path, folder.as_deref()).map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-async fn check_ffmpeg(app: tauri::AppHandle) -> Result<bool, String> {
-    let shell = app.shell();
+async fn delete_files(
+    files: Vec<AudioFile>,
+    history: tauri::State<'_, History>,
+) -> Result<(), String> {
+    history::recorded(
+        &history,
+        RunKind::Delete,
+        None,
+        serde_json::json!({}),
+        &files,
+        async { engine::files::delete_files(files.clone()) },
+    )
+    .await
+}
 
+/// Finds ffmpeg and ffprobe, as configured or wherever they are, and reports what they can do
+#[tauri::command]
+async fn check_ffmpeg(app: tauri::AppHandle) -> Result<FfmpegReport, String> {
     // Log the PATH environment variable
     if let Ok(path) = env::var("PATH") {
         info!("Current PATH: {}", path);
@@ -132,137 +127,337 @@ async fn check_ffmpeg(app: tauri::AppHandle) -> Result<bool, String> {
         error!("PATH environment variable not found");
     }
 
-    match find_ffmpeg::find_ffmpeg(shell).await {
-        Some(path) => {
-            info!("Found ffmpeg at: {}", path.display());
-            Ok(true)
-        }
-        None => {
-            error!("FFmpeg not found in PATH or common locations");
-            Ok(false)
-        }
+    let paths = ffmpeg_settings::configured_paths(&app);
+    let report = engine::ffmpeg_capabilities::check_ffmpeg(&SystemRunner, &paths).await;
+    match &report.ffmpeg {
+        Some(found) => info!(
+            "Found ffmpeg {} at: {} ({})",
+            report.version.as_deref().unwrap_or("of unknown version"),
+            found.path.display(),
+            found.source.describe()
+        ),
+        None => error!("FFmpeg not found in the settings, app bundle, PATH or common locations"),
     }
+    for warning in &report.warnings {
+        error!("{}", warning);
+    }
+    Ok(report)
+}
+
+/// How to split files, as the split command and split jobs take it
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct SplitRequest {
+    files: Vec<AudioFile>,
+    dest_path: String,
+    chunk_minutes: u32,
+    cut_at_silence: bool,
+    silence: Option<SilenceSettings>,
+    by_chapters: bool,
+    tempo: Option<f64>,
+    preset: Option<EncodingPreset>,
+    format: Option<OutputFormat>,
+    overlap_seconds: Option<f64>,
+    concurrency: Option<usize>,
+}
+
+async fn split_with(
+    app: &tauri::AppHandle,
+    history: &History,
+    request: SplitRequest,
+    progress: &impl ProgressSink,
+) -> Result<(), String> {
+    // MP3s can be split without ffmpeg, so a missing ffmpeg only matters for other files
+    let tools = ffmpeg_settings::locate_tools(app).ok();
+    let options = SegmentOptions {
+        silence: request.silence.unwrap_or_default(),
+        tempo: request.tempo.unwrap_or(1.0),
+        preset: request.preset.unwrap_or_default(),
+        format: request.format.unwrap_or_default(),
+        overlap: request.overlap_seconds.unwrap_or(0.0),
+        ..SegmentOptions::from_minutes(
+            request.chunk_minutes,
+            request.cut_at_silence,
+            request.by_chapters,
+        )
+    };
+    history::recorded(
+        history,
+        RunKind::Split,
+        Some(&request.dest_path),
+        serde_json::json!({
+            "chunk_minutes": request.chunk_minutes,
+            "cut_at_silence": request.cut_at_silence,
+            "silence": options.silence,
+            "by_chapters": request.by_chapters,
+            "tempo": options.tempo,
+            "preset": options.preset,
+            "format": options.format,
+            "overlap_seconds": options.overlap,
+            "concurrency": request.concurrency,
+        }),
+        &request.files,
+        engine::audio_segment::split_files(
+            tools.as_ref(),
+            &request.files,
+            &request.dest_path,
+            &options,
+            request
+                .concurrency
+                .unwrap_or_else(engine::audio_segment::default_concurrency),
+            progress,
+        ),
+    )
+    .await
 }
 
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 async fn split_audio_files(
     files: Vec<AudioFile>,
-    dest_path: &str,
+    dest_path: String,
     chunk_minutes: u32,
     cut_at_silence: bool,
+    silence: Option<SilenceSettings>,
+    by_chapters: bool,
+    tempo: Option<f64>,
+    preset: Option<EncodingPreset>,
+    format: Option<OutputFormat>,
+    overlap_seconds: Option<f64>,
+    concurrency: Option<usize>,
     window: tauri::Window,
+    history: tauri::State<'_, History>,
 ) -> Result<(), String> {
-    for (index, file) in files.iter().enumerate() {
-        // Emit progress start
-        window
-            .emit(
-                "copy-progress",
-                CopyProgress {
-                    file_name: file.name.clone(),
-                    completed: false,
-                    index,
-                    total: files.len(),
-                },
-            )
-            .map_err(|e| e.to_string())?;
+    let request = SplitRequest {
+        files,
+        dest_path,
+        chunk_minutes,
+        cut_at_silence,
+        silence,
+        by_chapters,
+        tempo,
+        preset,
+        format,
+        overlap_seconds,
+        concurrency,
+    };
+    split_with(window.app_handle(), &history, request, &window).await
+}
 
-        // Convert chunk_minutes to seconds for segment_audio
-        let segment_time = (chunk_minutes * 60) as i32;
+/// What to copy and where, as the copy command and copy jobs take it
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct CopyRequest {
+    files: Vec<AudioFile>,
+    dest_paths: Vec<String>,
+    mode: String,
+    marker_tones: Option<MarkerTones>,
+    preset: Option<EncodingPreset>,
+}
 
-        // Call segment_audio for each file with progress tracking
-        audio_segment::segment_audio(
-            &file.path,
-            dest_path,
-            segment_time,
-            cut_at_silence,
-            &window,
-            index,
-            files.len(),
+async fn copy_with(
+    app: &tauri::AppHandle,
+    history: &History,
+    request: CopyRequest,
+    progress: &impl ProgressSink,
+) -> Result<Vec<DestinationResult>, String> {
+    let CopyRequest {
+        files,
+        dest_paths,
+        mode,
+        marker_tones,
+        preset,
+    } = request;
+    let marker_tones = marker_tones.unwrap_or_default();
+    let preset = preset.unwrap_or_default();
+    let settings = serde_json::json!({
+        "mode": mode,
+        "marker_tones": marker_tones,
+        "preset": preset,
+    });
+    let copy = |files: Vec<AudioFile>| {
+        engine::files::copy_files_to_destinations(files, &dest_paths, &mode, progress)
+    };
+    if marker_tones == MarkerTones::None && preset == EncodingPreset::KeepOriginal {
+        return history::recorded_copy(
+            history,
+            settings,
+            CopiedFiles::Unchanged(&files),
+            &dest_paths,
+            || copy(files.clone()),
+        );
+    }
+
+    let tools = ffmpeg_settings::locate_tools(app).map_err(|e| e.to_string())?;
+    let cache_dir = app.path().app_cache_dir().map_err(|e| e.to_string())?;
+    // Each load encodes into a folder of its own, so a background job and a copy started
+    // from the window can run side by side
+    let work_dir = cache_dir
+        .join("encoded")
+        .join(format!("{:016x}", rand::random::<u64>()));
+    let results = async {
+        let encoded =
+            engine::encoding::encode_files(&tools, files.clone(), preset, &work_dir, progress)
+                .await
+                .map_err(|e| e.to_string())?;
+        let encoded = engine::marker_tones::insert_marker_tones(
+            &tools,
+            encoded,
+            marker_tones,
+            &cache_dir.join("marker-tones"),
         )
         .await
-        .map_err(|e| format!("Failed to split {}: {}", file.name, e))?;
-
-        // Emit progress completion
-        window
-            .emit(
-                "copy-progress",
-                CopyProgress {
-                    file_name: file.name.clone(),
-                    completed: true,
-                    index,
-                    total: files.len(),
-                },
-            )
-            .map_err(|e| e.to_string())?;
+        .map_err(|e| e.to_string())?;
+        history::recorded_copy(
+            history,
+            settings,
+            CopiedFiles::EncodedFrom(&files),
+            &dest_paths,
+            || copy(encoded),
+        )
     }
-    Ok(())
+    .await;
+    let _ = std::fs::remove_dir_all(&work_dir);
+    results
 }
 
+/// Copies the files to every destination at once, reporting how each one went.
+/// With a `preset`, files are re-encoded first. With `marker_tones`, a short cue is played
+/// before each folder after the first.
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 async fn copy_files(
     files: Vec<AudioFile>,
-    dest_path: &str,
-    mode: &str,
+    dest_paths: Vec<String>,
+    mode: String,
+    marker_tones: Option<MarkerTones>,
+    preset: Option<EncodingPreset>,
+    app: tauri::AppHandle,
     window: tauri::Window,
-) -> Result<(), String> {
-    // Delete destination directory if mode is "replace"
-    if mode == "replace" {
-        if let Err(e) = fs::remove_dir_all(dest_path) {
-            // Ignore if directory doesn't exist
-            if e.kind() != std::io::ErrorKind::NotFound {
-                return Err(e.to_string());
+    history: tauri::State<'_, History>,
+) -> Result<Vec<DestinationResult>, String> {
+    let request = CopyRequest {
+        files,
+        dest_paths,
+        mode,
+        marker_tones,
+        preset,
+    };
+    copy_with(&app, &history, request, &window).await
+}
+
+/// Files to re-encode with a preset into a folder, as transcode jobs take them
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct TranscodeRequest {
+    files: Vec<AudioFile>,
+    dest_path: String,
+    preset: EncodingPreset,
+}
+
+impl TranscodeRequest {
+    /// Keeping the original encoding would leave nothing in `dest_path`
+    fn check(&self) -> Result<(), String> {
+        match self.preset {
+            EncodingPreset::KeepOriginal => {
+                Err("Choose a preset to re-encode with, rather than keeping the original".into())
             }
+            _ => Ok(()),
         }
-        fs::create_dir_all(dest_path).map_err(|e| e.to_string())?;
     }
+}
 
-    let total = files.len();
-
-    for (index, file) in files.into_iter().enumerate() {
-        // Emit progress start
-        window
-            .emit(
-                "copy-progress",
-                CopyProgress {
-                    file_name: file.name.clone(),
-                    completed: false,
-                    index,
-                    total,
-                },
-            )
-            .map_err(|e| e.to_string())?;
+async fn transcode_with(
+    app: &tauri::AppHandle,
+    request: TranscodeRequest,
+    progress: &impl ProgressSink,
+) -> Result<(), String> {
+    request.check()?;
+    let tools = ffmpeg_settings::locate_tools(app).map_err(|e| e.to_string())?;
+    engine::encoding::encode_files(
+        &tools,
+        request.files,
+        request.preset,
+        std::path::Path::new(&request.dest_path),
+        progress,
+    )
+    .await
+    .map(|_| ())
+    .map_err(|e| e.to_string())
+}
 
-        // Perform the blocking copy
-        let src_path = PathBuf::from(&file.path);
-        let mut dest_file = PathBuf::from(dest_path);
+#[tauri::command]
+async fn list_encoding_presets() -> Vec<PresetInfo> {
+    EncodingPreset::ALL.iter().map(|p| p.info()).collect()
+}
 
-        // Create subdirectory if relative_path is not empty
-        if !file.relative_path.is_empty() {
-            dest_file = dest_file.join(&file.relative_path);
-            fs::create_dir_all(&dest_file).map_err(|e| e.to_string())?;
-        }
+#[tauri::command]
+async fn list_silence_presets() -> Vec<SilencePresetInfo> {
+    SilencePreset::ALL.iter().map(|p| p.info()).collect()
+}
 
-        dest_file = dest_file.join(&file.name);
-        fs::copy(&src_path, &dest_file)
-            .map_err(|e| format!("Failed to copy {}: {}", file.name, e))?;
+/// Predicts how the selection would fit on the destination with each encoding preset
+#[tauri::command]
+async fn estimate_fit(
+    files: Vec<AudioFile>,
+    dest_path: &str,
+    app: tauri::AppHandle,
+) -> Result<Vec<FitEstimate>, String> {
+    let tools = ffmpeg_settings::locate_tools(&app).map_err(|e| e.to_string())?;
+    engine::encoding::estimate_fit(&tools, &files, dest_path)
+        .await
+        .map_err(|e| e.to_string())
+}
 
-        // Small delay to ensure files have distinct timestamps
-        std::thread::sleep(std::time::Duration::from_millis(100));
+#[tauri::command]
+async fn create_disk_image(path: &str, size_mb: Option<u64>) -> Result<(), String> {
+    engine::disk_image::create_disk_image(
+        std::path::Path::new(path),
+        size_mb.unwrap_or(engine::disk_image::DEFAULT_IMAGE_SIZE_MB),
+    )
+    .map_err(|e| e.to_string())
+}
 
-        // Emit progress completion
-        window
-            .emit(
-                "copy-progress",
-                CopyProgress {
-                    file_name: file.name,
-                    completed: true,
-                    index,
-                    total,
-                },
-            )
-            .map_err(|e| e.to_string())?;
+/// Copies a prepared disk image's contents onto the headphones, in the image's play order
+#[tauri::command]
+async fn copy_disk_image_to_device(
+    image_path: &str,
+    dest_path: &str,
+    mode: &str,
+    window: tauri::Window,
+    history: tauri::State<'_, History>,
+) -> Result<(), String> {
+    let files = engine::disk_image::list_disk_image(image_path)?;
+    let results = history::recorded_copy(
+        &history,
+        serde_json::json!({ "mode": mode, "image": image_path }),
+        CopiedFiles::Unchanged(&files),
+        &[dest_path.to_string()],
+        || engine::disk_image::copy_image_to_device(image_path, dest_path, mode, &window),
+    )?;
+    match results.into_iter().next().and_then(|r| r.error) {
+        Some(error) => Err(error),
+        None => Ok(()),
     }
-    Ok(())
+}
+
+/// Writes an M3U8 playlist and a text report of what's on the device, in play order
+#[tauri::command]
+async fn export_device_contents(
+    dest_path: &str,
+    playlist_path: &str,
+    report_path: &str,
+    app: tauri::AppHandle,
+) -> Result<DeviceReport, String> {
+    let tools = ffmpeg_settings::locate_tools(&app).ok();
+    engine::device_report::export_device_contents(
+        dest_path,
+        tools.as_ref(),
+        std::path::Path::new(playlist_path),
+        std::path::Path::new(report_path),
+    )
+    .await
+    .map_err(|e| e.to_string())
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -279,14 +474,48 @@ pub fn run() {
         )
         .plugin(tauri_plugin_shell::init())
         .plugin(tauri_plugin_dialog::init())
+        .setup(|app| {
+            history::init(app)?;
+            jobs::init(app)?;
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             list_audio_files,
+            probe_audio_file,
+            probe_audio_files,
+            load_playlist,
             copy_files,
             shallow_list_files,
             deep_list_files,
             split_audio_files,
             check_ffmpeg,
             delete_files,
+            profiles::list_profiles,
+            profiles::save_profile,
+            profiles::load_profile,
+            profiles::delete_profile,
+            profiles::run_profile,
+            history::query_history,
+            history::new_since_last_load,
+            jobs::submit_job,
+            jobs::list_jobs,
+            jobs::job_events,
+            jobs::latest_job_progress,
+            jobs::resume_job,
+            jobs::discard_job,
+            list_encoding_presets,
+            list_silence_presets,
+            estimate_fit,
+            create_disk_image,
+            copy_disk_image_to_device,
+            export_device_contents,
+            ffmpeg_settings::get_ffmpeg_paths,
+            ffmpeg_settings::save_ffmpeg_paths,
+            feeds::list_feeds,
+            feeds::save_feed,
+            feeds::delete_feed,
+            feeds::import_opml,
+            feeds::refresh_feeds,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
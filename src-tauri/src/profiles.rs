@@ -0,0 +1,71 @@
+use anyhow::Result;
+
+use crate::config_file::ConfigFile;
+use crate::engine::files::copy_files_to_destinations;
+use crate::engine::plan::{plan_transfer, TransferProfile};
+use crate::history::{self, CopiedFiles, History};
+
+/// File in the app config directory holding every saved profile
+const PROFILES_FILE: &str = "profiles.json";
+
+fn profiles_file(app: &tauri::AppHandle) -> Result<ConfigFile> {
+    ConfigFile::new(app, PROFILES_FILE, "profiles")
+}
+
+#[tauri::command]
+pub async fn list_profiles(app: tauri::AppHandle) -> Result<Vec<TransferProfile>, String> {
+    let file = profiles_file(&app).map_err(|e| e.to_string())?;
+    file.read().map_err(|e| e.to_string())
+}
+
+/// Saves a profile, replacing any existing profile with the same name
+#[tauri::command]
+pub async fn save_profile(app: tauri::AppHandle, profile: TransferProfile) -> Result<(), String> {
+    let file = profiles_file(&app).map_err(|e| e.to_string())?;
+    let mut profiles: Vec<TransferProfile> = file.read().map_err(|e| e.to_string())?;
+    profiles.retain(|p| p.name != profile.name);
+    profiles.push(profile);
+    profiles.sort_by(|a, b| a.name.cmp(&b.name));
+    file.write(&profiles).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn load_profile(app: tauri::AppHandle, name: &str) -> Result<TransferProfile, String> {
+    let file = profiles_file(&app).map_err(|e| e.to_string())?;
+    file.read::<Vec<TransferProfile>>()
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .find(|p| p.name == name)
+        .ok_or_else(|| format!("No profile named {}", name))
+}
+
+#[tauri::command]
+pub async fn delete_profile(app: tauri::AppHandle, name: &str) -> Result<(), String> {
+    let file = profiles_file(&app).map_err(|e| e.to_string())?;
+    let mut profiles: Vec<TransferProfile> = file.read().map_err(|e| e.to_string())?;
+    profiles.retain(|p| p.name != name);
+    file.write(&profiles).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn run_profile(
+    name: &str,
+    app: tauri::AppHandle,
+    window: tauri::Window,
+    history: tauri::State<'_, History>,
+) -> Result<(), String> {
+    let profile = load_profile(app, name).await?;
+    let files = plan_transfer(&profile).map_err(|e| e.to_string())?;
+    let dest_paths = [profile.dest_path.clone()];
+    let results = history::recorded_copy(
+        &history,
+        serde_json::json!({ "mode": profile.mode, "profile": profile.name }),
+        CopiedFiles::Unchanged(&files),
+        &dest_paths,
+        || copy_files_to_destinations(files.clone(), &dest_paths, &profile.mode, &window),
+    )?;
+    match results.into_iter().next().and_then(|r| r.error) {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
+}
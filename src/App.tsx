@@ -5,6 +5,7 @@ import FileTransfer from "./components/FileTransfer";
 import SplitFiles from "./components/SplitFiles";
 import FindPlace from "./components/FindPlace";
 import About from "./components/About";
+import Jobs from "./components/Jobs";
 import { open } from "@tauri-apps/plugin-shell";
 
 const menuOptions = [
@@ -35,6 +36,13 @@ const menuOptions = [
       "Lost your place in an audiobook or podcast? This tool helps you quickly find where you left off by playing short segments.",
     component: FindPlace,
   },
+  {
+    id: "jobs",
+    title: "Background Jobs",
+    description:
+      "Follow copies and splits running in the background, and start again any that were interrupted when the app closed.",
+    component: Jobs,
+  },
 ];
 
 function AppContent() {
@@ -0,0 +1,133 @@
+import { useState, useEffect } from "react";
+import { invoke } from "@tauri-apps/api/core";
+
+export type Feature =
+  | "splitting"
+  | "change_tempo"
+  | "encoding_presets"
+  | "marker_tones"
+  | "convert_to_mp3"
+  | "convert_to_m4a"
+  | "convert_to_flac"
+  | "convert_to_wav";
+
+interface FoundFfmpeg {
+  path: string;
+  source: "user_override" | "sidecar" | "path" | "common_path";
+}
+
+interface FeatureStatus {
+  feature: Feature;
+  available: boolean;
+  reason: string | null;
+}
+
+export interface FfmpegReport {
+  ffmpeg: FoundFfmpeg | null;
+  ffprobe: FoundFfmpeg | null;
+  version: string | null;
+  configuration: string | null;
+  features: FeatureStatus[];
+  warnings: string[];
+}
+
+interface FfmpegPaths {
+  ffmpeg: string | null;
+  ffprobe: string | null;
+}
+
+const FFMPEG_SOURCES: Record<FoundFfmpeg["source"], string> = {
+  user_override: "chosen by you",
+  sidecar: "bundled with the app",
+  path: "on your PATH",
+  common_path: "in a common install location",
+};
+
+// The ffmpeg report, fetched once. Undefined while it's being checked.
+export function useFfmpegReport() {
+  const [report, setReport] = useState<FfmpegReport | undefined>(undefined);
+  useEffect(() => {
+    invoke<FfmpegReport>("check_ffmpeg").then(setReport);
+  }, []);
+  return [report, setReport] as const;
+}
+
+// Why a feature is off, or null when it's available (or the report isn't in yet)
+export function unavailableReason(
+  report: FfmpegReport | undefined,
+  feature: Feature
+): string | null {
+  const status = report?.features.find((f) => f.feature === feature);
+  return status && !status.available ? status.reason : null;
+}
+
+interface FfmpegSettingsProps {
+  report: FfmpegReport | undefined;
+  onReport: (report: FfmpegReport) => void;
+}
+
+export function FfmpegSettings({ report, onReport }: FfmpegSettingsProps) {
+  const [paths, setPaths] = useState<FfmpegPaths>({
+    ffmpeg: null,
+    ffprobe: null,
+  });
+
+  useEffect(() => {
+    invoke<FfmpegPaths>("get_ffmpeg_paths").then(setPaths);
+  }, []);
+
+  async function save() {
+    try {
+      onReport(await invoke<FfmpegReport>("save_ffmpeg_paths", { paths }));
+    } catch (error) {
+      alert(`Saving the ffmpeg settings failed: ${error}`);
+    }
+  }
+
+  return (
+    <details className="ffmpeg-settings">
+      <summary>
+        {report?.ffmpeg
+          ? `Using ffmpeg ${report.version ?? ""} ${
+              FFMPEG_SOURCES[report.ffmpeg.source]
+            }`
+          : "ffmpeg settings"}
+      </summary>
+      {report?.ffmpeg && <p>ffmpeg: {report.ffmpeg.path}</p>}
+      {report?.ffprobe && (
+        <p>
+          ffprobe: {report.ffprobe.path} ({FFMPEG_SOURCES[report.ffprobe.source]})
+        </p>
+      )}
+      {report?.ffmpeg && !report.ffprobe && (
+        <p>ffprobe wasn't found, so ffmpeg describes files instead.</p>
+      )}
+      {report?.warnings.map((warning) => (
+        <p key={warning} className="ffmpeg-missing-warning">
+          {warning}
+        </p>
+      ))}
+      <label>
+        ffmpeg path:
+        <input
+          type="text"
+          placeholder="Search the usual places"
+          value={paths.ffmpeg ?? ""}
+          onChange={(e) => setPaths({ ...paths, ffmpeg: e.target.value || null })}
+        />
+      </label>
+      <label>
+        ffprobe path:
+        <input
+          type="text"
+          placeholder="Search the usual places"
+          value={paths.ffprobe ?? ""}
+          onChange={(e) =>
+            setPaths({ ...paths, ffprobe: e.target.value || null })
+          }
+        />
+      </label>
+      <button onClick={save}>Save and check</button>
+    </details>
+  );
+}
@@ -1,8 +1,10 @@
 import { useState, useEffect } from "react";
 import { FileChoice } from "./FileChoice";
+import { unavailableReason, useFfmpegReport } from "./FfmpegSettings";
 import "./FileTransfer.css";
 import { invoke } from "@tauri-apps/api/core";
 import { listen } from "@tauri-apps/api/event";
+import { submitJob } from "./Jobs";
 
 interface AudioFile {
   name: string;
@@ -16,31 +18,121 @@ interface DirectoryStructure {
 
 interface CopyProgress {
   file_name: string;
+  dest_path: string;
   completed: boolean;
   index: number;
+  finished: number;
   total: number;
 }
 
+interface EncodeProgress {
+  file_name: string;
+  completed: boolean;
+  index: number;
+  finished: number;
+  total: number;
+}
+
+interface DestinationResult {
+  dest_path: string;
+  error: string | null;
+}
+
+interface PresetInfo {
+  preset: string;
+  label: string;
+  bytes_per_hour: number | null;
+}
+
+interface FitEstimate {
+  preset: string;
+  selection_hours: number;
+  estimated_bytes: number;
+  available_bytes: number;
+  hours_that_fit: number;
+  fits: boolean;
+}
+
+interface DestinationProgress {
+  fileName: string;
+  percent: number;
+}
+
 function FileTransfer() {
   const [sourceDir, setSourceDir] = useState<string>("");
   const [destDir, setDestDir] = useState<string>("");
+  // More headphones to load with the same files, at the same time
+  const [extraDestDirs, setExtraDestDirs] = useState<string[]>([]);
   const [files, setFiles] = useState<AudioFile[]>([]);
   const [expandedDirs, setExpandedDirs] = useState<Set<string>>(new Set([""]));
   const [shuffledDirs, setShuffledDirs] = useState<Set<string>>(new Set());
-  const [currentFile, setCurrentFile] = useState<string>("");
-  const [progress, setProgress] = useState<number>(0);
+  const [progress, setProgress] = useState<Record<string, DestinationProgress>>(
+    {}
+  );
+  // Re-encoding with a preset or marker tones, before anything is copied
+  const [encoding, setEncoding] = useState<DestinationProgress | null>(null);
   const [isTransferring, setIsTransferring] = useState(false);
   const [transferMode, setTransferMode] = useState<"append" | "replace">(
     "append"
   );
   const [existingFileCount, setExistingFileCount] = useState<number>(0);
+  const [presets, setPresets] = useState<PresetInfo[]>([]);
+  const [preset, setPreset] = useState<string>("keep_original");
+  const [fitEstimates, setFitEstimates] = useState<FitEstimate[]>([]);
+  const [markerTones, setMarkerTones] = useState<"none" | "pattern" | "count">(
+    "none"
+  );
+
+  const [ffmpegReport] = useFfmpegReport();
+  const markerTonesUnavailable = unavailableReason(
+    ffmpegReport,
+    "marker_tones"
+  );
+  const presetsUnavailable = unavailableReason(
+    ffmpegReport,
+    "encoding_presets"
+  );
+
+  useEffect(() => {
+    invoke<PresetInfo[]>("list_encoding_presets").then(setPresets);
+  }, []);
+
+  useEffect(() => {
+    // Estimates go stale whenever the selection or destination changes
+    setFitEstimates([]);
+  }, [files, destDir]);
+
+  async function handleEstimateFit() {
+    try {
+      setFitEstimates(
+        await invoke<FitEstimate[]>("estimate_fit", {
+          files,
+          destPath: destDir,
+        })
+      );
+    } catch (error) {
+      alert(`Couldn't estimate sizes: ${error}`);
+    }
+  }
 
   useEffect(() => {
     const setupListener = async () => {
       await listen<CopyProgress>("copy-progress", (event) => {
-        const { file_name, index, total } = event.payload;
-        setCurrentFile(file_name);
-        setProgress(Math.round(((index + 1) * 100) / total));
+        const { file_name, dest_path, index, total } = event.payload;
+        setProgress((prev) => ({
+          ...prev,
+          [dest_path]: {
+            fileName: file_name,
+            percent: Math.round(((index + 1) * 100) / total),
+          },
+        }));
+      });
+      await listen<EncodeProgress>("encode-progress", (event) => {
+        const { file_name, finished, total } = event.payload;
+        setEncoding({
+          fileName: file_name,
+          percent: Math.round((finished * 100) / total),
+        });
       });
     };
 
@@ -50,6 +142,9 @@ function FileTransfer() {
       listen<CopyProgress>("copy-progress", () => {}).then((unlisten) =>
         unlisten()
       );
+      listen<EncodeProgress>("encode-progress", () => {}).then((unlisten) =>
+        unlisten()
+      );
     };
   }, []);
 
@@ -91,6 +186,30 @@ function FileTransfer() {
     });
   }
 
+  function copySettings() {
+    return {
+      files,
+      destPaths: [destDir, ...extraDestDirs.filter((dir) => dir)],
+      mode: transferMode,
+      markerTones,
+      preset,
+    };
+  }
+
+  // Queues the copy to run in the background, where it carries on if this page reloads
+  async function handleTransferInBackground() {
+    if (!destDir) {
+      alert("Please select a destination directory");
+      return;
+    }
+    try {
+      await submitJob("copy", copySettings());
+      alert("Queued. Follow it under Background Jobs.");
+    } catch (error) {
+      alert(`Queueing the transfer failed: ${error}`);
+    }
+  }
+
   async function handleTransfer() {
     if (!destDir) {
       alert("Please select a destination directory");
@@ -98,18 +217,29 @@ function FileTransfer() {
     }
     try {
       setIsTransferring(true);
-      await invoke("copy_files", {
-        files,
-        destPath: destDir,
-        mode: transferMode,
-      });
-      alert("Files transferred successfully!");
+      const results = await invoke<DestinationResult[]>(
+        "copy_files",
+        copySettings()
+      );
+      const failures = results.filter((result) => result.error);
+      if (failures.length === 0) {
+        alert("Files transferred successfully!");
+      } else {
+        alert(
+          failures
+            .map(
+              (result) =>
+                `Transfer to ${result.dest_path} failed: ${result.error}`
+            )
+            .join("\n")
+        );
+      }
     } catch (error) {
       alert(`Transfer failed: ${error}`);
     } finally {
       setIsTransferring(false);
-      setProgress(0);
-      setCurrentFile("");
+      setProgress({});
+      setEncoding(null);
 
       // Refresh existing file count after transfer
       if (destDir) {
@@ -179,6 +309,35 @@ function FileTransfer() {
                 }}
                 storageKey="filetransfer_dest_folder" // Add storageKey prop
               />
+              {extraDestDirs.map((dir, i) => (
+                <div key={i} className="extra-destination">
+                  <FileChoice
+                    label="Another Destination Folder"
+                    value={dir}
+                    onChange={(path) =>
+                      setExtraDestDirs((prev) =>
+                        prev.map((d, j) => (j === i ? path : d))
+                      )
+                    }
+                    storageKey={`filetransfer_dest_folder_${i + 1}`}
+                  />
+                  <button
+                    className="remove-destination-button"
+                    onClick={() =>
+                      setExtraDestDirs((prev) => prev.filter((_, j) => j !== i))
+                    }
+                    title="remove"
+                  >
+                    ✕
+                  </button>
+                </div>
+              ))}
+              <button
+                className="add-destination-button"
+                onClick={() => setExtraDestDirs((prev) => [...prev, ""])}
+              >
+                + Load another pair of headphones
+              </button>
             </li>
             <li>
               Choose whether to
@@ -214,6 +373,81 @@ function FileTransfer() {
                 </div>
               </div>
             </li>
+            <li>
+              <label>
+                Between folders, play
+                <select
+                  className="marker-tones"
+                  value={markerTones}
+                  onChange={(e) =>
+                    setMarkerTones(
+                      e.target.value as "none" | "pattern" | "count"
+                    )
+                  }
+                >
+                  <option value="none">nothing</option>
+                  <option value="pattern" disabled={!!markerTonesUnavailable}>
+                    a different tone for each folder
+                  </option>
+                  <option value="count" disabled={!!markerTonesUnavailable}>
+                    one beep per folder number
+                  </option>
+                </select>
+              </label>
+              {markerTonesUnavailable && (
+                <span className="feature-unavailable">
+                  {markerTonesUnavailable}
+                </span>
+              )}
+            </li>
+            <li>
+              <label>
+                Encoding
+                <select
+                  className="encoding-preset"
+                  value={preset}
+                  onChange={(e) => setPreset(e.target.value)}
+                >
+                  {presets.map((p) => (
+                    <option
+                      key={p.preset}
+                      value={p.preset}
+                      disabled={
+                        p.preset !== "keep_original" && !!presetsUnavailable
+                      }
+                    >
+                      {p.label}
+                      {p.bytes_per_hour
+                        ? ` (about ${Math.round(p.bytes_per_hour / 1e6)} MB per hour)`
+                        : ""}
+                    </option>
+                  ))}
+                </select>
+              </label>
+              {presetsUnavailable && (
+                <span className="feature-unavailable">
+                  {presetsUnavailable}
+                </span>
+              )}
+              <button
+                className="estimate-fit-button"
+                onClick={handleEstimateFit}
+                disabled={!destDir || files.length === 0}
+              >
+                How much will fit?
+              </button>
+              {fitEstimates
+                .filter((estimate) => estimate.preset === preset)
+                .map((estimate) => (
+                  <p key={estimate.preset} className="fit-estimate">
+                    {estimate.selection_hours.toFixed(1)} hours selected,
+                    about {(estimate.estimated_bytes / 1e6).toFixed(0)} MB.
+                    There's room for{" "}
+                    {estimate.hours_that_fit.toFixed(1)} hours like this
+                    {estimate.fits ? "." : ", so not all of it will fit."}
+                  </p>
+                ))}
+            </li>
           </ol>
         </div>
         <div className="column">
@@ -286,18 +520,33 @@ function FileTransfer() {
           </div>
         </div>
       </div>
-      {isTransferring && (
+      {isTransferring && encoding && (
         <div className="progress">
-          <p>Copying: {currentFile}</p>
-          <p>Progress: {progress}%</p>
+          <p>Re-encoding: {encoding.fileName}</p>
+          <p>Progress: {encoding.percent}%</p>
           <div className="progress-bar">
             <div
               className="progress-bar-fill"
-              style={{ width: `${progress}%` }}
+              style={{ width: `${encoding.percent}%` }}
             />
           </div>
         </div>
       )}
+      {isTransferring &&
+        Object.entries(progress).map(([dest, { fileName, percent }]) => (
+          <div className="progress" key={dest}>
+            <p>
+              Copying to {dest}: {fileName}
+            </p>
+            <p>Progress: {percent}%</p>
+            <div className="progress-bar">
+              <div
+                className="progress-bar-fill"
+                style={{ width: `${percent}%` }}
+              />
+            </div>
+          </div>
+        ))}
 
       <button
         onClick={handleTransfer}
@@ -306,6 +555,13 @@ function FileTransfer() {
       >
         {isTransferring ? "Transferring..." : "Transfer Files"}
       </button>
+      <button
+        onClick={handleTransferInBackground}
+        disabled={!destDir || files.length === 0}
+        className="transfer-button"
+      >
+        Transfer in Background
+      </button>
     </div>
   );
 }
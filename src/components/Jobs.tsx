@@ -0,0 +1,147 @@
+import { useState, useEffect } from "react";
+import { invoke } from "@tauri-apps/api/core";
+import { listen } from "@tauri-apps/api/event";
+import "./Jobs.css";
+
+type JobState = "queued" | "running" | "done" | "failed" | "interrupted";
+
+interface Job {
+  id: number;
+  kind: "copy" | "split" | "transcode";
+  spec: { files?: { name: string }[]; destPath?: string; destPaths?: string[] };
+  state: JobState;
+  error: string | null;
+  created_at: number;
+  started_at: number | null;
+  finished_at: number | null;
+}
+
+interface JobEvent {
+  job_id: number;
+  seq: number;
+  at: number;
+  name: string;
+  payload: any;
+}
+
+const KIND_LABELS: Record<Job["kind"], string> = {
+  copy: "Copy",
+  split: "Split",
+  transcode: "Re-encode",
+};
+
+const STATE_LABELS: Record<JobState, string> = {
+  queued: "Waiting",
+  running: "Running",
+  done: "Done",
+  failed: "Failed",
+  interrupted: "Interrupted when the app closed",
+};
+
+// Queues a copy, split or re-encode to run in the background
+export async function submitJob(
+  kind: Job["kind"],
+  settings: Record<string, unknown>
+) {
+  return invoke<Job>("submit_job", { spec: { kind, ...settings } });
+}
+
+// What the latest progress event says, e.g. "book.mp3: 40% (80s left)"
+function describeProgress(event: JobEvent | undefined): string | null {
+  if (!event) return null;
+  const { payload } = event;
+  if (event.name === "segment-progress") {
+    const eta =
+      payload.eta_seconds != null
+        ? ` (${Math.ceil(payload.eta_seconds)}s left)`
+        : "";
+    return `${payload.file_name}: ${payload.progress.toFixed(0)}%${eta}`;
+  }
+  if (event.name === "copy-progress") {
+    return `${payload.file_name} (${payload.finished} of ${payload.total} done)`;
+  }
+  if (event.name === "encode-progress") {
+    return `Re-encoding ${payload.file_name} (${payload.finished} of ${payload.total} done)`;
+  }
+  return null;
+}
+
+function Jobs() {
+  const [jobs, setJobs] = useState<Job[]>([]);
+  const [latest, setLatest] = useState<{ [jobId: number]: JobEvent }>({});
+
+  async function refresh() {
+    const jobs = await invoke<Job[]>("list_jobs");
+    setJobs(jobs);
+    // Catch up on progress that happened before this page was open
+    for (const job of jobs.filter((job) => job.state === "running")) {
+      const progress = await invoke<JobEvent | null>("latest_job_progress", {
+        jobId: job.id,
+      });
+      if (progress) {
+        setLatest((prev) => ({ ...prev, [job.id]: progress }));
+      }
+    }
+  }
+
+  useEffect(() => {
+    refresh();
+    const unlisten = listen<JobEvent>("job-event", (event) => {
+      if (event.payload.name === "state") {
+        refresh();
+      } else {
+        setLatest((prev) => ({ ...prev, [event.payload.job_id]: event.payload }));
+      }
+    });
+    return () => {
+      unlisten.then((fn) => fn());
+    };
+  }, []);
+
+  async function act(command: "resume_job" | "discard_job", jobId: number) {
+    try {
+      await invoke(command, { jobId });
+      await refresh();
+    } catch (error) {
+      alert(`${error}`);
+    }
+  }
+
+  return (
+    <div className="jobs-container">
+      {jobs.length === 0 ? (
+        <p>No background jobs yet.</p>
+      ) : (
+        <ul className="job-list">
+          {jobs.map((job) => (
+            <li key={job.id} className={`job job-${job.state}`}>
+              <strong>
+                {KIND_LABELS[job.kind]} {job.spec.files?.length ?? 0} files
+              </strong>{" "}
+              to {job.spec.destPath ?? job.spec.destPaths?.join(", ")}
+              <div className="job-state">
+                {STATE_LABELS[job.state]}
+                {job.state === "running" &&
+                  describeProgress(latest[job.id]) &&
+                  `: ${describeProgress(latest[job.id])}`}
+              </div>
+              {job.error && <div className="job-error">{job.error}</div>}
+              {(job.state === "interrupted" || job.state === "failed") && (
+                <button onClick={() => act("resume_job", job.id)}>
+                  Start again
+                </button>
+              )}
+              {job.state !== "running" && (
+                <button onClick={() => act("discard_job", job.id)}>
+                  {job.state === "done" ? "Clear" : "Discard"}
+                </button>
+              )}
+            </li>
+          ))}
+        </ul>
+      )}
+    </div>
+  );
+}
+
+export default Jobs;
@@ -1,46 +1,168 @@
 import { useState, useEffect } from "react";
 import { invoke } from "@tauri-apps/api/core";
 import { listen } from "@tauri-apps/api/event";
+import { submitJob } from "./Jobs";
 import { FileChoice } from "./FileChoice";
+import {
+  Feature,
+  FfmpegSettings,
+  unavailableReason,
+  useFfmpegReport,
+} from "./FfmpegSettings";
 import "./SplitFiles.css";
 
+interface PresetInfo {
+  preset: string;
+  label: string;
+  bytes_per_hour: number | null;
+}
+
+type SilenceThreshold = "auto" | { decibels: number };
+
+interface SilenceSettings {
+  threshold: SilenceThreshold;
+  min_duration: number;
+}
+
+interface SilencePresetInfo {
+  preset: string;
+  label: string;
+  settings: SilenceSettings;
+}
+
+interface MediaInfo {
+  duration: number | null;
+  codec: string | null;
+  chapters: { start: number; end: number; title: string }[];
+}
+
 interface AudioFile {
   name: string;
   path: string;
   relative_path: string;
+  media?: MediaInfo;
 }
 
+function describeMedia(media: MediaInfo): string {
+  const parts = [];
+  if (media.duration !== null) {
+    const minutes = Math.round(media.duration / 60);
+    parts.push(
+      minutes >= 60
+        ? `${Math.floor(minutes / 60)} h ${minutes % 60} min`
+        : `${minutes} min`
+    );
+  }
+  if (media.codec) parts.push(media.codec);
+  if (media.chapters.length > 0)
+    parts.push(`${media.chapters.length} chapters`);
+  return parts.join(", ");
+}
+
+const FORMATS: [string, string, Feature][] = [
+  ["mp3", "MP3", "convert_to_mp3"],
+  ["m4a", "M4A (AAC)", "convert_to_m4a"],
+  ["flac", "FLAC", "convert_to_flac"],
+  ["wav", "WAV", "convert_to_wav"],
+];
+
 function SplitFiles() {
   const [sourceDir, setSourceDir] = useState<string>("");
   const [destDir, setDestDir] = useState<string>("");
   const [files, setFiles] = useState<AudioFile[]>([]);
   const [chunkMinutes, setChunkMinutes] = useState<number>(3);
   const [progress, setProgress] = useState<{ [key: string]: number }>({});
-  const [currentFile, setCurrentFile] = useState<string>("");
+  const [filesDone, setFilesDone] = useState<number>(0);
+  // Seconds left for each file, when ffmpeg is doing the work
+  const [etas, setEtas] = useState<{ [key: string]: number | null }>({});
   const [isProcessing, setIsProcessing] = useState(false);
-  const [ffmpegAvailable, setFfmpegAvailable] = useState<boolean | null>(null);
+  const [ffmpegReport, setFfmpegReport] = useFfmpegReport();
   const [cutAtSilence, setCutAtSilence] = useState<boolean>(true);
+  const [silencePresets, setSilencePresets] = useState<SilencePresetInfo[]>(
+    []
+  );
+  const [silence, setSilence] = useState<SilenceSettings>({
+    threshold: { decibels: -30 },
+    min_duration: 1,
+  });
+  const [byChapters, setByChapters] = useState<boolean>(false);
+  const [tempo, setTempo] = useState<number>(1);
+  const [overlapSeconds, setOverlapSeconds] = useState<number>(0);
+  // Files split at once; empty means one per core
+  const [concurrency, setConcurrency] = useState<number | null>(null);
+  const [presets, setPresets] = useState<PresetInfo[]>([]);
+  const [preset, setPreset] = useState<string>("keep_original");
+  const [format, setFormat] = useState<string>("keep_input");
 
   useEffect(() => {
-    // Check if ffmpeg is available
-    invoke<boolean>("check_ffmpeg")
-      .then((available) => setFfmpegAvailable(available))
-      .catch(() => setFfmpegAvailable(false));
+    invoke<PresetInfo[]>("list_encoding_presets").then(setPresets);
+    invoke<SilencePresetInfo[]>("list_silence_presets").then(
+      setSilencePresets
+    );
 
     const unlisten = listen("segment-progress", (event: any) => {
-      const { file_name, progress: fileProgress, completed } = event.payload;
-      setCurrentFile(file_name);
+      const {
+        file_name,
+        progress: fileProgress,
+        completed,
+        eta_seconds,
+      } = event.payload;
       setProgress((prev) => ({
         ...prev,
         [file_name]: completed ? 100 : fileProgress,
       }));
+      setEtas((prev) => ({
+        ...prev,
+        [file_name]: completed ? null : eta_seconds,
+      }));
+    });
+    // Several files split at once, so finished files are counted as they come in
+    const unlistenCopy = listen("copy-progress", (event: any) => {
+      setFilesDone(event.payload.finished);
     });
 
     return () => {
       unlisten.then((fn) => fn());
+      unlistenCopy.then((fn) => fn());
     };
   }, []);
 
+  const tempoUnavailable = unavailableReason(ffmpegReport, "change_tempo");
+  const presetsUnavailable = unavailableReason(
+    ffmpegReport,
+    "encoding_presets"
+  );
+
+  function splitSettings() {
+    return {
+      files,
+      destPath: destDir,
+      chunkMinutes,
+      cutAtSilence,
+      silence,
+      byChapters,
+      tempo,
+      preset,
+      format,
+      overlapSeconds,
+      concurrency,
+    };
+  }
+
+  // Queues the split to run in the background, where it carries on if this page reloads
+  async function handleSplitInBackground() {
+    if (!destDir) {
+      alert("Please select a destination directory");
+      return;
+    }
+    try {
+      await submitJob("split", splitSettings());
+      alert("Queued. Follow it under Background Jobs.");
+    } catch (error) {
+      alert(`Queueing the split failed: ${error}`);
+    }
+  }
+
   async function handleSplit() {
     if (!destDir) {
       alert("Please select a destination directory");
@@ -48,19 +170,15 @@ function SplitFiles() {
     }
     try {
       setIsProcessing(true);
-      await invoke("split_audio_files", {
-        files,
-        destPath: destDir,
-        chunkMinutes,
-        cutAtSilence,
-      });
+      await invoke("split_audio_files", splitSettings());
       alert("Files split successfully!");
     } catch (error) {
       alert(`Splitting failed: ${error}`);
     } finally {
       setIsProcessing(false);
       setProgress({});
-      setCurrentFile("");
+      setEtas({});
+      setFilesDone(0);
     }
   }
 
@@ -68,12 +186,22 @@ function SplitFiles() {
     <div className="split-files-container">
       <div className="split-files-columns">
         <div className="column">
-          {ffmpegAvailable === false && (
+          {ffmpegReport?.ffmpeg === null && (
             <div className="ffmpeg-missing-warning">
-              ⚠️ Warning: ffmpeg is not available on your system. File splitting
-              will not work without it. Please install ffmpeg first.
+              ⚠️ Warning: ffmpeg is not available on your system. Without it,
+              only MP3 files can be split, and only without changing their speed,
+              encoding or format, or cutting at chapters. Please install ffmpeg for
+              everything else.
             </div>
           )}
+          {ffmpegReport?.ffmpeg &&
+            unavailableReason(ffmpegReport, "splitting") && (
+              <div className="ffmpeg-missing-warning">
+                ⚠️ Only MP3 files can be split:{" "}
+                {unavailableReason(ffmpegReport, "splitting")}.
+              </div>
+            )}
+          <FfmpegSettings report={ffmpegReport} onReport={setFfmpegReport} />
           <p>
             Some audio files might be too long for your headphones to handle
             properly. This tool can split them into smaller chunks of a
@@ -91,6 +219,7 @@ function SplitFiles() {
                     "list_audio_files",
                     {
                       path,
+                      probe: true,
                     }
                   );
                   setFiles(audioFiles);
@@ -135,6 +264,204 @@ function SplitFiles() {
                 />
                 Cut at silences (when possible)
               </label>
+              {cutAtSilence && (
+                <div className="silence-settings">
+                  <label>
+                    Tuned for:{" "}
+                    <select
+                      value=""
+                      onChange={(e) => {
+                        const chosen = silencePresets.find(
+                          (p) => p.preset === e.target.value
+                        );
+                        if (chosen) setSilence(chosen.settings);
+                      }}
+                    >
+                      <option value="">choose a preset…</option>
+                      {silencePresets.map((p) => (
+                        <option key={p.preset} value={p.preset}>
+                          {p.label}
+                        </option>
+                      ))}
+                    </select>
+                  </label>
+                  <label>
+                    <input
+                      type="checkbox"
+                      checked={silence.threshold === "auto"}
+                      onChange={(e) =>
+                        setSilence({
+                          ...silence,
+                          threshold: e.target.checked
+                            ? "auto"
+                            : { decibels: -30 },
+                        })
+                      }
+                    />
+                    Measure the noise level of each file
+                  </label>
+                  {silence.threshold !== "auto" && (
+                    <label>
+                      Silence below:
+                      <input
+                        type="number"
+                        min="-90"
+                        max="-10"
+                        step="1"
+                        value={silence.threshold.decibels}
+                        onChange={(e) =>
+                          setSilence({
+                            ...silence,
+                            threshold: {
+                              decibels: Math.min(
+                                -10,
+                                Math.max(-90, parseFloat(e.target.value) || -30)
+                              ),
+                            },
+                          })
+                        }
+                      />
+                      dB
+                    </label>
+                  )}
+                  <label>
+                    Lasting at least:
+                    <input
+                      type="number"
+                      min="0.1"
+                      step="0.1"
+                      value={silence.min_duration}
+                      onChange={(e) =>
+                        setSilence({
+                          ...silence,
+                          min_duration: Math.max(
+                            0.1,
+                            parseFloat(e.target.value) || 1
+                          ),
+                        })
+                      }
+                    />
+                    seconds
+                  </label>
+                </div>
+              )}
+            </li>
+            <li>
+              <label>
+                <input
+                  type="checkbox"
+                  checked={byChapters}
+                  onChange={(e) => setByChapters(e.target.checked)}
+                />
+                Cut at chapter markers, naming pieces after the chapters
+              </label>
+            </li>
+            <li>
+              Optionally speed up playback (pitch is kept, but the files are
+              re-encoded, which takes longer)
+              <div className="chunk-duration">
+                <label>
+                  Playback speed:
+                  <input
+                    type="number"
+                    min="0.5"
+                    max="4"
+                    step="0.05"
+                    value={tempo}
+                    disabled={!!tempoUnavailable}
+                    onChange={(e) =>
+                      setTempo(
+                        Math.min(4, Math.max(0.5, parseFloat(e.target.value) || 1))
+                      )
+                    }
+                  />
+                  ×
+                </label>
+                {tempoUnavailable && (
+                  <span className="feature-unavailable">{tempoUnavailable}</span>
+                )}
+              </div>
+            </li>
+            <li>
+              Optionally repeat the end of each piece at the start of the next,
+              so a passage you missed plays again at the start of the next piece
+              <div className="chunk-duration">
+                <label>
+                  Overlap:
+                  <input
+                    type="number"
+                    min="0"
+                    max="120"
+                    step="5"
+                    value={overlapSeconds}
+                    onChange={(e) =>
+                      setOverlapSeconds(
+                        Math.min(120, Math.max(0, parseFloat(e.target.value) || 0))
+                      )
+                    }
+                  />
+                  seconds
+                </label>
+              </div>
+            </li>
+            <li>
+              <label>
+                Encoding:{" "}
+                <select value={preset} onChange={(e) => setPreset(e.target.value)}>
+                  {presets.map((p) => (
+                    <option
+                      key={p.preset}
+                      value={p.preset}
+                      disabled={
+                        p.preset !== "keep_original" && !!presetsUnavailable
+                      }
+                    >
+                      {p.label}
+                    </option>
+                  ))}
+                </select>
+              </label>
+              {presetsUnavailable && (
+                <span className="feature-unavailable">
+                  {presetsUnavailable}
+                </span>
+              )}
+            </li>
+            <li>
+              <label>
+                Format:{" "}
+                <select
+                  value={format}
+                  onChange={(e) => setFormat(e.target.value)}
+                  disabled={preset !== "keep_original"}
+                >
+                  <option value="keep_input">same as the original</option>
+                  {FORMATS.map(([value, label, feature]) => {
+                    const reason = unavailableReason(ffmpegReport, feature);
+                    return (
+                      <option key={value} value={value} disabled={!!reason}>
+                        {reason ? `${label} (${reason})` : label}
+                      </option>
+                    );
+                  })}
+                </select>
+              </label>
+            </li>
+            <li>
+              <label>
+                Files at once:{" "}
+                <input
+                  type="number"
+                  min="1"
+                  max="32"
+                  placeholder="one per core"
+                  value={concurrency ?? ""}
+                  onChange={(e) => {
+                    const value = parseInt(e.target.value);
+                    setConcurrency(value > 0 ? Math.min(32, value) : null);
+                  }}
+                />
+              </label>
             </li>
           </ol>
         </div>
@@ -146,12 +473,24 @@ function SplitFiles() {
                 {files.map((file) => (
                   <li key={file.path}>
                     {file.name}
-                    {currentFile === file.name && (
+                    {file.media && (
+                      <span className="file-media">
+                        {" "}
+                        ({describeMedia(file.media)})
+                      </span>
+                    )}
+                    {progress[file.name] !== undefined && (
                       <span className="file-progress">
                         Progress:{" "}
                         <span className="progress-value">
                           {progress[file.name]?.toFixed(1) || 0}%
                         </span>
+                        {etas[file.name] != null && (
+                          <span className="progress-eta">
+                            {" "}
+                            ({Math.ceil(etas[file.name]!)}s left)
+                          </span>
+                        )}
                       </span>
                     )}
                   </li>
@@ -169,7 +508,16 @@ function SplitFiles() {
         disabled={!destDir || isProcessing || files.length === 0}
         className="transfer-button"
       >
-        {isProcessing ? "Processing..." : "Split Files"}
+        {isProcessing
+          ? `Processing... ${filesDone} of ${files.length} files done`
+          : "Split Files"}
+      </button>
+      <button
+        onClick={handleSplitInBackground}
+        disabled={!destDir || files.length === 0}
+        className="transfer-button"
+      >
+        Split in Background
       </button>
     </div>
   );